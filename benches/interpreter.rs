@@ -0,0 +1,179 @@
+use std::hint::black_box;
+use std::io;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use fifth::interpreter::Program;
+
+const FACTORIAL: &str = include_str!("../examples/math/factorial.5th");
+const FIBONACCI: &str = include_str!("../examples/math/fibonacci.5th");
+const COUNTDOWN: &str = "
+push 255
+countdown
+halt
+
+countdown:
+  if
+    push 1
+    sub
+    countdown
+  then
+return
+";
+// 255 countdowns from 255 in a row
+const TIGHT_LOOP: &str = "
+push 255
+outer
+halt
+
+outer:
+  if
+    push 255
+    inner
+    pop
+    push 1
+    sub
+    outer
+  then
+return
+
+inner:
+  if
+    push 1
+    sub
+    inner
+  then
+return
+";
+// 200 calls deep, with work after every call so none of them is a tail call
+const DEEP_RECURSION: &str = "
+push 200
+down
+halt
+
+down:
+  if
+    push 1
+    sub
+    down
+    push 1
+    add
+  then
+return
+";
+// a case with a different branch for every value in a countdown
+const BRANCHES: &str = "
+push 255
+branch
+halt
+
+branch:
+  if
+    dup
+    case
+      push 1
+      of
+        push 1
+        pop
+      endof
+      push 2
+      of
+        push 2
+        pop
+      endof
+      push 3
+      of
+        push 3
+        pop
+      endof
+    endcase
+    push 1
+    sub
+    branch
+  then
+return
+";
+
+// how many instructions a run of the program takes
+fn steps(source: &str) -> u64 {
+    let mut program = parsed(source);
+    let mut steps = 0;
+    while !program.halted {
+        steps += 1;
+        program.step().unwrap();
+    }
+    steps
+}
+
+fn parsed(source: &str) -> Program {
+    let mut program = Program::new(source, 256);
+    program.parse().unwrap();
+    program.set_output(Box::new(io::sink()));
+    program
+}
+
+fn parse(c: &mut Criterion) {
+    let source = "push 1\ndup\nadd\npop\n".repeat(2500);
+    c.bench_function("parse 10000 instructions", |b| {
+        b.iter(|| parsed(black_box(&source)))
+    });
+    let mut group = c.benchmark_group("parse throughput");
+    for (name, source) in [("fibonacci", FIBONACCI), ("branches", BRANCHES)] {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(name, |b| b.iter(|| parsed(black_box(source))));
+    }
+    group.finish();
+}
+
+fn run(c: &mut Criterion) {
+    for (name, source) in [
+        ("run countdown", COUNTDOWN),
+        ("run factorial", FACTORIAL),
+        ("run fibonacci", FIBONACCI),
+    ] {
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || parsed(source),
+                |mut program| program.run().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+// instructions per second, for programs that stress different parts of the interpreter
+fn steps_per_second(c: &mut Criterion) {
+    let mut group = c.benchmark_group("steps");
+    for (name, source) in [
+        ("tight loop", TIGHT_LOOP),
+        ("deep recursion", DEEP_RECURSION),
+        ("branches", BRANCHES),
+    ] {
+        group.throughput(Throughput::Elements(steps(source)));
+        let mut program = parsed(source);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                program.reset();
+                program.run().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+// parsed once, then reset before every run
+fn run_many(c: &mut Criterion) {
+    let mut program = parsed(COUNTDOWN);
+    c.bench_function("rerun countdown", |b| {
+        b.iter(|| {
+            program.reset();
+            program.run().unwrap()
+        })
+    });
+    let mut program = parsed("add\nadd\nhalt\n");
+    c.bench_function("rerun with a stack", |b| {
+        b.iter(|| program.run_with_stack(black_box(&[1, 2, 3])).unwrap())
+    });
+}
+
+criterion_group!(benches, parse, run, steps_per_second, run_many);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+// parsing any text either succeeds or reports parse errors, without panicking
+#![no_main]
+
+use fifth::interpreter::Program;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = Program::new(source, 16).parse();
+    }
+});
@@ -0,0 +1,34 @@
+// runs any program that parses for a bounded number of steps, without input, output or a
+// real clock, checking its invariants after every step
+#![no_main]
+
+use std::io;
+
+use fifth::clock::VirtualClock;
+use fifth::interpreter::Program;
+use libfuzzer_sys::fuzz_target;
+
+const STACK_SIZE: usize = 16;
+const MAX_STEPS: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let mut program = Program::new(source, STACK_SIZE);
+    if program.parse().is_err() {
+        return;
+    }
+    program.set_input(io::empty());
+    program.set_output(io::sink());
+    program.set_error_output(io::sink());
+    program.set_clock(VirtualClock::default());
+    for _ in 0..MAX_STEPS {
+        if program.halted || program.step().is_err() {
+            break;
+        }
+        if let Err(error) = program.check_invariants() {
+            panic!("{}", error);
+        }
+    }
+});
@@ -0,0 +1,61 @@
+/* The C API of the fifth interpreter, built with
+ * `cargo rustc --release --lib --features capi --crate-type cdylib` into
+ * target/release/libfifth.so (libfifth.dylib on macOS, fifth.dll on Windows). */
+#ifndef FIFTH_H
+#define FIFTH_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* what fifth_step() returns besides the runtime errors, whose codes match the exit codes
+ * of the fifth command */
+#define FIFTH_RUNNING 0
+#define FIFTH_HALTED 1
+#define FIFTH_STACK_OVERFLOW 3
+#define FIFTH_STACK_UNDERFLOW 4
+#define FIFTH_INVALID_LABEL 5
+#define FIFTH_CALL_STACK_UNDERFLOW 6
+#define FIFTH_UNCLOSED_IF 7
+#define FIFTH_ASSERTION_FAILED 8
+#define FIFTH_HOST_WORD_FAILED 9
+#define FIFTH_LIMIT_EXCEEDED 10
+#define FIFTH_TASK_ERROR 11
+#define FIFTH_UNCAUGHT_EXCEPTION 12
+#define FIFTH_OUT_OF_MEMORY 13
+#define FIFTH_OUTPUT_FAILED 14
+
+typedef struct FifthProgram FifthProgram;
+
+typedef void (*FifthOutputCallback)(void *user_data, const uint8_t *bytes, size_t length);
+
+/* a program from NUL-terminated UTF-8 source, or NULL if the source is NULL or not UTF-8 */
+FifthProgram *fifth_new(const char *source, size_t stack_size);
+
+/* 0, or the number of parse errors */
+int fifth_parse(FifthProgram *program);
+
+/* runs one instruction: FIFTH_RUNNING, FIFTH_HALTED or the runtime error */
+int fifth_step(FifthProgram *program);
+
+size_t fifth_stack_len(const FifthProgram *program);
+
+/* the value at index counted from the bottom of the stack, or -1 past its top */
+int fifth_stack_get(const FifthProgram *program, size_t index);
+
+/* calls callback with user_data and everything the program prints, instead of writing to
+ * stdout */
+void fifth_set_output_callback(FifthProgram *program, FifthOutputCallback callback,
+                               void *user_data);
+
+/* frees the program; NULL is ignored */
+void fifth_free(FifthProgram *program);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
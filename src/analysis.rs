@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::bytecode;
+use crate::interpreter::{is_keyword, label_name, Program, Token};
+use crate::test_runner::is_test;
+
+#[derive(Debug)]
+pub enum Warning {
+    UnusedLabel(String, usize),
+    UnreachableCode(usize, usize),
+    MixedCaseKeyword(String, usize, usize),
+}
+
+impl Warning {
+    pub fn line_number(&self) -> usize {
+        match self {
+            Warning::UnusedLabel(_, line)
+            | Warning::UnreachableCode(line, _)
+            | Warning::MixedCaseKeyword(_, line, _) => *line,
+        }
+    }
+
+    // 1-based column, 0 if the warning isn't about a single word
+    pub fn column(&self) -> usize {
+        match self {
+            Warning::UnusedLabel(..) => 0,
+            Warning::UnreachableCode(_, column) | Warning::MixedCaseKeyword(_, _, column) => {
+                *column
+            }
+        }
+    }
+
+    // stable identifier for tooling
+    pub fn code(&self) -> &'static str {
+        match self {
+            Warning::UnusedLabel(..) => "unused-label",
+            Warning::UnreachableCode(..) => "unreachable-code",
+            Warning::MixedCaseKeyword(..) => "mixed-case-keyword",
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::UnusedLabel(label, _) => {
+                write!(f, "Label '{}' is never called", label_name(label))
+            }
+            Warning::UnreachableCode(..) => write!(f, "Unreachable code"),
+            Warning::MixedCaseKeyword(word, ..) => write!(
+                f,
+                "Keyword '{}' is in mixed case, write '{}'",
+                word,
+                word.to_lowercase()
+            ),
+        }
+    }
+}
+
+// indices of the tokens that can run directly after the token at `index`
+pub fn successors(program: &Program, targets: &[usize], index: usize) -> Vec<usize> {
+    match &program.tokens[index].token {
+        // a TRY continues at its handler after the CATCH on an error
+        Token::If | Token::Of | Token::Try => vec![index + 1, targets[index] + 1],
+        Token::Else | Token::EndOf | Token::Catch => vec![targets[index] + 1],
+        // EXECUTE can only go where a PUSH_ADDR pointed, so that's what counts as reaching it
+        Token::Call(label) | Token::Spawn(label) | Token::PushAddr(label) => {
+            match program.label_index(label) {
+                Some(target) => vec![target, index + 1],
+                None => vec![index + 1],
+            }
+        }
+        Token::Return | Token::Halt | Token::Exit => Vec::new(),
+        _ => vec![index + 1],
+    }
+}
+
+pub fn reachable(program: &Program) -> Vec<bool> {
+    let targets = bytecode::branch_targets(&program.tokens);
+    let mut reached = vec![false; program.tokens.len()];
+    let mut pending: Vec<usize> = vec![0];
+    pending.extend(
+        program
+            .labels()
+            .filter(|(label, _)| is_test(label))
+            .map(|(_, index)| index),
+    );
+    while let Some(index) = pending.pop() {
+        if index >= reached.len() || reached[index] {
+            continue;
+        }
+        reached[index] = true;
+        pending.extend(successors(program, &targets, index));
+    }
+    reached
+}
+
+// keywords written like `Push` or `ifThen`, which read like labels in a case-sensitive
+// program; all lowercase and all uppercase are fine
+fn mixed_case_keywords(program: &Program, warnings: &mut Vec<Warning>) {
+    for (line_number, line) in (1..).zip(program.source.lines()) {
+        let words = line
+            .split_whitespace()
+            .take_while(|word| !word.starts_with('#'));
+        for word in words {
+            let mixed =
+                word.chars().any(char::is_lowercase) && word.chars().any(char::is_uppercase);
+            if mixed && is_keyword(word) {
+                let offset = word.as_ptr() as usize - line.as_ptr() as usize;
+                let column = line[..offset].chars().count() + 1;
+                warnings.push(Warning::MixedCaseKeyword(
+                    word.to_string(),
+                    line_number,
+                    column,
+                ));
+            }
+        }
+    }
+}
+
+// the unused labels and unreachable code, and with `strict_case` the keywords in mixed case
+pub fn warnings(program: &Program, strict_case: bool) -> Vec<Warning> {
+    let called: HashSet<&str> = program
+        .tokens
+        .iter()
+        .filter_map(|annotated_token| match &annotated_token.token {
+            Token::Call(label) | Token::Spawn(label) | Token::PushAddr(label) => {
+                Some(label.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut warnings = Vec::new();
+    let mut unused_label_indices = HashSet::new();
+    for (label, index) in program.labels() {
+        if !called.contains(label) && !is_test(label) {
+            unused_label_indices.insert(index);
+            let line_number = program.label_line(label).unwrap_or(0);
+            warnings.push(Warning::UnusedLabel(label.to_string(), line_number));
+        }
+    }
+
+    // unreachable code is reported once per run of tokens (a label starts a new run);
+    // code behind an unused label is already covered by the label's warning
+    let label_indices: HashSet<usize> = program.labels().map(|(_, index)| index).collect();
+    let reached = reachable(program);
+    for (index, annotated_token) in program.tokens.iter().enumerate() {
+        let starts_run =
+            !reached[index] && (index == 0 || reached[index - 1] || label_indices.contains(&index));
+        if starts_run && !unused_label_indices.contains(&index) {
+            warnings.push(Warning::UnreachableCode(
+                annotated_token.line_number,
+                annotated_token.column,
+            ));
+        }
+    }
+
+    if strict_case {
+        mixed_case_keywords(program, &mut warnings);
+    }
+    warnings.sort_by_key(|warning| warning.line_number());
+    warnings
+}
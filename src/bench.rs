@@ -0,0 +1,94 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::cli::Config;
+use crate::interpreter::{Program, RuntimeError};
+use crate::Outcome;
+use fifth::clock::VirtualClock;
+
+// how long `fifth bench` parses the program over and over, and then runs it
+const PARSE_TIME: Duration = Duration::from_millis(200);
+const RUN_TIME: Duration = Duration::from_secs(1);
+
+// `fifth bench <filename>`: how fast the program parses, and how many instructions a
+// second it runs. It runs without input or output and with a clock that doesn't wait in
+// SLEEP, so only the interpreter is measured; once to count its instructions, then over
+// and over for a second
+pub fn bench(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let mut parses = 0u64;
+    let mut program = loop {
+        let mut program = crate::new_program(config, source);
+        if let Err(diagnostics) = program.parse() {
+            return Ok(Outcome::ParseFailed(diagnostics));
+        }
+        parses += 1;
+        if start.elapsed() >= PARSE_TIME {
+            break program;
+        }
+    };
+    let parse_time = start.elapsed() / parses as u32;
+    crate::optimize(config, &mut program);
+    program.set_input(io::empty());
+    program.set_output(io::sink());
+    program.set_error_output(io::sink());
+    program.set_clock(VirtualClock::default());
+    // every run gets the same seed, so it runs the same instructions
+    let seed = config.seed.unwrap_or(0);
+    let initial = [config.stack_init.as_slice(), &config.program_args].concat();
+
+    program.seed(seed);
+    program.stack.extend(&initial);
+    let mut steps = 0u64;
+    while !program.halted {
+        if program.pc < program.code.ops.len() {
+            steps += 1;
+        }
+        if let Err(error) = program.step() {
+            return Ok(failed(program, error));
+        }
+    }
+
+    let start = Instant::now();
+    let mut runs = 0u64;
+    while start.elapsed() < RUN_TIME {
+        program.seed(seed);
+        if let Err(error) = program.run_with_stack(&initial) {
+            return Ok(failed(program, error));
+        }
+        runs += 1;
+    }
+    let run_time = start.elapsed();
+
+    println!(
+        "parse: {:.1?} ({}B/s)",
+        parse_time,
+        per_second(source.len() as u64, parse_time)
+    );
+    println!("steps: {} per run", steps);
+    println!("runs: {} in {:.2?}", runs, run_time);
+    println!(
+        "speed: {} instructions/s",
+        per_second(steps * runs, run_time)
+    );
+    Ok(Outcome::Checked)
+}
+
+fn failed(program: Program, error: RuntimeError) -> Outcome {
+    Outcome::RuntimeFailed {
+        backtrace: program.backtrace(),
+        error,
+        stack: program.stack,
+    }
+}
+
+// `count` per second with a k, M or G suffix, like 12.3M
+fn per_second(count: u64, elapsed: Duration) -> String {
+    let rate = count as f64 / elapsed.as_secs_f64().max(f64::MIN_POSITIVE);
+    match rate {
+        rate if rate >= 1e9 => format!("{:.1}G", rate / 1e9),
+        rate if rate >= 1e6 => format!("{:.1}M", rate / 1e6),
+        rate if rate >= 1e3 => format!("{:.1}k", rate / 1e3),
+        rate => format!("{:.0}", rate),
+    }
+}
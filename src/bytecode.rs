@@ -0,0 +1,966 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::interpreter::{AnnotatedToken, BinOp, Token, MEMORY_SIZE};
+
+// jump target of an IF or ELSE that has no matching ELSE/THEN
+pub const UNCLOSED: u32 = u32::MAX;
+
+// subroutine addresses are bytes on the stack, so a program can take at most this many
+pub const MAX_ADDRESSES: usize = 256;
+
+// one VM instruction; labels and IF/ELSE/THEN are resolved to op indices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Push(u8),
+    Pop,
+    Dup,
+    Swap,
+    Rotate,
+    Over,
+    Nip,
+    Tuck,
+    // PICK and ROLL with the count as operand, or popped from the stack
+    PickN(u32),
+    Pick,
+    RollN(u32),
+    Roll,
+    Add,
+    Sub,
+    Random,
+    Assert,
+    AssertEq,
+    PrintByte,
+    PrintChar,
+    // prints two lowercase hexadecimal digits
+    PrintHex,
+    // prints the bytes of UTF-8 text, each character once its last byte comes
+    PrintUtf8,
+    // PRINT_BYTE and PRINT_CHAR to the error output
+    EprintByte,
+    EprintChar,
+    // PRINT_NEWLINE (or CR) and SPACE, which print a newline and a space
+    PrintNewline,
+    PrintSpace,
+    // prints the stack to the error output, leaving it as it is
+    PrintStack,
+    ReadChar,
+    // READ_LINE pops an address and a maximum length and pushes the length it read;
+    // PARSE_NUM pops an address and a length and pushes the number and whether it is one
+    ReadLine,
+    ParseNum,
+    // pushes 1 if the last READ_CHAR or READ_LINE ran out of input, 0 otherwise
+    Eof,
+    // pops a number of milliseconds and waits that long
+    Sleep,
+    // pushes the milliseconds since the program started as two bytes, the low one on top
+    Ticks,
+    // FOPEN pops the address and length of a file name and a mode and pushes a handle;
+    // FREAD pops a handle and pushes a byte, FWRITE pops a byte and a handle, and FCLOSE
+    // pops a handle. Each pushes a status last, 0 unless it failed
+    Fopen,
+    Fread,
+    Fwrite,
+    Fclose,
+    // KEY pushes the next key, waiting for it, and KEY? a key and whether there was one;
+    // CLEAR_SCREEN and CURSOR_AT print escape sequences, CURSOR_AT for the column and row
+    // it pops
+    Key,
+    KeyPoll,
+    ClearScreen,
+    CursorAt,
+    // TCP_LISTEN pops a port as two bytes and pushes a handle, TCP_ACCEPT pops the handle
+    // and pushes the handle of a connection, and TCP_CONNECT pops the address and length of
+    // a host name and a port and pushes one too; TCP_SEND pops a byte and a handle, TCP_RECV
+    // pops a handle and pushes a byte and TCP_CLOSE pops a handle. Each pushes a status last
+    TcpListen,
+    TcpAccept,
+    TcpConnect,
+    TcpSend,
+    TcpRecv,
+    TcpClose,
+    // GFX_INIT pops a width and a height and starts a frame of that size, PIXEL pops the
+    // column, the row and the gray level of a pixel and GFX_FLUSH shows the frame
+    GfxInit,
+    Pixel,
+    GfxFlush,
+    // pops the address and length of a variable's name and the address and maximum length
+    // of its value, which it writes there, and pushes the length written and a status
+    Env,
+    // IF: jumps past the matching ELSE or THEN if the top of the stack is zero
+    JumpIfZero(u32),
+    // ELSE and ENDOF: jumps past the matching THEN or ENDCASE
+    Jump(u32),
+    // THEN, ENDTRY and CASE
+    Nop,
+    // OF: pops a value and, if the selector below it is equal, the selector too; otherwise
+    // jumps past the matching ENDOF
+    Of(u32),
+    // TRY: sets up a handler that continues at the target, after the matching CATCH
+    Try(u32),
+    // CATCH: removes the handler and jumps past the matching ENDTRY
+    Catch(u32),
+    Throw,
+    Call(u32),
+    // a word the host registered with Program::register_word, by its index in `natives`
+    Native(u32),
+    // PUSH_ADDR: pushes the subroutine's address, its index in `addresses`
+    Address(u32),
+    // calls the subroutine whose address it pops
+    Execute,
+    // FETCH pops an address and pushes its cell; STORE pops an address and a value for it
+    Fetch,
+    Store,
+    // pops a count of cells and pushes the address of the first one it reserved
+    Allot,
+    // like FETCH and STORE, with an index on top of the address that is added to it
+    FetchAt,
+    StoreAt,
+    // SPAWN: starts a task at the target
+    Spawn(u32),
+    Yield,
+    Join,
+    ChanNew,
+    Send,
+    Recv,
+    Return,
+    Halt,
+    Exit,
+}
+
+impl Op {
+    // the mnemonic the disassembly shows
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Push(_) => "push",
+            Op::Pop => "pop",
+            Op::Dup => "dup",
+            Op::Swap => "swap",
+            Op::Rotate => "rotate",
+            Op::Over => "over",
+            Op::Nip => "nip",
+            Op::Tuck => "tuck",
+            Op::PickN(_) | Op::Pick => "pick",
+            Op::RollN(_) | Op::Roll => "roll",
+            Op::Add => "add",
+            Op::Sub => "sub",
+            Op::Random => "random",
+            Op::Assert => "assert",
+            Op::AssertEq => "assert_eq",
+            Op::PrintByte => "print_byte",
+            Op::PrintChar => "print_char",
+            Op::PrintHex => "print_hex",
+            Op::PrintUtf8 => "print_utf8",
+            Op::EprintByte => "eprint_byte",
+            Op::EprintChar => "eprint_char",
+            Op::PrintNewline => "print_newline",
+            Op::PrintSpace => "space",
+            Op::PrintStack => "print_stack",
+            Op::ReadChar => "read_char",
+            Op::ReadLine => "read_line",
+            Op::ParseNum => "parse_num",
+            Op::Eof => "eof",
+            Op::Sleep => "sleep",
+            Op::Ticks => "ticks",
+            Op::Fopen => "fopen",
+            Op::Fread => "fread",
+            Op::Fwrite => "fwrite",
+            Op::Fclose => "fclose",
+            Op::Key => "key",
+            Op::KeyPoll => "key?",
+            Op::ClearScreen => "clear_screen",
+            Op::CursorAt => "cursor_at",
+            Op::TcpListen => "tcp_listen",
+            Op::TcpAccept => "tcp_accept",
+            Op::TcpConnect => "tcp_connect",
+            Op::TcpSend => "tcp_send",
+            Op::TcpRecv => "tcp_recv",
+            Op::TcpClose => "tcp_close",
+            Op::GfxInit => "gfx_init",
+            Op::Pixel => "pixel",
+            Op::GfxFlush => "gfx_flush",
+            Op::Env => "env",
+            Op::JumpIfZero(_) => "jump_if_zero",
+            Op::Jump(_) => "jump",
+            Op::Nop => "nop",
+            Op::Of(_) => "of",
+            Op::Try(_) => "try",
+            Op::Catch(_) => "catch",
+            Op::Throw => "throw",
+            Op::Call(_) => "call",
+            Op::Native(_) => "native",
+            Op::Address(_) => "push_addr",
+            Op::Execute => "execute",
+            Op::Fetch => "fetch",
+            Op::Store => "store",
+            Op::Allot => "allot",
+            Op::FetchAt => "fetch_at",
+            Op::StoreAt => "store_at",
+            Op::Spawn(_) => "spawn",
+            Op::Yield => "yield",
+            Op::Join => "join",
+            Op::ChanNew => "chan_new",
+            Op::Send => "send",
+            Op::Recv => "recv",
+            Op::Return => "return",
+            Op::Halt => "halt",
+            Op::Exit => "exit",
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operand = match *self {
+            Op::Push(value) => Some(value.into()),
+            Op::PickN(operand)
+            | Op::RollN(operand)
+            | Op::JumpIfZero(operand)
+            | Op::Jump(operand)
+            | Op::Of(operand)
+            | Op::Try(operand)
+            | Op::Catch(operand)
+            | Op::Call(operand)
+            | Op::Native(operand)
+            | Op::Address(operand)
+            | Op::Spawn(operand) => Some(operand),
+            _ => None,
+        };
+        match operand {
+            Some(UNCLOSED) => write!(f, "{} ?", self.name()),
+            Some(operand) => write!(f, "{} {}", self.name(), operand),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+// for every IF the index of its ELSE (or THEN if there is none), for every ELSE the index
+// of its THEN, for every TRY the index of its CATCH, for every CATCH the index of its
+// ENDTRY, for every OF the index of its ENDOF and for every ENDOF the index of its
+// ENDCASE; unclosed statements point past the last token
+pub fn branch_targets(tokens: &[AnnotatedToken]) -> Vec<usize> {
+    let mut targets = vec![tokens.len(); tokens.len()];
+    let mut open: Vec<usize> = Vec::new();
+    // the ENDOFs of every open CASE
+    let mut cases: Vec<Vec<usize>> = Vec::new();
+    for (index, annotated_token) in tokens.iter().enumerate() {
+        match annotated_token.token {
+            Token::If | Token::Try | Token::Of => open.push(index),
+            Token::Case => cases.push(Vec::new()),
+            Token::EndOf => {
+                if let Some(of_index) = open.pop() {
+                    targets[of_index] = index;
+                }
+                if let Some(end_ofs) = cases.last_mut() {
+                    end_ofs.push(index);
+                }
+            }
+            Token::EndCase => {
+                for end_of in cases.pop().unwrap_or_default() {
+                    targets[end_of] = index;
+                }
+            }
+            Token::Else | Token::Catch => {
+                if let Some(if_index) = open.pop() {
+                    targets[if_index] = index;
+                }
+                open.push(index);
+            }
+            Token::Then | Token::EndTry => {
+                if let Some(open_index) = open.pop() {
+                    targets[open_index] = index;
+                }
+            }
+            _ => (),
+        }
+    }
+    targets
+}
+
+pub struct Bytecode {
+    pub ops: Vec<Op>,
+    // line number and column of the word every op was compiled from
+    pub positions: Vec<(usize, usize)>,
+    // label names (uppercase, unless the program is case-sensitive) and the index of the op they point at, ordered by index
+    pub labels: Vec<(String, usize)>,
+    // the names (uppercase) of the host words the program uses
+    pub natives: Vec<String>,
+    // the index of the op every subroutine address points at, by address
+    pub addresses: Vec<usize>,
+    // what the memory holds from address 0 when the program starts, a value for every cell
+    // the program declared; ALLOT hands out the cells after it, which hold 0
+    pub data: Vec<u8>,
+}
+
+impl Bytecode {
+    // the label as it's written, or in uppercase like the labels of programs that aren't
+    // case-sensitive
+    pub fn label_index(&self, label: &str) -> Option<usize> {
+        let uppercase = label.to_uppercase();
+        let find = |label: &str| self.labels.iter().find(|(name, _)| name == label);
+        find(label)
+            .or_else(|| find(&uppercase))
+            .map(|&(_, index)| index)
+    }
+
+    // the label of the subroutine the op at `index` belongs to
+    pub fn enclosing_label(&self, index: usize) -> Option<&str> {
+        let start = self
+            .labels
+            .iter()
+            .map(|&(_, label_index)| label_index)
+            .filter(|&label_index| label_index <= index)
+            .max()?;
+        self.labels
+            .iter()
+            .find(|&&(_, label_index)| label_index == start)
+            .map(|(label, _)| label.as_str())
+    }
+
+    // the token the op at `index` stands for, as reported in errors and traces
+    pub fn token(&self, index: usize) -> AnnotatedToken {
+        let (line_number, column) = self.positions.get(index).copied().unwrap_or((0, 0));
+        let token = match self.ops.get(index) {
+            Some(&op) => op_token(op, self),
+            None => Token::Halt,
+        };
+        AnnotatedToken {
+            token,
+            line_number,
+            column,
+        }
+    }
+}
+
+fn op_token(op: Op, code: &Bytecode) -> Token {
+    match op {
+        Op::Push(value) => Token::Push(value),
+        Op::Pop => Token::Pop,
+        Op::Dup => Token::Dup,
+        Op::Swap => Token::Swap,
+        Op::Rotate => Token::Rotate,
+        Op::Over => Token::Over,
+        Op::Nip => Token::Nip,
+        Op::Tuck => Token::Tuck,
+        Op::PickN(index) => Token::Pick(Some(index as usize)),
+        Op::Pick => Token::Pick(None),
+        Op::RollN(count) => Token::Roll(Some(count as usize)),
+        Op::Roll => Token::Roll(None),
+        Op::Add => Token::BinOp(BinOp::Add),
+        Op::Sub => Token::BinOp(BinOp::Sub),
+        Op::Random => Token::Random,
+        Op::Assert => Token::Assert,
+        Op::AssertEq => Token::AssertEq,
+        Op::PrintByte => Token::PrintByte,
+        Op::PrintChar => Token::PrintChar,
+        Op::PrintHex => Token::PrintHex,
+        Op::PrintUtf8 => Token::PrintUtf8,
+        Op::EprintByte => Token::EprintByte,
+        Op::EprintChar => Token::EprintChar,
+        Op::PrintNewline => Token::PrintNewline,
+        Op::PrintSpace => Token::PrintSpace,
+        Op::PrintStack => Token::PrintStack,
+        Op::ReadChar => Token::ReadChar,
+        Op::ReadLine => Token::ReadLine,
+        Op::ParseNum => Token::ParseNum,
+        Op::Eof => Token::Eof,
+        Op::Sleep => Token::Sleep,
+        Op::Ticks => Token::Ticks,
+        Op::Fopen => Token::Fopen,
+        Op::Fread => Token::Fread,
+        Op::Fwrite => Token::Fwrite,
+        Op::Fclose => Token::Fclose,
+        Op::Key => Token::Key,
+        Op::KeyPoll => Token::KeyPoll,
+        Op::ClearScreen => Token::ClearScreen,
+        Op::CursorAt => Token::CursorAt,
+        Op::TcpListen => Token::TcpListen,
+        Op::TcpAccept => Token::TcpAccept,
+        Op::TcpConnect => Token::TcpConnect,
+        Op::TcpSend => Token::TcpSend,
+        Op::TcpRecv => Token::TcpRecv,
+        Op::TcpClose => Token::TcpClose,
+        Op::GfxInit => Token::GfxInit,
+        Op::Pixel => Token::Pixel,
+        Op::GfxFlush => Token::GfxFlush,
+        Op::Env => Token::Env,
+        Op::JumpIfZero(_) => Token::If,
+        Op::Jump(_) => Token::Else,
+        Op::Nop => Token::Then,
+        Op::Of(_) => Token::Of,
+        Op::Try(_) => Token::Try,
+        Op::Catch(_) => Token::Catch,
+        Op::Throw => Token::Throw,
+        Op::Call(target) => Token::Call(
+            code.labels
+                .iter()
+                .find(|&&(_, index)| index == target as usize)
+                .map_or_else(|| target.to_string(), |(label, _)| label.clone()),
+        ),
+        Op::Native(index) => Token::Call(
+            code.natives
+                .get(index as usize)
+                .map_or_else(|| index.to_string(), |name| name.clone()),
+        ),
+        Op::Address(address) => Token::PushAddr(
+            code.addresses
+                .get(address as usize)
+                .and_then(|&target| code.labels.iter().find(|&&(_, index)| index == target))
+                .map_or_else(|| address.to_string(), |(label, _)| label.clone()),
+        ),
+        Op::Execute => Token::Execute,
+        Op::Fetch => Token::Fetch,
+        Op::Store => Token::Store,
+        Op::Allot => Token::Allot,
+        Op::FetchAt => Token::FetchAt,
+        Op::StoreAt => Token::StoreAt,
+        Op::Spawn(target) => Token::Spawn(
+            code.labels
+                .iter()
+                .find(|&&(_, index)| index == target as usize)
+                .map_or_else(|| target.to_string(), |(label, _)| label.clone()),
+        ),
+        Op::Yield => Token::Yield,
+        Op::Join => Token::Join,
+        Op::ChanNew => Token::ChanNew,
+        Op::Send => Token::Send,
+        Op::Recv => Token::Recv,
+        Op::Return => Token::Return,
+        Op::Halt => Token::Halt,
+        Op::Exit => Token::Exit,
+    }
+}
+
+fn operand(value: usize) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+// lowers parsed tokens to one op per token; every call must name a defined label or one
+// of the host words in `natives` (uppercase), with the label taking precedence. The
+// subroutines PUSH_ADDR names get addresses in the order they first appear
+pub fn compile<'a>(
+    tokens: &[AnnotatedToken],
+    labels: impl Iterator<Item = (&'a str, usize)>,
+    natives: &[String],
+) -> Bytecode {
+    let mut labels: Vec<(String, usize)> = labels
+        .map(|(label, index)| (label.to_string(), index))
+        .collect();
+    labels.sort_by(|(a, a_index), (b, b_index)| a_index.cmp(b_index).then(a.cmp(b)));
+
+    let targets = branch_targets(tokens);
+    // jumps land after the ELSE or THEN
+    let jump = |index: usize| match targets[index] {
+        target if target >= tokens.len() => UNCLOSED,
+        target => operand(target + 1),
+    };
+    let mut addresses = Vec::new();
+    let ops = tokens
+        .iter()
+        .enumerate()
+        .map(|(index, annotated_token)| match &annotated_token.token {
+            Token::Push(value) => Op::Push(*value),
+            Token::Pop => Op::Pop,
+            Token::Dup => Op::Dup,
+            Token::Swap => Op::Swap,
+            Token::Rotate => Op::Rotate,
+            Token::Over => Op::Over,
+            Token::Nip => Op::Nip,
+            Token::Tuck => Op::Tuck,
+            Token::Pick(Some(index)) => Op::PickN(operand(*index)),
+            Token::Pick(None) => Op::Pick,
+            Token::Roll(Some(count)) => Op::RollN(operand(*count)),
+            Token::Roll(None) => Op::Roll,
+            Token::BinOp(BinOp::Add) => Op::Add,
+            Token::BinOp(BinOp::Sub) => Op::Sub,
+            Token::Random => Op::Random,
+            Token::Assert => Op::Assert,
+            Token::AssertEq => Op::AssertEq,
+            Token::PrintByte => Op::PrintByte,
+            Token::PrintChar => Op::PrintChar,
+            Token::PrintHex => Op::PrintHex,
+            Token::PrintUtf8 => Op::PrintUtf8,
+            Token::EprintByte => Op::EprintByte,
+            Token::EprintChar => Op::EprintChar,
+            Token::PrintNewline => Op::PrintNewline,
+            Token::PrintSpace => Op::PrintSpace,
+            Token::PrintStack => Op::PrintStack,
+            Token::ReadChar => Op::ReadChar,
+            Token::ReadLine => Op::ReadLine,
+            Token::ParseNum => Op::ParseNum,
+            Token::Eof => Op::Eof,
+            Token::Sleep => Op::Sleep,
+            Token::Ticks => Op::Ticks,
+            Token::Fopen => Op::Fopen,
+            Token::Fread => Op::Fread,
+            Token::Fwrite => Op::Fwrite,
+            Token::Fclose => Op::Fclose,
+            Token::Key => Op::Key,
+            Token::KeyPoll => Op::KeyPoll,
+            Token::ClearScreen => Op::ClearScreen,
+            Token::CursorAt => Op::CursorAt,
+            Token::TcpListen => Op::TcpListen,
+            Token::TcpAccept => Op::TcpAccept,
+            Token::TcpConnect => Op::TcpConnect,
+            Token::TcpSend => Op::TcpSend,
+            Token::TcpRecv => Op::TcpRecv,
+            Token::TcpClose => Op::TcpClose,
+            Token::GfxInit => Op::GfxInit,
+            Token::Pixel => Op::Pixel,
+            Token::GfxFlush => Op::GfxFlush,
+            Token::Env => Op::Env,
+            Token::If => Op::JumpIfZero(jump(index)),
+            Token::Else => Op::Jump(jump(index)),
+            Token::Then | Token::EndTry | Token::Case => Op::Nop,
+            Token::Of => Op::Of(jump(index)),
+            // ENDOF skips the rest of the CASE, and ENDCASE drops the selector no OF matched
+            Token::EndOf => Op::Jump(jump(index)),
+            Token::EndCase => Op::Pop,
+            Token::Try => Op::Try(jump(index)),
+            Token::Catch => Op::Catch(jump(index)),
+            Token::Throw => Op::Throw,
+            Token::Call(label) => {
+                let target = labels.iter().find(|(name, _)| name == label);
+                match natives
+                    .iter()
+                    .position(|name| *name == label.to_uppercase())
+                {
+                    Some(native) if target.is_none() => Op::Native(operand(native)),
+                    _ => Op::Call(target.map_or(UNCLOSED, |&(_, target)| operand(target))),
+                }
+            }
+            Token::Spawn(label) => {
+                let target = labels.iter().find(|(name, _)| name == label);
+                Op::Spawn(target.map_or(UNCLOSED, |&(_, target)| operand(target)))
+            }
+            Token::PushAddr(label) => {
+                let target = labels.iter().find(|(name, _)| name == label);
+                let target = target.map_or(tokens.len(), |&(_, target)| target);
+                let address = match addresses.iter().position(|&address| address == target) {
+                    Some(address) => address,
+                    None => {
+                        addresses.push(target);
+                        addresses.len() - 1
+                    }
+                };
+                Op::Address(operand(address))
+            }
+            Token::Execute => Op::Execute,
+            Token::Fetch => Op::Fetch,
+            Token::Store => Op::Store,
+            Token::Allot => Op::Allot,
+            Token::FetchAt => Op::FetchAt,
+            Token::StoreAt => Op::StoreAt,
+            Token::Yield => Op::Yield,
+            Token::Join => Op::Join,
+            Token::ChanNew => Op::ChanNew,
+            Token::Send => Op::Send,
+            Token::Recv => Op::Recv,
+            Token::Return => Op::Return,
+            Token::Halt => Op::Halt,
+            Token::Exit => Op::Exit,
+        })
+        .collect();
+    let positions = tokens
+        .iter()
+        .map(|annotated_token| (annotated_token.line_number, annotated_token.column))
+        .collect();
+    Bytecode {
+        ops,
+        positions,
+        labels,
+        natives: natives.to_vec(),
+        addresses,
+        data: Vec::new(),
+    }
+}
+
+// compiled program files (.fbc) start with MAGIC and VERSION, followed by the ops, the
+// line map, the label table, the host word names, the subroutine addresses and the initial
+// memory; all numbers are little-endian. Version 1 files have no host word names, version
+// 2 files no addresses and version 3 files no memory
+pub const MAGIC: &[u8; 4] = b"5THB";
+pub const VERSION: u16 = 4;
+
+#[derive(Debug)]
+pub enum LoadError {
+    NotCompiled,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidOpcode(u8),
+    InvalidTarget(usize),
+    InvalidLabel,
+    InvalidAddress(usize),
+    DataTooLarge(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotCompiled => write!(f, "Not a compiled program"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Compiled program has version {}, but only versions up to {} are supported",
+                version, VERSION
+            ),
+            LoadError::Truncated => write!(f, "Compiled program is truncated"),
+            LoadError::InvalidOpcode(opcode) => {
+                write!(f, "Compiled program contains invalid opcode {}", opcode)
+            }
+            LoadError::InvalidTarget(index) => write!(
+                f,
+                "Compiled program jumps outside of its code at op {}",
+                index
+            ),
+            LoadError::InvalidLabel => write!(f, "Compiled program has an invalid label table"),
+            LoadError::InvalidAddress(index) => write!(
+                f,
+                "Compiled program pushes an invalid subroutine address at op {}",
+                index
+            ),
+            LoadError::DataTooLarge(size) => write!(
+                f,
+                "Compiled program has {} bytes of data, more than the memory holds",
+                size
+            ),
+        }
+    }
+}
+
+impl core::error::Error for LoadError {}
+
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend(operand(value).to_le_bytes());
+}
+
+// the opcode byte of every op and its 32-bit operand; PUSH is followed by its byte
+fn encode(op: Op) -> (u8, Option<u32>) {
+    match op {
+        Op::Push(_) => (0, None),
+        Op::Pop => (1, None),
+        Op::Dup => (2, None),
+        Op::Swap => (3, None),
+        Op::Rotate => (4, None),
+        Op::Over => (5, None),
+        Op::Nip => (6, None),
+        Op::Tuck => (7, None),
+        Op::PickN(index) => (8, Some(index)),
+        Op::Pick => (9, None),
+        Op::RollN(count) => (10, Some(count)),
+        Op::Roll => (11, None),
+        Op::Add => (12, None),
+        Op::Sub => (13, None),
+        Op::Random => (14, None),
+        Op::Assert => (15, None),
+        Op::AssertEq => (16, None),
+        Op::PrintByte => (17, None),
+        Op::PrintChar => (18, None),
+        Op::ReadChar => (19, None),
+        Op::JumpIfZero(target) => (20, Some(target)),
+        Op::Jump(target) => (21, Some(target)),
+        Op::Nop => (22, None),
+        Op::Call(target) => (23, Some(target)),
+        Op::Native(index) => (27, Some(index)),
+        Op::Spawn(target) => (28, Some(target)),
+        Op::Yield => (29, None),
+        Op::Join => (30, None),
+        Op::ChanNew => (31, None),
+        Op::Send => (32, None),
+        Op::Recv => (33, None),
+        Op::Try(target) => (34, Some(target)),
+        Op::Catch(target) => (35, Some(target)),
+        Op::Throw => (36, None),
+        Op::Address(address) => (37, Some(address)),
+        Op::Execute => (38, None),
+        Op::Of(target) => (39, Some(target)),
+        Op::Fetch => (40, None),
+        Op::Store => (41, None),
+        Op::Allot => (42, None),
+        Op::FetchAt => (43, None),
+        Op::StoreAt => (44, None),
+        Op::PrintNewline => (45, None),
+        Op::PrintSpace => (46, None),
+        Op::PrintHex => (47, None),
+        Op::PrintStack => (48, None),
+        Op::EprintByte => (49, None),
+        Op::EprintChar => (50, None),
+        Op::ReadLine => (51, None),
+        Op::ParseNum => (52, None),
+        Op::Eof => (53, None),
+        Op::Sleep => (54, None),
+        Op::Ticks => (55, None),
+        Op::Fopen => (56, None),
+        Op::Fread => (57, None),
+        Op::Fwrite => (58, None),
+        Op::Fclose => (59, None),
+        Op::Key => (60, None),
+        Op::KeyPoll => (61, None),
+        Op::ClearScreen => (62, None),
+        Op::CursorAt => (63, None),
+        Op::TcpListen => (64, None),
+        Op::TcpAccept => (65, None),
+        Op::TcpConnect => (66, None),
+        Op::TcpSend => (67, None),
+        Op::TcpRecv => (68, None),
+        Op::TcpClose => (69, None),
+        Op::GfxInit => (70, None),
+        Op::Pixel => (71, None),
+        Op::GfxFlush => (72, None),
+        Op::Env => (73, None),
+        Op::PrintUtf8 => (74, None),
+        Op::Return => (24, None),
+        Op::Halt => (25, None),
+        Op::Exit => (26, None),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], LoadError> {
+        if count > self.bytes.len() {
+            return Err(LoadError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, LoadError> {
+        Ok(self.u32()? as usize)
+    }
+}
+
+impl Bytecode {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        put_u32(&mut bytes, self.ops.len());
+        for &op in &self.ops {
+            let (opcode, operand) = encode(op);
+            bytes.push(opcode);
+            if let Op::Push(value) = op {
+                bytes.push(value);
+            }
+            if let Some(operand) = operand {
+                bytes.extend(operand.to_le_bytes());
+            }
+        }
+        for &(line_number, column) in &self.positions {
+            put_u32(&mut bytes, line_number);
+            put_u32(&mut bytes, column);
+        }
+        put_u32(&mut bytes, self.labels.len());
+        for (label, index) in &self.labels {
+            put_u32(&mut bytes, label.len());
+            bytes.extend(label.as_bytes());
+            put_u32(&mut bytes, *index);
+        }
+        put_u32(&mut bytes, self.natives.len());
+        for name in &self.natives {
+            put_u32(&mut bytes, name.len());
+            bytes.extend(name.as_bytes());
+        }
+        put_u32(&mut bytes, self.addresses.len());
+        for &target in &self.addresses {
+            put_u32(&mut bytes, target);
+        }
+        put_u32(&mut bytes, self.data.len());
+        bytes.extend(&self.data);
+        bytes
+    }
+
+    // checks every jump, label and address, so running the result can't go out of bounds
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, LoadError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(LoadError::NotCompiled);
+        }
+        let version = reader.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+
+        let count = reader.usize()?;
+        let mut ops = Vec::new();
+        for index in 0..count {
+            let op = match reader.u8()? {
+                0 => Op::Push(reader.u8()?),
+                1 => Op::Pop,
+                2 => Op::Dup,
+                3 => Op::Swap,
+                4 => Op::Rotate,
+                5 => Op::Over,
+                6 => Op::Nip,
+                7 => Op::Tuck,
+                8 => Op::PickN(reader.u32()?),
+                9 => Op::Pick,
+                10 => Op::RollN(reader.u32()?),
+                11 => Op::Roll,
+                12 => Op::Add,
+                13 => Op::Sub,
+                14 => Op::Random,
+                15 => Op::Assert,
+                16 => Op::AssertEq,
+                17 => Op::PrintByte,
+                18 => Op::PrintChar,
+                19 => Op::ReadChar,
+                20 => Op::JumpIfZero(reader.u32()?),
+                21 => Op::Jump(reader.u32()?),
+                22 => Op::Nop,
+                23 => Op::Call(reader.u32()?),
+                24 => Op::Return,
+                25 => Op::Halt,
+                26 => Op::Exit,
+                27 => Op::Native(reader.u32()?),
+                28 => Op::Spawn(reader.u32()?),
+                29 => Op::Yield,
+                30 => Op::Join,
+                31 => Op::ChanNew,
+                32 => Op::Send,
+                33 => Op::Recv,
+                34 => Op::Try(reader.u32()?),
+                35 => Op::Catch(reader.u32()?),
+                36 => Op::Throw,
+                37 => Op::Address(reader.u32()?),
+                38 => Op::Execute,
+                39 => Op::Of(reader.u32()?),
+                40 => Op::Fetch,
+                41 => Op::Store,
+                42 => Op::Allot,
+                43 => Op::FetchAt,
+                44 => Op::StoreAt,
+                45 => Op::PrintNewline,
+                46 => Op::PrintSpace,
+                47 => Op::PrintHex,
+                48 => Op::PrintStack,
+                49 => Op::EprintByte,
+                50 => Op::EprintChar,
+                51 => Op::ReadLine,
+                52 => Op::ParseNum,
+                53 => Op::Eof,
+                54 => Op::Sleep,
+                55 => Op::Ticks,
+                56 => Op::Fopen,
+                57 => Op::Fread,
+                58 => Op::Fwrite,
+                59 => Op::Fclose,
+                60 => Op::Key,
+                61 => Op::KeyPoll,
+                62 => Op::ClearScreen,
+                63 => Op::CursorAt,
+                64 => Op::TcpListen,
+                65 => Op::TcpAccept,
+                66 => Op::TcpConnect,
+                67 => Op::TcpSend,
+                68 => Op::TcpRecv,
+                69 => Op::TcpClose,
+                70 => Op::GfxInit,
+                71 => Op::Pixel,
+                72 => Op::GfxFlush,
+                73 => Op::Env,
+                74 => Op::PrintUtf8,
+                opcode => return Err(LoadError::InvalidOpcode(opcode)),
+            };
+            if let Op::JumpIfZero(target)
+            | Op::Jump(target)
+            | Op::Of(target)
+            | Op::Call(target)
+            | Op::Spawn(target)
+            | Op::Try(target)
+            | Op::Catch(target) = op
+            {
+                if target != UNCLOSED && target as usize > count {
+                    return Err(LoadError::InvalidTarget(index));
+                }
+            }
+            ops.push(op);
+        }
+
+        let mut positions = Vec::new();
+        for _ in 0..count {
+            positions.push((reader.usize()?, reader.usize()?));
+        }
+
+        let mut labels = Vec::new();
+        for _ in 0..reader.usize()? {
+            let length = reader.usize()?;
+            let label = core::str::from_utf8(reader.take(length)?)
+                .map_err(|_| LoadError::InvalidLabel)?
+                .to_string();
+            let index = reader.usize()?;
+            if index > count {
+                return Err(LoadError::InvalidLabel);
+            }
+            labels.push((label, index));
+        }
+        labels.sort_by(|(a, a_index), (b, b_index)| a_index.cmp(b_index).then(a.cmp(b)));
+
+        let mut natives = Vec::new();
+        if version >= 2 {
+            for _ in 0..reader.usize()? {
+                let length = reader.usize()?;
+                let name = core::str::from_utf8(reader.take(length)?)
+                    .map_err(|_| LoadError::InvalidLabel)?;
+                natives.push(name.to_string());
+            }
+        }
+
+        let mut addresses = Vec::new();
+        if version >= 3 {
+            for _ in 0..reader.usize()? {
+                let target = reader.usize()?;
+                if target > count {
+                    return Err(LoadError::InvalidLabel);
+                }
+                addresses.push(target);
+            }
+        }
+        // every address fits on the stack and points somewhere
+        for (index, &op) in ops.iter().enumerate() {
+            if let Op::Address(address) = op {
+                if address > u8::MAX.into() || address as usize >= addresses.len() {
+                    return Err(LoadError::InvalidAddress(index));
+                }
+            }
+        }
+
+        let mut data = Vec::new();
+        if version >= 4 {
+            let size = reader.usize()?;
+            if size > MEMORY_SIZE {
+                return Err(LoadError::DataTooLarge(size));
+            }
+            data = reader.take(size)?.to_vec();
+        }
+
+        Ok(Bytecode {
+            ops,
+            positions,
+            labels,
+            natives,
+            addresses,
+            data,
+        })
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::bytecode::{Bytecode, Op};
+use crate::interpreter::label_name;
+
+// the name of the code before the first label, as in the profile
+const TOP_LEVEL: &str = "(top level)";
+
+// how one subroutine names another, strongest first when it does in several ways
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Edge {
+    Call,
+    Spawn,
+    Address,
+}
+
+// the subroutines of a program as a Graphviz DOT graph, with an edge for every subroutine
+// another one calls, spawns (dashed) or pushes the address of (dotted). Edges on a cycle,
+// which is recursion, are red, and with the `counts` of a profile calls are labeled with
+// how often they ran, e.g.
+//   digraph calls {
+//       "(top level)";
+//       "fib";
+//       "(top level)" -> "fib" [label="1"];
+//       "fib" -> "fib" [color=red, label="14"];
+//   }
+pub fn calls(code: &Bytecode, counts: Option<&HashMap<(Option<usize>, usize), u64>>) -> String {
+    let name = |index: Option<usize>| {
+        index
+            .and_then(|index| code.enclosing_label(index))
+            .map_or(TOP_LEVEL.to_string(), label_name)
+    };
+    let mut nodes = BTreeSet::new();
+    if !code.ops.is_empty() && code.enclosing_label(0).is_none() {
+        nodes.insert(TOP_LEVEL.to_string());
+    }
+    nodes.extend(code.labels.iter().map(|(label, _)| label_name(label)));
+
+    let mut edges: BTreeMap<(String, String), (Edge, Option<u64>)> = BTreeMap::new();
+    for (index, &op) in code.ops.iter().enumerate() {
+        let (target, kind) = match op {
+            Op::Call(target) => (Some(target as usize), Edge::Call),
+            Op::Spawn(target) => (Some(target as usize), Edge::Spawn),
+            Op::Address(address) => (code.addresses.get(address as usize).copied(), Edge::Address),
+            _ => continue,
+        };
+        if target
+            .and_then(|target| code.enclosing_label(target))
+            .is_none()
+        {
+            continue;
+        }
+        let edge = edges
+            .entry((name(Some(index)), name(target)))
+            .or_insert((kind, None));
+        edge.0 = edge.0.min(kind);
+    }
+    // calls through EXECUTE only show up in the counts
+    for (&(caller, callee), &count) in counts.into_iter().flatten() {
+        let edge = edges
+            .entry((name(caller), name(Some(callee))))
+            .or_insert((Edge::Call, None));
+        edge.1 = Some(edge.1.unwrap_or(0) + count);
+    }
+
+    let mut out = "digraph calls {\n".to_string();
+    for node in &nodes {
+        out.push_str(&format!("    {};\n", quote(node)));
+    }
+    for ((caller, callee), &(kind, count)) in &edges {
+        let mut attributes = Vec::new();
+        match kind {
+            Edge::Call => (),
+            Edge::Spawn => attributes.push("style=dashed".to_string()),
+            Edge::Address => attributes.push("style=dotted".to_string()),
+        }
+        if reaches(&edges, callee, caller) {
+            attributes.push("color=red".to_string());
+        }
+        if let Some(count) = count {
+            attributes.push(format!("label=\"{}\"", count));
+        }
+        out.push_str(&format!("    {} -> {}", quote(caller), quote(callee)));
+        if !attributes.is_empty() {
+            out.push_str(&format!(" [{}]", attributes.join(", ")));
+        }
+        out.push_str(";\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+// whether `from` leads to `to` through calls and addresses; spawned tasks run on their own,
+// so they don't make recursion
+fn reaches(edges: &BTreeMap<(String, String), (Edge, Option<u64>)>, from: &str, to: &str) -> bool {
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![from];
+    while let Some(node) = pending.pop() {
+        if node == to {
+            return true;
+        }
+        if !seen.insert(node) {
+            continue;
+        }
+        pending.extend(
+            edges
+                .iter()
+                .filter(|((caller, _), &(kind, _))| caller == node && kind != Edge::Spawn)
+                .map(|((_, callee), _)| callee.as_str()),
+        );
+    }
+    false
+}
+
+pub fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,120 @@
+// a C API for embedding the interpreter, declared in include/fifth.h. Every function but
+// fifth_new() is unsafe for the same reason: it takes a program fifth_new() returned, which
+// must not be used after fifth_free()
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::io::{self, Write};
+use std::ptr;
+
+use crate::interpreter::{Program, RuntimeError};
+
+// fifth_step() results besides the error codes, which match the exit codes of the fifth
+// command
+const RUNNING: c_int = 0;
+const HALTED: c_int = 1;
+
+fn error_code(error: &RuntimeError) -> c_int {
+    match error {
+        RuntimeError::StackOverflow(_) => 3,
+        RuntimeError::StackUnderflow(_) => 4,
+        RuntimeError::InvalidLabel(_) => 5,
+        RuntimeError::CallStackUnderflow(_) => 6,
+        RuntimeError::UnclosedIfStatement(_) => 7,
+        RuntimeError::AssertionFailed(_) => 8,
+        RuntimeError::HostWordFailed(_, _) => 9,
+        RuntimeError::LimitExceeded(_, _) => 10,
+        RuntimeError::TaskFailed(_, _) => 11,
+        RuntimeError::Thrown(_, _) => 12,
+        RuntimeError::OutOfMemory(_) => 13,
+        RuntimeError::OutputFailed(_) => 14,
+    }
+}
+
+pub type OutputCallback = extern "C" fn(user_data: *mut c_void, bytes: *const u8, length: usize);
+
+// passes everything the program prints to the host
+struct CallbackOutput {
+    callback: OutputCallback,
+    user_data: *mut c_void,
+}
+
+impl Write for CallbackOutput {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        (self.callback)(self.user_data, buffer.as_ptr(), buffer.len());
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// a program from NUL-terminated UTF-8 source, or NULL if the source is NULL or not UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn fifth_new(source: *const c_char, stack_size: usize) -> *mut Program {
+    if source.is_null() {
+        return ptr::null_mut();
+    }
+    match CStr::from_ptr(source).to_str() {
+        Ok(source) => Box::into_raw(Box::new(Program::new(source, stack_size))),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+// 0, or the number of parse errors
+#[no_mangle]
+pub unsafe extern "C" fn fifth_parse(program: *mut Program) -> c_int {
+    let program = &mut *program;
+    match program.parse() {
+        Ok(()) => 0,
+        Err(diagnostics) => diagnostics.errors.len().try_into().unwrap_or(c_int::MAX),
+    }
+}
+
+// runs one instruction: 0 while the program runs, 1 once it has halted, or the exit code
+// of the fifth command for the runtime error it stopped with
+#[no_mangle]
+pub unsafe extern "C" fn fifth_step(program: *mut Program) -> c_int {
+    let program = &mut *program;
+    match program.step() {
+        Ok(()) if program.halted => HALTED,
+        Ok(()) => RUNNING,
+        Err(error) => error_code(&error),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fifth_stack_len(program: *const Program) -> usize {
+    let program = &*program;
+    program.stack.len()
+}
+
+// the value at `index` counted from the bottom of the stack, or -1 past its top
+#[no_mangle]
+pub unsafe extern "C" fn fifth_stack_get(program: *const Program, index: usize) -> c_int {
+    let program = &*program;
+    program.stack.get(index).map_or(-1, |&value| value.into())
+}
+
+// calls `callback` with `user_data` and the bytes of everything the program prints,
+// instead of writing to stdout; `user_data` has to stay valid while the program runs
+#[no_mangle]
+pub unsafe extern "C" fn fifth_set_output_callback(
+    program: *mut Program,
+    callback: OutputCallback,
+    user_data: *mut c_void,
+) {
+    let program = &mut *program;
+    program.set_output(Box::new(CallbackOutput {
+        callback,
+        user_data,
+    }));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fifth_free(program: *mut Program) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+use crate::bytecode::{Bytecode, Op, UNCLOSED};
+use crate::callgraph::quote;
+use crate::disasm::callee;
+use crate::interpreter::label_name;
+use crate::optimizer;
+
+// the control flow graph of every subroutine as a Graphviz DOT graph. The code is split
+// into basic blocks, which start where control can enter other than by falling through
+// (labels, jump targets and the return address after a call) and end after a branch,
+// call, return or halt; every subroutine's blocks are grouped in a cluster, e.g.
+//   digraph cfg {
+//       node [shape=box, fontname=monospace];
+//       subgraph "cluster_fib" {
+//           label="fib";
+//           b4 [label="4  jump_if_zero 20\l"];
+//       }
+//       b4 -> b5;
+//       b4 -> b20 [label="zero"];
+//   }
+pub fn cfg(code: &Bytecode) -> String {
+    let length = code.ops.len();
+    let mut starts = optimizer::entry_points(code);
+    starts[0] = true;
+    for (index, &op) in code.ops.iter().enumerate() {
+        if ends_block(op) {
+            starts[index + 1] = true;
+        }
+    }
+    let blocks: Vec<usize> = (0..length).filter(|&index| starts[index]).collect();
+
+    // blocks by the subroutine they belong to, the code before the first label first
+    let mut subroutines: BTreeMap<Option<&str>, Vec<usize>> = BTreeMap::new();
+    for &start in &blocks {
+        subroutines
+            .entry(code.enclosing_label(start))
+            .or_default()
+            .push(start);
+    }
+    let mut out = "digraph cfg {\n    node [shape=box, fontname=monospace];\n".to_string();
+    for (label, starts) in &subroutines {
+        let name = label.map_or("(top level)".to_string(), label_name);
+        out.push_str(&format!(
+            "    subgraph {} {{\n        label={};\n",
+            quote(&format!("cluster_{}", name)),
+            quote(&name)
+        ));
+        for &start in starts {
+            out.push_str(&format!(
+                "        b{} [label={}];\n",
+                start,
+                block_label(code, start, end(&blocks, start, length))
+            ));
+        }
+        out.push_str("    }\n");
+    }
+    let mut reaches_end = false;
+    for &start in &blocks {
+        let last = end(&blocks, start, length) - 1;
+        for (target, label) in successors(code.ops[last], last) {
+            let node = if target >= length {
+                reaches_end = true;
+                "end".to_string()
+            } else {
+                format!("b{}", target)
+            };
+            out.push_str(&format!("    b{} -> {}", start, node));
+            if let Some(label) = label {
+                out.push_str(&format!(" [label={}]", quote(label)));
+            }
+            out.push_str(";\n");
+        }
+    }
+    if reaches_end {
+        out.push_str("    end [shape=plain];\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn ends_block(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Jump(_)
+            | Op::JumpIfZero(_)
+            | Op::Of(_)
+            | Op::Try(_)
+            | Op::Catch(_)
+            | Op::Call(_)
+            | Op::Execute
+            | Op::Return
+            | Op::Halt
+            | Op::Exit
+            | Op::Throw
+    )
+}
+
+// where the block starting at `start` ends: at the next block, or the end of the code
+fn end(blocks: &[usize], start: usize, length: usize) -> usize {
+    blocks
+        .iter()
+        .find(|&&block| block > start)
+        .copied()
+        .unwrap_or(length)
+}
+
+// the ops of a block, left-aligned with their addresses
+fn block_label(code: &Bytecode, start: usize, end: usize) -> String {
+    let mut label = String::new();
+    for (index, &op) in code.ops[start..end].iter().enumerate() {
+        label.push_str(&format!("{}  {}", start + index, op));
+        if let Some(name) = callee(code, op) {
+            label.push_str(&format!(" ({})", name));
+        }
+        label.push_str("\\l");
+    }
+    // quote() would escape the backslashes of the line breaks
+    format!("\"{}\"", label.replace('"', "\\\""))
+}
+
+// the ops that can run after the op at `index`, which ends a block, with what the edge
+// means if it's not falling through or jumping unconditionally
+fn successors(op: Op, index: usize) -> Vec<(usize, Option<&'static str>)> {
+    let next = index + 1;
+    let target = |target: u32| (target != UNCLOSED).then_some(target as usize);
+    match op {
+        Op::Jump(jump) | Op::Catch(jump) => {
+            target(jump).map(|jump| (jump, None)).into_iter().collect()
+        }
+        Op::JumpIfZero(jump) => [(next, None)]
+            .into_iter()
+            .chain(target(jump).map(|jump| (jump, Some("zero"))))
+            .collect(),
+        Op::Of(jump) => [(next, Some("match"))]
+            .into_iter()
+            .chain(target(jump).map(|jump| (jump, None)))
+            .collect(),
+        Op::Try(jump) => [(next, None)]
+            .into_iter()
+            .chain(target(jump).map(|jump| (jump, Some("error"))))
+            .collect(),
+        Op::Return | Op::Halt | Op::Exit | Op::Throw => Vec::new(),
+        // a call continues after it once the subroutine returns
+        _ => vec![(next, None)],
+    }
+}
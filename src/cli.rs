@@ -0,0 +1,817 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::condition::{self, Breakpoint};
+use crate::file_io;
+use crate::lint::{Level, Rule};
+use crate::log::Verbosity;
+use crate::optimizer;
+use crate::project;
+
+#[derive(PartialEq)]
+pub enum Command {
+    Run,
+    Debug,
+    Test,
+    Check,
+    Fmt,
+    Lint,
+    Dap,
+    Compile,
+    Disasm,
+    Bench,
+    Graph,
+    Xref,
+    Doc,
+    Repl,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "run" => Some(Command::Run),
+            "debug" => Some(Command::Debug),
+            "test" => Some(Command::Test),
+            "check" => Some(Command::Check),
+            "fmt" => Some(Command::Fmt),
+            "lint" => Some(Command::Lint),
+            "dap" => Some(Command::Dap),
+            "compile" => Some(Command::Compile),
+            "disasm" => Some(Command::Disasm),
+            "bench" => Some(Command::Bench),
+            "graph" => Some(Command::Graph),
+            "xref" => Some(Command::Xref),
+            "doc" => Some(Command::Doc),
+            "repl" => Some(Command::Repl),
+            _ => None,
+        }
+    }
+}
+
+// what compile produces
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    Bytecode,
+    Wasm,
+}
+
+// what graph draws
+#[derive(Clone, Copy, PartialEq)]
+pub enum Graph {
+    Calls,
+    Cfg,
+}
+
+// what Ctrl-C does to a running program
+#[derive(Clone, Copy, PartialEq)]
+pub enum OnInterrupt {
+    // stop it, printing where it was
+    Dump,
+    // stop in the debugger
+    Break,
+}
+
+// what --profile produces
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProfileFormat {
+    Report,
+    Flamegraph,
+    // the call graph with call counts, for graph --profile
+    CallGraph,
+}
+
+// how errors and warnings are printed
+#[derive(Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+// whether diagnostics use ANSI colors; auto means only on a terminal without NO_COLOR
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+pub struct Config {
+    pub command: Command,
+    pub filename: String,
+    pub eval: Option<String>,
+    pub output: Option<String>,
+    pub target: Target,
+    pub input_file: Option<String>,
+    pub allow_fs: bool,
+    pub allow_net: bool,
+    pub allow_env: bool,
+    pub gfx_output: Option<String>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub snapshot_on_exit: bool,
+    pub output_state: bool,
+    pub resume: Option<String>,
+    pub stack_size: usize,
+    pub max_output: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub on_interrupt: OnInterrupt,
+    pub seed: Option<u64>,
+    pub stack_init: Vec<u8>,
+    pub program_args: Vec<u8>,
+    pub deny_warnings: bool,
+    pub case_sensitive: bool,
+    pub strict_case: bool,
+    pub stack_analysis: bool,
+    pub lint_levels: Vec<(Rule, Level)>,
+    pub fmt_check: bool,
+    pub golden: bool,
+    pub update: bool,
+    pub watch: bool,
+    pub fmt_stdout: bool,
+    pub dump_tokens: bool,
+    pub opt_level: u8,
+    pub inline_threshold: usize,
+    pub dump_optimized: bool,
+    pub graph: Graph,
+    pub html: bool,
+    pub listing: bool,
+    pub listing_file: Option<String>,
+    pub jit: bool,
+    pub profile: Option<ProfileFormat>,
+    pub stats: bool,
+    pub error_format: ErrorFormat,
+    pub color: ColorChoice,
+    pub verbosity: Verbosity,
+    pub log_file: Option<String>,
+    pub step: bool,
+    pub breakpoints: Vec<Breakpoint>,
+}
+
+pub fn usage() -> String {
+    [
+        "Usage: program [run] [OPTIONS] <filename> [-- <byte>...]",
+        "       program [run] [OPTIONS] -e <program> [-- <byte>...]",
+        "       program debug [OPTIONS] <filename> [-- <byte>...]",
+        "       program test [OPTIONS] <filename>",
+        "       program test --golden [--update] [OPTIONS] <directory>",
+        "       program check <filename>",
+        "       program fmt [--check] [--stdout] <filename>",
+        "       program lint [--allow=<rules>] [--deny=<rules>] <filename>",
+        "       program dap",
+        "       program compile [-O] [-o <file>] [--target=<target>] <filename>",
+        "       program disasm [-O] [--dump-tokens] <filename>",
+        "       program bench [-O] <filename> [-- <byte>...]",
+        "       program graph [--calls [--profile] | --cfg] [-o <file>] <filename>",
+        "       program xref <filename>",
+        "       program doc [--html] [-o <file>] <filename>",
+        "       program repl [OPTIONS] [<filename>]",
+        "Commands:",
+        "  run                  Run a program (default)",
+        "  debug                Run a program step by step, with watchpoints (see the README)",
+        "  test                 Run every subroutine whose name starts with test_",
+        "  check                Parse and validate a program without running it",
+        "  fmt                  Format a program in place (--check: only report if it isn't formatted)",
+        "  lint                 Report suspicious code; see the README for the rules",
+        "  dap                  Serve the Debug Adapter Protocol on stdin/stdout for editors",
+        "  compile              Compile a program to bytecode (.fbc), which run and debug accept",
+        "  disasm               Print the bytecode of a program or .fbc file",
+        "  bench                Measure how fast a program parses and runs, in instructions per second",
+        "  graph                Print the calls between subroutines (or with --cfg, the branches in them)",
+        "                       as a Graphviz DOT graph",
+        "  xref                 List where every label is defined and called, and calls to missing labels",
+        "  doc                  Document every subroutine from the comments above it, in Markdown",
+        "  repl                 Run lines as they're typed, after the program if one is given",
+        "Options:",
+        "  -e, --eval=<program> Run the given program text instead of a file",
+        "  --target=<target>    With compile, produce bytecode (default) or a wasm module",
+        "  -O, --optimize       Optimize the bytecode before running, compiling or disassembling it",
+        "  -O2                  Optimize more, inlining short subroutines into their callers",
+        "  --inline-threshold=<n>",
+        "                       With -O2, inline subroutines of up to n instructions (default: 8)",
+        "  --dump-optimized     With -O, print the bytecode before and after optimizing to stderr",
+        "  --listing[=<file>]   With run, debug or compile, write an assembler listing of the bytecode",
+        "                       to this file (default: <filename>.lst)",
+        "  --jit                Compile the code that runs often to native code (needs the jit feature)",
+        "  --profile[=<format>] Report the instructions run per line and subroutine to stderr,",
+        "                       or with =flamegraph write folded call stacks (<filename>.folded)",
+        "  --stats              Print instruction counts, peak stack and call depth and time to stderr",
+        "  -o, --output=<file>  With compile, graph, doc, a flamegraph or --snapshot-on-exit, write to this file instead",
+        "  --input-file=<file>  Read program input from a file instead of stdin",
+        "  --allow-fs           Let the program open, read and write files with fopen",
+        "  --allow-net          Let the program listen for and open TCP connections",
+        "  --allow-env          Let the program read environment variables with env",
+        "  --gfx-output=<file>  Write the frames gfx_flush shows to this PPM file (default: <filename>.ppm)",
+        "  --record=<file>      Write the program's input and seed to a file, to replay the run",
+        "  --replay=<file>      Run with the input and seed of a recorded run",
+        "  --snapshot-on-exit   Write the stack, call stack and pc to <filename>.snapshot when the run stops",
+        "  --output-state=json  Print the final stack, steps run and exit value as JSON to stderr",
+        "  --resume=<file>      Continue the run saved in a snapshot",
+        "  --watch              With run, run the program again whenever its file changes",
+        "  --stack-size=<size>  Set stack size (default: 256)",
+        "  --max-output=<bytes> Stop the program with an error once it printed more than this",
+        "  --timeout=<seconds>  Stop the program with an error once it ran this long",
+        "  --on-interrupt=<action>",
+        "                       On Ctrl-C, stop printing where the program was (dump, the default)",
+        "                       or stop in the debugger (break)",
+        "  --stack-init=<list>  Push comma-separated bytes before running",
+        "  --seed=<n>           Seed the RANDOM instruction (default: time-based)",
+        "  --check              Same as the check command without one (with fmt: don't write, fail if unformatted)",
+        "  --stdout             With fmt, print the formatted program instead of writing the file",
+        "  --golden             With test, compare what the programs in a directory print with their .expected files",
+        "  --update             With --golden, write the .expected files instead of comparing",
+        "  --html               With doc, write HTML instead of Markdown",
+        "  --dump-tokens        With disasm, print the parsed words of a program instead",
+        "  --deny-warnings      Treat warnings (unused labels, unreachable code) as errors",
+        "  --case-sensitive     Tell labels, variables and constants apart by case (Foo isn't foo)",
+        "  --strict-case        Warn about keywords in mixed case, like Push",
+        "  --stack-analysis     With check, find stack underflows and overflows on every path",
+        "  --allow=<rules>      With lint, don't report the comma-separated rules",
+        "  --deny=<rules>       With lint, treat the comma-separated rules as errors",
+        "  --error-format=<fmt> Print errors as human-readable text (default) or json",
+        "  --color=<when>       Color errors and warnings: auto (default), always or never",
+        "  -q, --quiet          Print only errors, no warnings",
+        "  -v, --verbose        Also print how a run ended, with the final stack",
+        "  -vv                  Also print the stack and the call stack before every step",
+        "  --log-file=<file>    Write traces, the debugger and -v output to a file instead of stderr",
+        "  -s, --step           Wait for a debugger command before every step",
+        "  --break=<breakpoint> Stop in the debugger at `line <n>` or `line <n> if <condition>`",
+    ]
+    .iter()
+    .map(|line| format!("{}\n", line))
+    .collect()
+}
+
+// the options in the project file (see project.rs) come first, so the ones on the command
+// line override them
+pub fn parse_args(args: &[String]) -> Result<Config, String> {
+    let config = parse_options(args, &[])?;
+    let Some(path) = project::find(&config.filename) else {
+        return Ok(config);
+    };
+    let defaults = project::load(&path)?;
+    parse_options(args, &defaults).map_err(|err| {
+        // the command line alone was fine, so the project file is at fault
+        format!("{} (with the options in {})", err, path.display())
+    })
+}
+
+// parses the command line with `defaults` in front of the options after the command
+fn parse_options(args: &[String], defaults: &[String]) -> Result<Config, String> {
+    let mut config = Config {
+        command: Command::Run,
+        filename: String::new(),
+        eval: None,
+        output: None,
+        target: Target::Bytecode,
+        input_file: None,
+        allow_fs: false,
+        allow_net: false,
+        allow_env: false,
+        gfx_output: None,
+        record: None,
+        replay: None,
+        snapshot_on_exit: false,
+        output_state: false,
+        resume: None,
+        stack_size: 256,
+        max_output: None,
+        timeout: None,
+        on_interrupt: OnInterrupt::Dump,
+        seed: None,
+        stack_init: Vec::new(),
+        program_args: Vec::new(),
+        deny_warnings: false,
+        case_sensitive: false,
+        strict_case: false,
+        stack_analysis: false,
+        lint_levels: Vec::new(),
+        fmt_check: false,
+        golden: false,
+        update: false,
+        watch: false,
+        fmt_stdout: false,
+        dump_tokens: false,
+        opt_level: 0,
+        inline_threshold: optimizer::DEFAULT_INLINE_THRESHOLD,
+        dump_optimized: false,
+        graph: Graph::Calls,
+        html: false,
+        listing: false,
+        listing_file: None,
+        jit: false,
+        profile: None,
+        stats: false,
+        error_format: ErrorFormat::Human,
+        color: ColorChoice::Auto,
+        verbosity: Verbosity::Normal,
+        log_file: None,
+        step: false,
+        breakpoints: Vec::new(),
+    };
+
+    let mut i = 1;
+    if let Some(command) = args.get(1).and_then(|arg| Command::from_name(arg)) {
+        config.command = command;
+        i += 1;
+    }
+    // --check stands in for the check command, but doesn't replace another one
+    let command_given = i > 1;
+    let args: Vec<String> = args[..i.min(args.len())]
+        .iter()
+        .chain(defaults)
+        .chain(args.get(i..).unwrap_or_default())
+        .cloned()
+        .collect();
+    if config.command == Command::Debug {
+        config.step = true;
+    }
+    while i < args.len() {
+        match args[i].as_str() {
+            "--" => {
+                for arg in &args[i + 1..] {
+                    let value = arg
+                        .parse()
+                        .map_err(|_| format!("Invalid program argument: {}", arg))?;
+                    config.program_args.push(value);
+                }
+                break;
+            }
+            "-e" | "--eval" => {
+                let program = args
+                    .get(i + 1)
+                    .ok_or_else(|| format!("Missing program for {}", args[i]))?;
+                config.eval = Some(program.clone());
+                i += 2;
+            }
+            arg if arg.starts_with("--eval=") => {
+                config.eval = Some(arg["--eval=".len()..].to_string());
+                i += 1;
+            }
+            "-o" | "--output" => {
+                let output = args
+                    .get(i + 1)
+                    .ok_or_else(|| format!("Missing file for {}", args[i]))?;
+                config.output = Some(output.clone());
+                i += 2;
+            }
+            arg if arg.starts_with("--output=") => {
+                config.output = Some(arg["--output=".len()..].to_string());
+                i += 1;
+            }
+            "--calls" if config.command == Command::Graph => {
+                config.graph = Graph::Calls;
+                i += 1;
+            }
+            "--html" if config.command == Command::Doc => {
+                config.html = true;
+                i += 1;
+            }
+            "--cfg" if config.command == Command::Graph => {
+                config.graph = Graph::Cfg;
+                i += 1;
+            }
+            "--check" if config.command == Command::Fmt => {
+                config.fmt_check = true;
+                i += 1;
+            }
+            "--check" if !command_given || config.command == Command::Check => {
+                config.command = Command::Check;
+                i += 1;
+            }
+            "--check" => {
+                return Err("--check only works with check and fmt".to_string());
+            }
+            "--stdout" => {
+                config.fmt_stdout = true;
+                i += 1;
+            }
+            "--golden" => {
+                config.golden = true;
+                i += 1;
+            }
+            "--update" => {
+                config.update = true;
+                i += 1;
+            }
+            "--watch" => {
+                config.watch = true;
+                i += 1;
+            }
+            "-O" | "--optimize" => {
+                config.opt_level = 1;
+                i += 1;
+            }
+            arg if arg.starts_with("-O") => {
+                let level_str = &arg["-O".len()..];
+                config.opt_level = match level_str.parse() {
+                    Ok(level) if level <= 2 => level,
+                    _ => return Err(format!("Invalid optimization level: {}", level_str)),
+                };
+                i += 1;
+            }
+            arg if arg.starts_with("--inline-threshold=") => {
+                let threshold_str = &arg["--inline-threshold=".len()..];
+                config.inline_threshold = threshold_str
+                    .parse()
+                    .map_err(|_| format!("Invalid inline threshold: {}", threshold_str))?;
+                i += 1;
+            }
+            "--dump-optimized" => {
+                config.dump_optimized = true;
+                i += 1;
+            }
+            "--profile" => {
+                config.profile = Some(ProfileFormat::Report);
+                i += 1;
+            }
+            arg if arg.starts_with("--profile=") => {
+                config.profile = match &arg["--profile=".len()..] {
+                    "report" => Some(ProfileFormat::Report),
+                    "flamegraph" => Some(ProfileFormat::Flamegraph),
+                    format => return Err(format!("Invalid profile format: {}", format)),
+                };
+                i += 1;
+            }
+            "--stats" => {
+                config.stats = true;
+                i += 1;
+            }
+            "--jit" => {
+                if !cfg!(feature = "jit") {
+                    return Err("This build has no JIT, rebuild it with --features jit".to_string());
+                }
+                config.jit = true;
+                i += 1;
+            }
+            "--dump-tokens" => {
+                config.dump_tokens = true;
+                i += 1;
+            }
+            "--deny-warnings" => {
+                config.deny_warnings = true;
+                i += 1;
+            }
+            "--case-sensitive" => {
+                config.case_sensitive = true;
+                i += 1;
+            }
+            "--strict-case" => {
+                config.strict_case = true;
+                i += 1;
+            }
+            "--stack-analysis" => {
+                config.stack_analysis = true;
+                i += 1;
+            }
+            "-q" | "--quiet" => {
+                config.verbosity = Verbosity::Quiet;
+                i += 1;
+            }
+            // -v twice is the same as -vv
+            "-v" | "--verbose" => {
+                config.verbosity = match config.verbosity {
+                    Verbosity::Verbose | Verbosity::Trace => Verbosity::Trace,
+                    _ => Verbosity::Verbose,
+                };
+                i += 1;
+            }
+            "-vv" => {
+                config.verbosity = Verbosity::Trace;
+                i += 1;
+            }
+            arg if arg.starts_with("--log-file=") => {
+                config.log_file = Some(arg["--log-file=".len()..].to_string());
+                i += 1;
+            }
+            "-s" | "--step" => {
+                config.step = true;
+                i += 1;
+            }
+            arg if arg.starts_with("--break=") => {
+                let breakpoint = &arg["--break=".len()..];
+                config.breakpoints.push(
+                    condition::parse_breakpoint(breakpoint)
+                        .map_err(|err| format!("Invalid breakpoint {}: {}", breakpoint, err))?,
+                );
+                i += 1;
+            }
+            arg if arg.starts_with("--record=") => {
+                config.record = Some(arg["--record=".len()..].to_string());
+                i += 1;
+            }
+            arg if arg.starts_with("--replay=") => {
+                config.replay = Some(arg["--replay=".len()..].to_string());
+                i += 1;
+            }
+            "--snapshot-on-exit" => {
+                config.snapshot_on_exit = true;
+                i += 1;
+            }
+            arg if arg.starts_with("--output-state=") => {
+                config.output_state = match &arg["--output-state=".len()..] {
+                    "json" => true,
+                    format => return Err(format!("Invalid state format: {}", format)),
+                };
+                i += 1;
+            }
+            arg if arg.starts_with("--resume=") => {
+                config.resume = Some(arg["--resume=".len()..].to_string());
+                i += 1;
+            }
+            arg if arg.starts_with("--input-file=") => {
+                config.input_file = Some(arg["--input-file=".len()..].to_string());
+                i += 1;
+            }
+            "--allow-fs" => {
+                config.allow_fs = true;
+                i += 1;
+            }
+            "--allow-net" => {
+                config.allow_net = true;
+                i += 1;
+            }
+            "--allow-env" => {
+                config.allow_env = true;
+                i += 1;
+            }
+            "--listing" => {
+                config.listing = true;
+                i += 1;
+            }
+            arg if arg.starts_with("--listing=") => {
+                config.listing = true;
+                config.listing_file = Some(arg["--listing=".len()..].to_string());
+                i += 1;
+            }
+            arg if arg.starts_with("--gfx-output=") => {
+                config.gfx_output = Some(arg["--gfx-output=".len()..].to_string());
+                i += 1;
+            }
+            arg if arg.starts_with("--stack-size=") => {
+                let size_str = &arg["--stack-size=".len()..];
+                config.stack_size = size_str
+                    .parse()
+                    .map_err(|_| format!("Invalid stack size: {}", size_str))?;
+                i += 1;
+            }
+            arg if arg.starts_with("--max-output=") => {
+                let bytes_str = &arg["--max-output=".len()..];
+                config.max_output = Some(
+                    bytes_str
+                        .parse()
+                        .map_err(|_| format!("Invalid output limit: {}", bytes_str))?,
+                );
+                i += 1;
+            }
+            arg if arg.starts_with("--timeout=") => {
+                let seconds_str = &arg["--timeout=".len()..];
+                config.timeout = Some(
+                    seconds_str
+                        .parse()
+                        .ok()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or_else(|| format!("Invalid timeout: {}", seconds_str))?,
+                );
+                i += 1;
+            }
+            arg if arg.starts_with("--on-interrupt=") => {
+                config.on_interrupt = match &arg["--on-interrupt=".len()..] {
+                    "dump" => OnInterrupt::Dump,
+                    "break" => OnInterrupt::Break,
+                    action => return Err(format!("Invalid interrupt action: {}", action)),
+                };
+                i += 1;
+            }
+            arg if arg.starts_with("--stack-init=") => {
+                let values_str = &arg["--stack-init=".len()..];
+                config.stack_init = values_str
+                    .split(',')
+                    .filter(|value| !value.is_empty())
+                    .map(|value| {
+                        value
+                            .trim()
+                            .parse()
+                            .map_err(|_| format!("Invalid stack value: {}", value))
+                    })
+                    .collect::<Result<_, _>>()?;
+                i += 1;
+            }
+            arg if arg.starts_with("--allow=") || arg.starts_with("--deny=") => {
+                let (option, rules) = arg.split_once('=').unwrap();
+                let level = if option == "--allow" {
+                    Level::Allow
+                } else {
+                    Level::Deny
+                };
+                for id in rules.split(',').filter(|id| !id.is_empty()) {
+                    let rule = Rule::from_id(id.trim())
+                        .ok_or_else(|| format!("Unknown lint rule: {}", id))?;
+                    config.lint_levels.push((rule, level));
+                }
+                i += 1;
+            }
+            arg if arg.starts_with("--target=") => {
+                config.target = match &arg["--target=".len()..] {
+                    "bytecode" => Target::Bytecode,
+                    "wasm" => Target::Wasm,
+                    target => return Err(format!("Invalid target: {}", target)),
+                };
+                i += 1;
+            }
+            arg if arg.starts_with("--error-format=") => {
+                config.error_format = match &arg["--error-format=".len()..] {
+                    "human" => ErrorFormat::Human,
+                    "json" => ErrorFormat::Json,
+                    format => return Err(format!("Invalid error format: {}", format)),
+                };
+                i += 1;
+            }
+            arg if arg.starts_with("--color=") => {
+                config.color = match &arg["--color=".len()..] {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    when => return Err(format!("Invalid color choice: {}", when)),
+                };
+                i += 1;
+            }
+            arg if arg.starts_with("--seed=") => {
+                let seed_str = &arg["--seed=".len()..];
+                config.seed = Some(
+                    seed_str
+                        .parse()
+                        .map_err(|_| format!("Invalid seed: {}", seed_str))?,
+                );
+                i += 1;
+            }
+            arg if arg.starts_with("-") && arg != "-" => {
+                return Err(format!("Unknown option: {}", arg));
+            }
+            _ => {
+                if config.filename.is_empty() {
+                    config.filename = args[i].clone();
+                } else {
+                    return Err("Multiple filenames specified".to_string());
+                }
+                i += 1;
+            }
+        }
+    }
+
+    // the debug adapter gets its program from the editor, and the repl from what's typed
+    if config.filename.is_empty()
+        && config.eval.is_none()
+        && !matches!(config.command, Command::Dap | Command::Repl)
+    {
+        return Err("No filename specified".to_string());
+    }
+    if !config.filename.is_empty() && config.eval.is_some() {
+        return Err("Both a filename and --eval specified".to_string());
+    }
+    // the output file is named after the input file by default
+    if config.output.is_none() && (config.eval.is_some() || config.filename == "-") {
+        if config.command == Command::Compile {
+            return Err("No output file specified for compile".to_string());
+        }
+        if config.profile == Some(ProfileFormat::Flamegraph) {
+            return Err("No output file specified for the flamegraph".to_string());
+        }
+        if config.snapshot_on_exit {
+            return Err("No output file specified for the snapshot".to_string());
+        }
+        if config.command == Command::Graph && config.profile.is_some() {
+            return Err("No output file specified for the call graph".to_string());
+        }
+    }
+    if config.graph == Graph::Cfg && config.profile.is_some() {
+        return Err("--profile only works with graph --calls".to_string());
+    }
+    // graph --profile runs the program and labels the calls with how often they ran
+    if config.command == Command::Graph && config.profile.is_some() {
+        config.profile = Some(ProfileFormat::CallGraph);
+    }
+    if config.profile == Some(ProfileFormat::Flamegraph) && config.snapshot_on_exit {
+        return Err(
+            "Both the flamegraph and the snapshot would be written to the output file".to_string(),
+        );
+    }
+    if config.output_state && !matches!(config.command, Command::Run | Command::Debug) {
+        return Err("--output-state only works with run and debug".to_string());
+    }
+    if config.golden && (config.command != Command::Test || config.eval.is_some()) {
+        return Err("--golden only works with test and a directory".to_string());
+    }
+    if config.command == Command::Bench && config.jit {
+        return Err(
+            "bench measures the interpreter, so it can't be combined with --jit".to_string(),
+        );
+    }
+    // each of these runs the program its own way, so only one of them can
+    let modes = [
+        (
+            config.step || !config.breakpoints.is_empty(),
+            "the debugger",
+        ),
+        (config.verbosity == Verbosity::Trace, "-vv"),
+        (config.profile.is_some(), "--profile"),
+        (config.stats, "--stats"),
+        (config.jit, "--jit"),
+    ];
+    let modes: Vec<&str> = modes
+        .iter()
+        .filter(|(given, _)| *given)
+        .map(|&(_, mode)| mode)
+        .collect();
+    if modes.len() > 1 {
+        return Err(format!("{} can't be combined", modes.join(" and ")));
+    }
+    // a program from -e or stdin has no file to watch
+    if config.watch
+        && (config.command != Command::Run
+            || config.eval.is_some()
+            || matches!(config.filename.as_str(), "" | "-"))
+    {
+        return Err("--watch only works with run and a file".to_string());
+    }
+    if config.update && !config.golden {
+        return Err("--update only works with --golden".to_string());
+    }
+    if config.record.is_some() && config.replay.is_some() {
+        return Err("Both --record and --replay specified".to_string());
+    }
+    // a recording brings its own input and seed
+    if config.replay.is_some() && (config.seed.is_some() || config.input_file.is_some()) {
+        return Err("--replay can't be combined with --seed or --input-file".to_string());
+    }
+    // so does a snapshot, and its stack
+    if config.resume.is_some()
+        && (config.seed.is_some()
+            || config.record.is_some()
+            || config.replay.is_some()
+            || !config.stack_init.is_empty()
+            || !config.program_args.is_empty())
+    {
+        return Err(
+            "--resume can't be combined with --seed, --record, --replay, --stack-init or arguments"
+                .to_string(),
+        );
+    }
+    if config.stack_init.len() + config.program_args.len() > config.stack_size {
+        return Err("Initial stack values do not fit on the stack".to_string());
+    }
+
+    Ok(config)
+}
+
+impl Config {
+    // the program text, or the bytes of a compiled program
+    pub fn contents(&self) -> io::Result<Vec<u8>> {
+        match &self.eval {
+            Some(program) => Ok(program.clone().into_bytes()),
+            None if self.filename.is_empty() => Ok(Vec::new()),
+            None => file_io::read_file(&self.filename),
+        }
+    }
+
+    pub fn output_path(&self) -> PathBuf {
+        match &self.output {
+            Some(output) => PathBuf::from(output),
+            None => Path::new(&self.filename).with_extension(match self.command {
+                Command::Compile if self.target == Target::Wasm => "wasm",
+                Command::Compile => "fbc",
+                Command::Graph => "dot",
+                _ if self.snapshot_on_exit => "snapshot",
+                _ => "folded",
+            }),
+        }
+    }
+
+    // programs without a file of their own write their frames to fifth.ppm
+    pub fn gfx_output_path(&self) -> PathBuf {
+        match (&self.gfx_output, &self.eval, self.filename.as_str()) {
+            (Some(gfx_output), _, _) => PathBuf::from(gfx_output),
+            (None, Some(_), _) | (None, None, "-") => PathBuf::from("fifth.ppm"),
+            (None, None, filename) => Path::new(filename).with_extension("ppm"),
+        }
+    }
+
+    // programs without a file of their own write their listing to fifth.lst
+    pub fn listing_path(&self) -> PathBuf {
+        match (&self.listing_file, &self.eval, self.filename.as_str()) {
+            (Some(listing_file), _, _) => PathBuf::from(listing_file),
+            (None, Some(_), _) | (None, None, "-") => PathBuf::from("fifth.lst"),
+            (None, None, filename) => Path::new(filename).with_extension("lst"),
+        }
+    }
+
+    // the name shown in error messages
+    pub fn source_name(&self) -> &str {
+        match (&self.eval, self.filename.as_str()) {
+            (Some(_), _) => "<eval>",
+            (None, "-") => "<stdin>",
+            (None, "") => "<repl>",
+            (None, filename) => filename,
+        }
+    }
+}
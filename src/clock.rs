@@ -0,0 +1,57 @@
+// what SLEEP waits with and TICKS reads. The system clock really sleeps; a virtual clock
+// only counts the time slept, for runs that have to come out the same every time (replays
+// and tests) and for builds without std, which have no clock
+pub trait Clock {
+    fn sleep(&mut self, milliseconds: u64);
+    // milliseconds since the program started
+    fn now(&mut self) -> u64;
+}
+
+// time only passes in SLEEP
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock {
+    // milliseconds slept so far
+    pub elapsed: u64,
+}
+
+impl Clock for VirtualClock {
+    fn sleep(&mut self, milliseconds: u64) {
+        self.elapsed += milliseconds;
+    }
+
+    fn now(&mut self) -> u64 {
+        self.elapsed
+    }
+}
+
+#[cfg(feature = "std")]
+pub struct SystemClock {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn sleep(&mut self, milliseconds: u64) {
+        std::thread::sleep(std::time::Duration::from_millis(milliseconds));
+    }
+
+    fn now(&mut self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
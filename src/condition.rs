@@ -0,0 +1,273 @@
+use crate::interpreter::Program;
+
+// a breakpoint condition over the machine state before a step, e.g.
+// `stack[0] == 42 && depth > 3`. Comparisons and ! give 1 or 0, and anything but 0 is true
+#[derive(Clone)]
+pub enum Expr {
+    Number(i64),
+    // a stack slot counted from the bottom; a condition using a slot the stack doesn't
+    // reach is false
+    Stack(Box<Expr>),
+    // a memory cell; an address past the memory makes the condition false
+    Memory(Box<Expr>),
+    // the number of values on the stack
+    Size,
+    // the index of the instruction that runs next
+    Pc,
+    // the number of subroutine calls that haven't returned
+    Depth,
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Sub,
+}
+
+// a breakpoint given as `line <n>` or `line <n> if <condition>`
+#[derive(Clone)]
+pub struct Breakpoint {
+    pub line: usize,
+    pub condition: Option<Expr>,
+}
+
+#[derive(Clone)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// longer symbols first, so `<=` isn't read as `<`
+const SYMBOLS: [&str; 15] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let length = if c.is_ascii_digit() {
+            let length = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let number = rest[..length]
+                .parse()
+                .map_err(|_| format!("Number too large: {}", &rest[..length]))?;
+            tokens.push(Token::Number(number));
+            length
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..length].to_lowercase()));
+            length
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(*symbol))
+                .ok_or_else(|| format!("Unexpected character: {}", c))?;
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// a recursive descent parser; from loosest to tightest binding: ||, &&, comparisons,
+// + and -, then ! and the operands
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let matches = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Expected {}", symbol))
+        }
+    }
+
+    // one level of left-associative binary operators
+    fn binary(
+        &mut self,
+        operators: &[(&str, BinaryOp)],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut left = operand(self)?;
+        'outer: loop {
+            for &(symbol, op) in operators {
+                if self.eat(symbol) {
+                    let right = operand(self)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let operators = [
+            ("==", BinaryOp::Equal),
+            ("!=", BinaryOp::NotEqual),
+            ("<=", BinaryOp::LessEqual),
+            (">=", BinaryOp::GreaterEqual),
+            ("<", BinaryOp::Less),
+            (">", BinaryOp::Greater),
+        ];
+        self.binary(&operators, Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let operators = [("+", BinaryOp::Add), ("-", BinaryOp::Sub)];
+        self.binary(&operators, Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        let token = self.peek().cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Name(name)) => match name.as_str() {
+                "stack" => {
+                    self.expect("[")?;
+                    let slot = self.or()?;
+                    self.expect("]")?;
+                    Ok(Expr::Stack(Box::new(slot)))
+                }
+                "mem" => {
+                    self.expect("[")?;
+                    let address = self.or()?;
+                    self.expect("]")?;
+                    Ok(Expr::Memory(Box::new(address)))
+                }
+                "size" => Ok(Expr::Size),
+                "pc" => Ok(Expr::Pc),
+                "depth" => Ok(Expr::Depth),
+                _ => Err(format!(
+                    "Unknown name: {} (try stack[<n>], mem[<n>], size, pc or depth)",
+                    name
+                )),
+            },
+            Some(Token::Symbol(symbol)) => Err(format!("Unexpected {}", symbol)),
+            None => Err("Unexpected end of condition".to_string()),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(Token::Number(number)) => Err(format!("Unexpected {}", number)),
+        Some(Token::Name(name)) => Err(format!("Unexpected {}", name)),
+        Some(Token::Symbol(symbol)) => Err(format!("Unexpected {}", symbol)),
+    }
+}
+
+pub fn parse_breakpoint(text: &str) -> Result<Breakpoint, String> {
+    let text = text.trim();
+    let (location, condition) = match text.split_once(" if ") {
+        Some((location, condition)) => (location, Some(parse(condition)?)),
+        None => (text, None),
+    };
+    let line = location
+        .trim()
+        .strip_prefix("line")
+        .and_then(|line| line.trim().parse().ok())
+        .ok_or_else(|| format!("Expected line <n>, got {}", location.trim()))?;
+    Ok(Breakpoint { line, condition })
+}
+
+impl Expr {
+    // None if the condition reads a stack slot that isn't there
+    pub fn eval(&self, program: &Program) -> Option<i64> {
+        let truth = |condition: bool| i64::from(condition);
+        Some(match self {
+            Expr::Number(number) => *number,
+            Expr::Stack(slot) => {
+                let slot = usize::try_from(slot.eval(program)?).ok()?;
+                (*program.stack.get(slot)?).into()
+            }
+            Expr::Memory(address) => {
+                let address = usize::try_from(address.eval(program)?).ok()?;
+                (*program.memory.get(address)?).into()
+            }
+            Expr::Size => program.stack.len() as i64,
+            Expr::Pc => program.pc as i64,
+            Expr::Depth => program.call_depth() as i64,
+            Expr::Not(operand) => truth(operand.eval(program)? == 0),
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(program)?;
+                // || and && only look at the right side when they need to
+                match op {
+                    BinaryOp::Or if left != 0 => return Some(1),
+                    BinaryOp::And if left == 0 => return Some(0),
+                    _ => (),
+                }
+                let right = right.eval(program)?;
+                match op {
+                    BinaryOp::Or | BinaryOp::And => truth(right != 0),
+                    BinaryOp::Equal => truth(left == right),
+                    BinaryOp::NotEqual => truth(left != right),
+                    BinaryOp::Less => truth(left < right),
+                    BinaryOp::LessEqual => truth(left <= right),
+                    BinaryOp::Greater => truth(left > right),
+                    BinaryOp::GreaterEqual => truth(left >= right),
+                    BinaryOp::Add => left.saturating_add(right),
+                    BinaryOp::Sub => left.saturating_sub(right),
+                }
+            }
+        })
+    }
+
+    pub fn holds(&self, program: &Program) -> bool {
+        self.eval(program).is_some_and(|value| value != 0)
+    }
+}
@@ -0,0 +1,158 @@
+use std::io;
+
+use crate::debugger::{Debugger, StepMode, Stop, Watched};
+use crate::disasm;
+use crate::interpreter::{RuntimeError, MEMORY_SIZE};
+use crate::log::{Log, Verbosity};
+
+// what can be typed at the prompt of the debug command
+enum Command {
+    Step,
+    // step over a call
+    Next,
+    // run until the current subroutine returns
+    Finish,
+    Continue,
+    Watch(Watched),
+}
+
+fn parse_watch(target: &str) -> Result<Command, String> {
+    if let Some(slot) = target
+        .strip_prefix("stack[")
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        return slot
+            .parse()
+            .map(|slot| Command::Watch(Watched::Stack(slot)))
+            .map_err(|_| format!("Invalid stack slot: {}", slot));
+    }
+    if let Some(address) = target
+        .strip_prefix("mem[")
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        return match address.parse() {
+            Ok(address) if address < MEMORY_SIZE => Ok(Command::Watch(Watched::Memory(address))),
+            _ => Err(format!("Invalid memory address: {}", address)),
+        };
+    }
+    Err(format!(
+        "Can't watch {}, only stack[<n>] or mem[<n>]",
+        target
+    ))
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        [] | ["s"] | ["step"] => Ok(Command::Step),
+        ["n"] | ["next"] => Ok(Command::Next),
+        ["f"] | ["finish"] => Ok(Command::Finish),
+        ["c"] | ["continue"] => Ok(Command::Continue),
+        ["watch", target] => parse_watch(target),
+        _ => Err(format!(
+            "Unknown command: {} (try step, next, finish, continue or watch stack[<n>] or mem[<n>])",
+            line.trim()
+        )),
+    }
+}
+
+// the debugger talks to whoever is debugging, so even -q doesn't silence it
+fn say(log: &Log, line: &str) {
+    log.write(Verbosity::Quiet, &format!("{}\n", line));
+}
+
+fn describe(value: Option<u8>) -> String {
+    value.map_or("empty".to_string(), |value| value.to_string())
+}
+
+// reads commands until one resumes the program; the end of the input steps through the rest
+fn prompt(debugger: &mut Debugger, log: &Log) -> io::Result<StepMode> {
+    loop {
+        log.flush();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(StepMode::StepIn);
+        }
+        match parse_command(&input) {
+            Ok(Command::Step) => return Ok(StepMode::StepIn),
+            Ok(Command::Next) => return Ok(StepMode::StepOver),
+            Ok(Command::Finish) if debugger.program.call_depth() == 0 => {
+                say(log, "Not in a subroutine")
+            }
+            Ok(Command::Finish) => return Ok(StepMode::StepOut),
+            Ok(Command::Continue) => return Ok(StepMode::Continue),
+            Ok(Command::Watch(watched)) => {
+                let value = debugger.watch(watched);
+                say(
+                    log,
+                    &format!("Watching {}, now {}", watched, describe(value)),
+                );
+            }
+            Err(message) => say(log, &message),
+        }
+    }
+}
+
+// runs the program for the debug command, printing the stack and the line and waiting for
+// a command whenever it stops; without `stop_on_entry` it first runs to a breakpoint
+pub fn run(
+    debugger: &mut Debugger,
+    stop_on_entry: bool,
+    log: &Log,
+) -> io::Result<Result<(), RuntimeError>> {
+    let mut mode = if stop_on_entry || debugger.at_breakpoint() {
+        None
+    } else {
+        Some(StepMode::Continue)
+    };
+    loop {
+        if let Some(mode) = mode.take() {
+            debugger.resume(mode);
+            match debugger.run(usize::MAX) {
+                Stop::Error(error) => return Ok(Err(error)),
+                Stop::Halted => return Ok(Ok(())),
+                Stop::Breakpoint => {
+                    let token = debugger.program.code.token(debugger.program.pc);
+                    say(log, &format!("Breakpoint at line {}", token.line_number));
+                }
+                Stop::Watchpoint {
+                    watched,
+                    old,
+                    new,
+                    index,
+                } => {
+                    let token = debugger.program.code.token(index);
+                    say(
+                        log,
+                        &format!(
+                            "{} changed from {} to {} by line {}: {}",
+                            watched,
+                            describe(old),
+                            describe(new),
+                            token.line_number,
+                            token.token
+                        ),
+                    );
+                }
+                _ => (),
+            }
+        }
+        let program = &debugger.program;
+        let Some(current_token) = program.current_token().filter(|_| !program.halted) else {
+            return Ok(Ok(()));
+        };
+        say(log, &format!("Stack: {:?}", program.stack));
+        log.write(
+            Verbosity::Quiet,
+            &disasm::window(&program.code, &program.source, program.pc, 2, 5),
+        );
+        say(
+            log,
+            &format!(
+                "Line {}: {}",
+                current_token.line_number, current_token.token
+            ),
+        );
+
+        mode = Some(prompt(debugger, log)?);
+    }
+}
@@ -0,0 +1,518 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::cli::Config;
+use crate::condition::{self, Breakpoint, Expr};
+use crate::debugger::{Debugger, StepMode, Stop};
+use crate::file_io::{self, SharedBuffer};
+use crate::interpreter::{label_name, Program};
+use crate::json::{self, Value};
+
+// instructions run between checks for new requests, so a pause is handled quickly
+const INSTRUCTIONS_PER_POLL: usize = 10_000;
+const THREAD_ID: i64 = 1;
+const DATA_STACK_REFERENCE: i64 = 1;
+
+// reads one "Content-Length: N\r\n\r\n<body>" message, None at the end of the input
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+// forwards requests from stdin, so they can be received while the program runs
+fn spawn_reader() -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        while let Ok(Some(message)) = read_message(&mut stdin) {
+            match json::parse(&message) {
+                Ok(value) => {
+                    if sender.send(value).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => eprintln!("Ignoring invalid message: {}", err),
+            }
+        }
+    });
+    receiver
+}
+
+struct Session<'a> {
+    config: &'a Config,
+    seq: i64,
+    debugger: Option<Debugger>,
+    source_path: String,
+    output: SharedBuffer,
+    // breakpoint lines received before the program was launched
+    breakpoints: Vec<Breakpoint>,
+    stop_on_entry: bool,
+    running: bool,
+    // set once the program halted or failed, it can't be resumed after that
+    exit_code: Option<i32>,
+    done: bool,
+}
+
+impl Session<'_> {
+    fn send(&mut self, mut fields: Vec<(&str, Value)>) -> io::Result<()> {
+        self.seq += 1;
+        fields.insert(0, ("seq", Value::Number(self.seq)));
+        let message = Value::object(fields).to_string();
+        let mut stdout = io::stdout().lock();
+        write!(
+            stdout,
+            "Content-Length: {}\r\n\r\n{}",
+            message.len(),
+            message
+        )?;
+        stdout.flush()
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(vec![
+            ("type", "response".into()),
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Value::Null),
+            ),
+            ("success", true.into()),
+            (
+                "command",
+                request.get("command").cloned().unwrap_or(Value::Null),
+            ),
+            ("body", body),
+        ])
+    }
+
+    fn respond_error(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(vec![
+            ("type", "response".into()),
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Value::Null),
+            ),
+            ("success", false.into()),
+            (
+                "command",
+                request.get("command").cloned().unwrap_or(Value::Null),
+            ),
+            ("message", message.into()),
+        ])
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(vec![
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ])
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        self.running = false;
+        self.event(
+            "stopped",
+            Value::object(vec![
+                ("reason", reason.into()),
+                ("threadId", Value::Number(THREAD_ID)),
+                ("text", text.into()),
+                ("allThreadsStopped", true.into()),
+            ]),
+        )
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        let bytes: Vec<u8> = self.output.0.borrow_mut().drain(..).collect();
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let output = String::from_utf8_lossy(&bytes).into_owned();
+        self.event(
+            "output",
+            Value::object(vec![
+                ("category", "stdout".into()),
+                ("output", output.into()),
+            ]),
+        )
+    }
+
+    fn terminate(&mut self, exit_code: i32) -> io::Result<()> {
+        self.running = false;
+        self.flush_output()?;
+        self.event(
+            "exited",
+            Value::object(vec![("exitCode", Value::Number(exit_code.into()))]),
+        )?;
+        self.event("terminated", Value::object(Vec::<(&str, Value)>::new()))
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<(), String> {
+        let path = arguments
+            .get("program")
+            .and_then(Value::as_str)
+            .ok_or("Missing 'program' in launch arguments")?;
+        let source = file_io::read_file_to_string(path).map_err(|err| err.to_string())?;
+        let stack_size = arguments
+            .get("stackSize")
+            .and_then(Value::as_i64)
+            .map_or(self.config.stack_size, |size| size.max(0) as usize);
+
+        let mut program = Program::new(&source, stack_size);
+        program.set_case_sensitive(self.config.case_sensitive);
+        program
+            .parse()
+            .map_err(|diagnostics| diagnostics.to_string())?;
+        if let Some(seed) = arguments.get("seed").and_then(Value::as_i64) {
+            program.seed(seed as u64);
+        }
+        // stdin carries the protocol, so the program only gets input from a file
+        match arguments.get("inputFile").and_then(Value::as_str) {
+            Some(input_file) => {
+                program.set_input(file_io::open_input(input_file).map_err(|err| err.to_string())?)
+            }
+            None => program.set_input(Box::new(io::empty())),
+        }
+        program.set_output(Box::new(self.output.clone()));
+        for arg in arguments
+            .get("args")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+        {
+            let byte = arg
+                .as_i64()
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or_else(|| format!("Invalid program argument: {}", arg))?;
+            if program.stack.len() >= program.stack_size {
+                return Err("Initial stack values do not fit on the stack".to_string());
+            }
+            program.stack.push(byte);
+        }
+
+        let mut debugger = Debugger::new(program);
+        debugger.set_breakpoints(&self.breakpoints);
+        self.debugger = Some(debugger);
+        self.source_path = path.to_string();
+        self.stop_on_entry = arguments
+            .get("stopOnEntry")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        Ok(())
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        // a breakpoint whose condition doesn't parse is reported unverified
+        let requested: Vec<(usize, Result<Option<Expr>, String>)> = arguments
+            .get("breakpoints")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|breakpoint| {
+                let line = breakpoint.get("line")?.as_i64()?.max(0) as usize;
+                let condition = breakpoint
+                    .get("condition")
+                    .and_then(Value::as_str)
+                    .filter(|condition| !condition.trim().is_empty())
+                    .map(condition::parse)
+                    .transpose();
+                Some((line, condition))
+            })
+            .collect();
+        self.breakpoints = requested
+            .iter()
+            .filter_map(|(line, condition)| {
+                Some(Breakpoint {
+                    line: *line,
+                    condition: condition.clone().ok()?,
+                })
+            })
+            .collect();
+        let mut resolved = match &mut self.debugger {
+            Some(debugger) => debugger.set_breakpoints(&self.breakpoints),
+            None => self
+                .breakpoints
+                .iter()
+                .map(|breakpoint| Some(breakpoint.line))
+                .collect(),
+        }
+        .into_iter();
+        let breakpoints = requested
+            .into_iter()
+            .map(|(line, condition)| match condition {
+                Ok(_) => {
+                    let resolved = resolved.next().flatten();
+                    Value::object(vec![
+                        ("verified", resolved.is_some().into()),
+                        ("line", resolved.unwrap_or(line).into()),
+                    ])
+                }
+                Err(message) => Value::object(vec![
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", message.into()),
+                ]),
+            })
+            .collect();
+        Value::object(vec![("breakpoints", Value::Array(breakpoints))])
+    }
+
+    fn stack_trace(&self) -> Value {
+        let Some(debugger) = &self.debugger else {
+            return Value::object(vec![("stackFrames", Value::Array(Vec::new()))]);
+        };
+        let program = &debugger.program;
+        let column = program
+            .code
+            .positions
+            .get(program.pc)
+            .map_or(1, |&(_, column)| column.max(1));
+        let frames: Vec<Value> = program
+            .backtrace()
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                Value::object(vec![
+                    ("id", id.into()),
+                    (
+                        "name",
+                        frame
+                            .label
+                            .as_ref()
+                            .map_or("<top level>".to_string(), |label| label_name(label))
+                            .into(),
+                    ),
+                    (
+                        "source",
+                        Value::object(vec![("path", self.source_path.as_str().into())]),
+                    ),
+                    ("line", frame.line_number.into()),
+                    ("column", if id == 0 { column } else { 1 }.into()),
+                ])
+            })
+            .collect();
+        Value::object(vec![
+            ("totalFrames", frames.len().into()),
+            ("stackFrames", Value::Array(frames)),
+        ])
+    }
+
+    // the data stack, listed from the top like PICK counts
+    fn variables(&self) -> Value {
+        let stack = self
+            .debugger
+            .as_ref()
+            .map_or(&[][..], |debugger| &debugger.program.stack);
+        let variables = stack
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, &byte)| {
+                let value = if byte.is_ascii_graphic() || byte == b' ' {
+                    format!("{} '{}'", byte, char::from(byte))
+                } else {
+                    byte.to_string()
+                };
+                Value::object(vec![
+                    ("name", depth.to_string().into()),
+                    ("value", value.into()),
+                    ("variablesReference", Value::Number(0)),
+                ])
+            })
+            .collect();
+        Value::object(vec![("variables", Value::Array(variables))])
+    }
+
+    fn resume(&mut self, mode: StepMode) -> io::Result<()> {
+        match (&mut self.debugger, self.exit_code) {
+            (Some(debugger), None) => {
+                debugger.resume(mode);
+                self.running = true;
+                Ok(())
+            }
+            // resuming after a runtime error ends the session
+            (Some(debugger), Some(exit_code)) if !debugger.program.halted => {
+                debugger.program.halted = true;
+                self.terminate(exit_code)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn handle(&mut self, request: &Value) -> io::Result<()> {
+        let empty = Value::object(Vec::<(&str, Value)>::new());
+        let arguments = request.get("arguments").unwrap_or(&empty);
+        match request.get("command").and_then(Value::as_str).unwrap_or("") {
+            "initialize" => self.respond(
+                request,
+                Value::object(vec![
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsConditionalBreakpoints", true.into()),
+                    ("supportsTerminateRequest", true.into()),
+                ]),
+            ),
+            "launch" => match self.launch(arguments) {
+                Ok(()) => {
+                    self.respond(request, empty.clone())?;
+                    self.event("initialized", empty)
+                }
+                Err(err) => self.respond_error(request, &err),
+            },
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(arguments);
+                self.respond(request, body)
+            }
+            "setExceptionBreakpoints" => self.respond(request, empty),
+            "configurationDone" => {
+                self.respond(request, empty)?;
+                if self.stop_on_entry {
+                    self.stopped("entry", None)
+                } else {
+                    self.resume(StepMode::Continue)
+                }
+            }
+            "threads" => self.respond(
+                request,
+                Value::object(vec![(
+                    "threads",
+                    Value::Array(vec![Value::object(vec![
+                        ("id", Value::Number(THREAD_ID)),
+                        ("name", "main".into()),
+                    ])]),
+                )]),
+            ),
+            "stackTrace" => {
+                let body = self.stack_trace();
+                self.respond(request, body)
+            }
+            "scopes" => self.respond(
+                request,
+                Value::object(vec![(
+                    "scopes",
+                    Value::Array(vec![Value::object(vec![
+                        ("name", "Data stack".into()),
+                        ("variablesReference", Value::Number(DATA_STACK_REFERENCE)),
+                        ("expensive", false.into()),
+                    ])]),
+                )]),
+            ),
+            "variables" => {
+                let body = self.variables();
+                self.respond(request, body)
+            }
+            "continue" => {
+                self.respond(
+                    request,
+                    Value::object(vec![("allThreadsContinued", true.into())]),
+                )?;
+                self.resume(StepMode::Continue)
+            }
+            "next" => {
+                self.respond(request, empty)?;
+                self.resume(StepMode::StepOver)
+            }
+            "stepIn" => {
+                self.respond(request, empty)?;
+                self.resume(StepMode::StepIn)
+            }
+            "stepOut" => {
+                self.respond(request, empty)?;
+                self.resume(StepMode::StepOut)
+            }
+            "pause" => {
+                self.respond(request, empty)?;
+                self.flush_output()?;
+                self.stopped("pause", None)
+            }
+            "disconnect" | "terminate" => {
+                self.done = true;
+                self.respond(request, empty)
+            }
+            command => self.respond_error(request, &format!("Unsupported request '{}'", command)),
+        }
+    }
+
+    // runs the program for a while and reports why it stopped, if it did
+    fn run(&mut self) -> io::Result<()> {
+        let Some(debugger) = &mut self.debugger else {
+            self.running = false;
+            return Ok(());
+        };
+        let stop = debugger.run(INSTRUCTIONS_PER_POLL);
+        let exit_code = debugger.program.exit_code;
+        self.flush_output()?;
+        match stop {
+            Stop::Running => Ok(()),
+            Stop::Breakpoint => self.stopped("breakpoint", None),
+            Stop::Step => self.stopped("step", None),
+            Stop::Watchpoint { .. } => self.stopped("data breakpoint", None),
+            Stop::Halted => {
+                let exit_code = exit_code.map_or(0, i32::from);
+                self.exit_code = Some(exit_code);
+                self.terminate(exit_code)
+            }
+            Stop::Error(err) => {
+                self.exit_code = Some(crate::runtime_exit_code(&err));
+                let text = format!("{} at line {}", err, err.token().line_number);
+                self.stopped("exception", Some(text))
+            }
+        }
+    }
+}
+
+// serves the Debug Adapter Protocol on stdin and stdout until the client disconnects
+pub fn serve(config: &Config) -> Result<(), Box<dyn Error>> {
+    let requests = spawn_reader();
+    let mut session = Session {
+        config,
+        seq: 0,
+        debugger: None,
+        source_path: String::new(),
+        output: SharedBuffer::default(),
+        breakpoints: Vec::new(),
+        stop_on_entry: false,
+        running: false,
+        exit_code: None,
+        done: false,
+    };
+    while !session.done {
+        let request = if session.running {
+            match requests.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match requests.recv() {
+                Ok(request) => Some(request),
+                Err(_) => break,
+            }
+        };
+        match request {
+            Some(request) => session.handle(&request)?,
+            None => session.run()?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::condition::{Breakpoint, Expr};
+use crate::interpreter::{Program, RuntimeError};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StepMode {
+    // run until a breakpoint or the end of the program
+    Continue,
+    // run a single instruction
+    StepIn,
+    // run a single instruction, treating a subroutine call as one instruction
+    StepOver,
+    // run until the current subroutine returns
+    StepOut,
+}
+
+// what a watchpoint watches: a stack slot counted from the bottom, or a memory cell
+#[derive(Clone, Copy, PartialEq)]
+pub enum Watched {
+    Stack(usize),
+    Memory(usize),
+}
+
+impl Watched {
+    fn value(self, program: &Program) -> Option<u8> {
+        match self {
+            Watched::Stack(slot) => program.stack.get(slot).copied(),
+            Watched::Memory(address) => program.memory.get(address).copied(),
+        }
+    }
+}
+
+impl fmt::Display for Watched {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watched::Stack(slot) => write!(f, "stack[{}]", slot),
+            Watched::Memory(address) => write!(f, "mem[{}]", address),
+        }
+    }
+}
+
+// why the debugger stopped running the program
+pub enum Stop {
+    Breakpoint,
+    Step,
+    Halted,
+    Error(RuntimeError),
+    // a watched stack slot or memory cell changed; `index` is the op that changed it, and
+    // a slot that doesn't exist has no value
+    Watchpoint {
+        watched: Watched,
+        old: Option<u8>,
+        new: Option<u8>,
+        index: usize,
+    },
+    // the instruction budget ran out, the program can be run further
+    Running,
+}
+
+pub struct Debugger {
+    pub program: Program,
+    // the conditions of the breakpoints at every op index, None for an unconditional one
+    breakpoints: HashMap<usize, Vec<Option<Expr>>>,
+    // what's watched, with the value it had after the last step
+    watchpoints: Vec<(Watched, Option<u8>)>,
+    mode: StepMode,
+    // call depth when the program was last resumed
+    depth: usize,
+    // whether an instruction ran since resuming, so a breakpoint doesn't stop twice
+    moved: bool,
+}
+
+impl Debugger {
+    // `program` must already be parsed
+    pub fn new(program: Program) -> Self {
+        Debugger {
+            program,
+            breakpoints: HashMap::new(),
+            watchpoints: Vec::new(),
+            mode: StepMode::Continue,
+            depth: 0,
+            moved: false,
+        }
+    }
+
+    // replaces all breakpoints; a breakpoint on a line without instructions moves to the
+    // next line that has some. Returns the line every breakpoint ended up on, if any
+    pub fn set_breakpoints(&mut self, breakpoints: &[Breakpoint]) -> Vec<Option<usize>> {
+        self.breakpoints.clear();
+        breakpoints
+            .iter()
+            .map(|breakpoint| {
+                let positions = &self.program.code.positions;
+                let index = positions
+                    .iter()
+                    .position(|&(line_number, _)| line_number >= breakpoint.line)?;
+                let conditions = self.breakpoints.entry(index).or_default();
+                conditions.push(breakpoint.condition.clone());
+                Some(positions[index].0)
+            })
+            .collect()
+    }
+
+    // whether a breakpoint at the next op holds
+    pub fn at_breakpoint(&self) -> bool {
+        self.breakpoints
+            .get(&self.program.pc)
+            .is_some_and(|conditions| {
+                conditions.iter().any(|condition| match condition {
+                    Some(condition) => condition.holds(&self.program),
+                    None => true,
+                })
+            })
+    }
+
+    // stops the program whenever the watched value changes; returns its current value
+    pub fn watch(&mut self, watched: Watched) -> Option<u8> {
+        let value = watched.value(&self.program);
+        if !self.watchpoints.iter().any(|&(other, _)| other == watched) {
+            self.watchpoints.push((watched, value));
+        }
+        value
+    }
+
+    // the first watched value the op at `index` changed
+    fn check_watchpoints(&mut self, index: usize) -> Option<Stop> {
+        let mut changed = None;
+        for (watched, value) in &mut self.watchpoints {
+            let new = watched.value(&self.program);
+            if new != *value {
+                let old = std::mem::replace(value, new);
+                changed = changed.or(Some(Stop::Watchpoint {
+                    watched: *watched,
+                    old,
+                    new,
+                    index,
+                }));
+            }
+        }
+        changed
+    }
+
+    pub fn resume(&mut self, mode: StepMode) {
+        self.mode = mode;
+        self.depth = self.program.call_depth();
+        self.moved = false;
+    }
+
+    // runs at most `budget` instructions, then writes out what the program printed so it
+    // shows before the debugger says where it stopped
+    pub fn run(&mut self, budget: usize) -> Stop {
+        let stop = self.run_steps(budget);
+        match self.program.flush() {
+            Err(error) if !matches!(stop, Stop::Error(_)) => Stop::Error(error),
+            _ => stop,
+        }
+    }
+
+    fn run_steps(&mut self, budget: usize) -> Stop {
+        for _ in 0..budget {
+            if self.moved && self.at_breakpoint() {
+                return Stop::Breakpoint;
+            }
+            let index = self.program.pc;
+            if let Err(err) = self.program.step() {
+                return Stop::Error(err);
+            }
+            self.moved = true;
+            if self.program.halted {
+                return Stop::Halted;
+            }
+            if let Some(stop) = self.check_watchpoints(index) {
+                return stop;
+            }
+            let depth = self.program.call_depth();
+            match self.mode {
+                StepMode::StepIn => return Stop::Step,
+                StepMode::StepOver if depth <= self.depth => return Stop::Step,
+                StepMode::StepOut if depth < self.depth => return Stop::Step,
+                _ => (),
+            }
+        }
+        Stop::Running
+    }
+}
@@ -0,0 +1,339 @@
+use crate::analysis::Warning;
+use std::env;
+use std::io::{self, IsTerminal};
+
+use crate::cli::{ColorChoice, Config, ErrorFormat};
+use crate::interpreter::{label_name, Frame, ParseError, RuntimeError};
+use crate::json::Value;
+use crate::lint::Lint;
+use crate::log::Verbosity;
+use crate::stack_analysis::StackIssue;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Parse,
+    Runtime,
+    Analysis,
+    Lint,
+    Io,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Parse => "parse",
+            Kind::Runtime => "runtime",
+            Kind::Analysis => "analysis",
+            Kind::Lint => "lint",
+            Kind::Io => "io",
+        }
+    }
+}
+
+// the most lines of callers a backtrace shows, half of them the innermost and half the
+// outermost
+const MAX_CALLER_LINES: usize = 20;
+
+// the lines of a backtrace after the innermost frame, like `called from 'fact' line 5`. A
+// run of calls from the same place, which a recursive loop makes, shows once with the
+// number of the others, e.g. `... called 6999 more times from 'loop' line 5`
+pub fn caller_lines(backtrace: &[Frame]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut callers = backtrace.iter().skip(1).peekable();
+    while let Some(frame) = callers.next() {
+        lines.push(format!("called from {}", frame));
+        let mut repeated = 0;
+        while callers.next_if(|caller| *caller == frame).is_some() {
+            repeated += 1;
+        }
+        if repeated == 1 {
+            lines.push(format!("called from {}", frame));
+        } else if repeated > 1 {
+            lines.push(format!("... called {} more times from {}", repeated, frame));
+        }
+    }
+    if lines.len() > MAX_CALLER_LINES {
+        let hidden = lines.len() - MAX_CALLER_LINES;
+        let half = MAX_CALLER_LINES / 2;
+        lines.splice(
+            half..half + hidden,
+            [format!("... {} more lines of callers", hidden)],
+        );
+    }
+    lines
+}
+
+// one error or warning, independent of how it gets printed
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: Kind,
+    pub code: &'static str,
+    pub message: String,
+    // 1-based; 0 if unknown
+    pub line_number: usize,
+    pub column: usize,
+    pub backtrace: Vec<Frame>,
+    pub stack: Option<Vec<u8>>,
+}
+
+impl Diagnostic {
+    fn new(kind: Kind, severity: Severity, code: &'static str, message: String) -> Self {
+        Diagnostic {
+            severity,
+            kind,
+            code,
+            message,
+            line_number: 0,
+            column: 0,
+            backtrace: Vec::new(),
+            stack: None,
+        }
+    }
+
+    fn at(mut self, line_number: usize, column: usize) -> Self {
+        self.line_number = line_number;
+        self.column = column;
+        self
+    }
+
+    pub fn parse_error(err: &ParseError) -> Self {
+        Diagnostic::new(Kind::Parse, Severity::Error, err.code(), err.to_string())
+            .at(err.line_number(), err.column())
+    }
+
+    pub fn runtime_error(err: &RuntimeError, backtrace: Vec<Frame>, stack: Vec<u8>) -> Self {
+        let token = err.token();
+        Diagnostic {
+            backtrace,
+            stack: Some(stack),
+            ..Diagnostic::new(Kind::Runtime, Severity::Error, err.code(), err.to_string())
+                .at(token.line_number, token.column)
+        }
+    }
+
+    pub fn warning(warning: &Warning) -> Self {
+        Diagnostic::new(
+            Kind::Analysis,
+            Severity::Warning,
+            warning.code(),
+            warning.to_string(),
+        )
+        .at(warning.line_number(), warning.column())
+    }
+
+    pub fn stack_issue(issue: &StackIssue) -> Self {
+        Diagnostic::new(
+            Kind::Analysis,
+            Severity::Error,
+            issue.code(),
+            issue.to_string(),
+        )
+        .at(issue.line_number(), issue.column())
+    }
+
+    pub fn lint(lint: &Lint, severity: Severity) -> Self {
+        Diagnostic::new(Kind::Lint, severity, lint.rule.id(), lint.to_string())
+            .at(lint.line_number, lint.column)
+    }
+
+    pub fn io_error(err: &dyn std::error::Error) -> Self {
+        Diagnostic::new(Kind::Io, Severity::Error, "io-error", err.to_string())
+    }
+
+    fn title(&self) -> &'static str {
+        match (self.severity, self.kind) {
+            (Severity::Warning, _) => "Warning",
+            (Severity::Error, Kind::Parse) => "Parse error",
+            (Severity::Error, Kind::Runtime) => "Runtime error",
+            (Severity::Error, _) => "Error",
+        }
+    }
+}
+
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+const BOLD: &str = "1";
+
+// prints diagnostics about one program to stderr in the configured format
+pub struct Reporter<'a> {
+    format: ErrorFormat,
+    color: bool,
+    file: &'a str,
+    source: &'a str,
+    // with -q warnings aren't printed, unless --deny-warnings makes them count
+    quiet: bool,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(config: &'a Config, source: &'a str) -> Self {
+        let color = match config.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                io::stderr().is_terminal()
+                    && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        };
+        Reporter {
+            format: config.error_format,
+            color,
+            file: config.source_name(),
+            source,
+            quiet: config.verbosity == Verbosity::Quiet && !config.deny_warnings,
+        }
+    }
+
+    // wraps text in an ANSI style if colors are enabled
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", style, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == ErrorFormat::Json
+    }
+
+    pub fn emit(&self, diagnostic: &Diagnostic) {
+        if self.quiet && diagnostic.severity == Severity::Warning {
+            return;
+        }
+        match self.format {
+            ErrorFormat::Human => eprint!("{}", self.human(diagnostic)),
+            ErrorFormat::Json => eprintln!("{}", self.json(diagnostic)),
+        }
+    }
+
+    fn line(&self, line_number: usize) -> Option<&str> {
+        line_number
+            .checked_sub(1)
+            .and_then(|index| self.source.lines().nth(index))
+    }
+
+    // length in characters of the word starting at the diagnostic's column
+    fn word_length(&self, diagnostic: &Diagnostic) -> usize {
+        match self.line(diagnostic.line_number) {
+            Some(line) if diagnostic.column > 0 => line
+                .chars()
+                .skip(diagnostic.column - 1)
+                .take_while(|c| !c.is_whitespace())
+                .count()
+                .max(1),
+            _ => 0,
+        }
+    }
+
+    fn human(&self, diagnostic: &Diagnostic) -> String {
+        let style = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut out = match diagnostic.kind {
+            Kind::Io => format!(
+                "{} {}\n",
+                self.paint(style, "Error:"),
+                self.paint(BOLD, &diagnostic.message)
+            ),
+            // lints name their rule, so it can be passed to --allow or --deny
+            Kind::Lint => format!(
+                "{} in {} at line {}: {} [{}]\n",
+                self.paint(style, diagnostic.title()),
+                self.file,
+                diagnostic.line_number,
+                self.paint(BOLD, &diagnostic.message),
+                diagnostic.code
+            ),
+            _ => format!(
+                "{} in {} at line {}: {}\n",
+                self.paint(style, diagnostic.title()),
+                self.file,
+                diagnostic.line_number,
+                self.paint(BOLD, &diagnostic.message)
+            ),
+        };
+        if let Some(source_line) = self.line(diagnostic.line_number) {
+            // the line with the offending word underlined, e.g.
+            //    11 |   pop
+            //       |   ^^^
+            let gutter = diagnostic.line_number.to_string();
+            let bar = self.paint(BLUE, &format!("{} |", gutter));
+            out.push_str(&format!("  {} {}\n", bar, source_line));
+            if diagnostic.column > 0 {
+                let bar = self.paint(BLUE, &format!("{} |", " ".repeat(gutter.len())));
+                let underline = "^".repeat(self.word_length(diagnostic));
+                out.push_str(&format!(
+                    "  {} {}{}\n",
+                    bar,
+                    " ".repeat(diagnostic.column - 1),
+                    self.paint(style, &underline)
+                ));
+            }
+        }
+        if let Some(frame) = diagnostic.backtrace.first() {
+            out.push_str(&format!("  in {}\n", frame));
+        }
+        for line in caller_lines(&diagnostic.backtrace) {
+            out.push_str(&format!("  {}\n", line));
+        }
+        if let Some(stack) = &diagnostic.stack {
+            out.push_str(&format!("  stack: {:?}\n", stack));
+        }
+        out
+    }
+
+    fn json(&self, diagnostic: &Diagnostic) -> Value {
+        let position = |value: usize| (value > 0).then_some(value);
+        let span = match (diagnostic.line_number, diagnostic.column) {
+            (0, _) | (_, 0) => Value::Null,
+            (line_number, column) => Value::object(vec![
+                ("line", line_number.into()),
+                ("start_column", column.into()),
+                ("end_column", (column + self.word_length(diagnostic)).into()),
+            ]),
+        };
+        let mut fields = vec![
+            (
+                "severity",
+                match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                }
+                .into(),
+            ),
+            ("kind", diagnostic.kind.name().into()),
+            ("code", diagnostic.code.into()),
+            ("message", diagnostic.message.as_str().into()),
+            ("file", self.file.into()),
+            ("line", position(diagnostic.line_number).into()),
+            ("column", position(diagnostic.column).into()),
+            ("span", span),
+        ];
+        if diagnostic.kind == Kind::Runtime {
+            let backtrace = diagnostic
+                .backtrace
+                .iter()
+                .map(|frame| {
+                    Value::object(vec![
+                        (
+                            "label",
+                            frame.label.as_ref().map(|label| label_name(label)).into(),
+                        ),
+                        ("line", frame.line_number.into()),
+                    ])
+                })
+                .collect();
+            fields.push(("backtrace", Value::Array(backtrace)));
+            fields.push(("stack", diagnostic.stack.clone().into()));
+        }
+        Value::object(fields)
+    }
+}
@@ -0,0 +1,178 @@
+use crate::bytecode::{Bytecode, Op};
+use crate::interpreter::{label_name, AnnotatedToken};
+use fifth::source::Source;
+
+// width of the instruction column, so the source references line up
+const INSTRUCTION_WIDTH: usize = 24;
+
+// one line per op with its address, the instruction and the source line it came from;
+// labels are printed in front of the op they point at, e.g.
+//   fib:
+//      4  dup                      ; line 8: dup
+//      5  jump_if_zero 9           ; line 9: if
+pub fn disassemble(code: &Bytecode, source: &Source) -> String {
+    let width = code.ops.len().max(1).to_string().len().max(4);
+    let mut out = String::new();
+    let mut labels = code.labels.iter().peekable();
+    for (index, op) in code.ops.iter().enumerate() {
+        while let Some((label, _)) = labels.next_if(|&&(_, start)| start == index) {
+            out.push_str(&format!("{}:\n", label_name(label)));
+        }
+        let mut instruction = op.to_string();
+        if let Some(name) = callee(code, *op) {
+            instruction.push_str(&format!(" ({})", name));
+        }
+        out.push_str(&format!(
+            "{:>width$}  {:<INSTRUCTION_WIDTH$} {}\n",
+            index,
+            instruction,
+            reference(code, source, index)
+        ));
+    }
+    // labels at the very end of the program point past the last op
+    for (label, _) in labels {
+        out.push_str(&format!("{}:\n", label_name(label)));
+    }
+    out
+}
+
+// the subroutine a call, spawn or address goes to, or the name of a host word
+pub fn callee(code: &Bytecode, op: Op) -> Option<String> {
+    let name = match op {
+        Op::Call(target) | Op::Spawn(target) => code.enclosing_label(target as usize)?,
+        Op::Address(address) => code.enclosing_label(*code.addresses.get(address as usize)?)?,
+        Op::Native(index) => code.natives.get(index as usize)?,
+        _ => return None,
+    };
+    Some(label_name(name))
+}
+
+// the source line an op came from, e.g. `; line 9: if`
+fn reference(code: &Bytecode, source: &Source, index: usize) -> String {
+    let (line_number, _) = code.positions[index];
+    match source.line(line_number) {
+        Some(line) => format!("; line {}: {}", line_number, line.trim()),
+        None => format!("; line {}", line_number),
+    }
+}
+
+// the ops from `before` ops in front of `pc` to `after` ops behind it, with an arrow at `pc`
+// and the targets of jumps and calls resolved to a line or label, e.g.
+//      5  jump_if_zero 9 (line 12: push 0) ; line 9: if
+//   -> 6  push 1                   ; line 10: push 1
+pub fn window(code: &Bytecode, source: &Source, pc: usize, before: usize, after: usize) -> String {
+    let width = code.ops.len().max(1).to_string().len();
+    let end = code.ops.len().min(pc.saturating_add(after + 1));
+    let mut out = String::new();
+    for index in pc.saturating_sub(before)..end {
+        let op = code.ops[index];
+        let mut instruction = op.to_string();
+        match op {
+            Op::Call(_) | Op::Native(_) | Op::Spawn(_) | Op::Address(_) => {
+                if let Some(name) = callee(code, op) {
+                    instruction.push_str(&format!(" ({})", name));
+                }
+            }
+            Op::Jump(target) | Op::JumpIfZero(target) | Op::Of(target)
+                if target as usize >= code.ops.len() =>
+            {
+                instruction.push_str(" (end)")
+            }
+            Op::Jump(target) | Op::JumpIfZero(target) | Op::Of(target) => {
+                let token = code.token(target as usize);
+                instruction.push_str(&format!(" (line {}: {})", token.line_number, token.token));
+            }
+            _ => (),
+        }
+        let arrow = if index == pc { "->" } else { "  " };
+        out.push_str(&format!(
+            "{} {:>width$}  {:<INSTRUCTION_WIDTH$} {}\n",
+            arrow,
+            index,
+            instruction,
+            reference(code, source, index)
+        ));
+    }
+    out
+}
+
+// the bytecode as an assembler listing, one op per row with its address, the instruction,
+// where it jumps, calls or points to as a label and offset, and the line it came from, e.g.
+//   addr  instruction              target      line  source
+//   fib:
+//      4  dup                                     8  dup
+//      5  jump_if_zero 9           fib+5          9  if
+pub fn listing(code: &Bytecode, source: &Source) -> String {
+    let width = code.ops.len().max(1).to_string().len().max(4);
+    let target_width = code
+        .ops
+        .iter()
+        .filter_map(|&op| target(code, op))
+        .map(|target| target.len())
+        .max()
+        .unwrap_or(0)
+        .max("target".len());
+    let mut out = format!(
+        "{:>width$}  {:<INSTRUCTION_WIDTH$} {:<target_width$}  line  source\n",
+        "addr", "instruction", "target"
+    );
+    let mut labels = code.labels.iter().peekable();
+    for (index, &op) in code.ops.iter().enumerate() {
+        while let Some((label, _)) = labels.next_if(|&&(_, start)| start == index) {
+            out.push_str(&format!("{}:\n", label_name(label)));
+        }
+        let line_number = code.positions[index].0;
+        let row = format!(
+            "{:>width$}  {:<INSTRUCTION_WIDTH$} {:<target_width$}  {:>4}  {}",
+            index,
+            op.to_string(),
+            target(code, op).unwrap_or_default(),
+            line_number,
+            source.line(line_number).map_or("", str::trim)
+        );
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+    for (label, _) in labels {
+        out.push_str(&format!("{}:\n", label_name(label)));
+    }
+    out
+}
+
+// where an op jumps to as the subroutine and the offset in it, `end` past the last op, or
+// what it calls or points to
+fn target(code: &Bytecode, op: Op) -> Option<String> {
+    match op {
+        Op::Jump(target)
+        | Op::JumpIfZero(target)
+        | Op::Of(target)
+        | Op::Try(target)
+        | Op::Catch(target) => {
+            let target = target as usize;
+            if target >= code.ops.len() {
+                return Some("end".to_string());
+            }
+            Some(match code.enclosing_label(target) {
+                Some(label) => {
+                    let start = code.label_index(label)?;
+                    format!("{}+{}", label_name(label), target - start)
+                }
+                None => target.to_string(),
+            })
+        }
+        _ => callee(code, op),
+    }
+}
+
+// the parsed words of a program with their positions, one per line
+pub fn dump_tokens(tokens: &[AnnotatedToken]) -> String {
+    tokens
+        .iter()
+        .map(|annotated_token| {
+            format!(
+                "{:>4}:{:<4} {}\n",
+                annotated_token.line_number, annotated_token.column, annotated_token.token
+            )
+        })
+        .collect()
+}
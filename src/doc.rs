@@ -0,0 +1,112 @@
+use crate::interpreter::{label_name, Program};
+use crate::test_runner::is_test;
+
+// a label and the comment lines right above it: a line like `( a b -- sum )` is its stack
+// effect, the others describe it, with empty comment lines between paragraphs
+struct Word {
+    name: String,
+    line_number: usize,
+    effect: Option<String>,
+    paragraphs: Vec<String>,
+}
+
+// every label but the tests, in the order they're defined
+fn words(program: &Program) -> Vec<Word> {
+    let mut words: Vec<Word> = program
+        .labels()
+        .filter(|(label, _)| !is_test(label))
+        .map(|(label, _)| {
+            let line_number = program.label_line(label).unwrap_or(0);
+            let mut comments = Vec::new();
+            let mut above = line_number.saturating_sub(1);
+            while let Some(comment) = program
+                .source
+                .line(above)
+                .and_then(|line| line.trim().strip_prefix('#'))
+            {
+                comments.push(comment.strip_prefix(' ').unwrap_or(comment).trim_end());
+                above -= 1;
+            }
+            comments.reverse();
+
+            let mut effect = None;
+            let mut paragraphs = vec![String::new()];
+            for comment in comments {
+                if comment.starts_with('(') && comment.ends_with(')') && comment.contains("--") {
+                    effect = Some(comment.to_string());
+                } else if comment.is_empty() {
+                    paragraphs.push(String::new());
+                } else {
+                    let paragraph = paragraphs.last_mut().unwrap();
+                    if !paragraph.is_empty() {
+                        paragraph.push(' ');
+                    }
+                    paragraph.push_str(comment);
+                }
+            }
+            paragraphs.retain(|paragraph| !paragraph.is_empty());
+            Word {
+                name: label_name(label),
+                line_number,
+                effect,
+                paragraphs,
+            }
+        })
+        .collect();
+    words.sort_by_key(|word| word.line_number);
+    words
+}
+
+// a heading with the name of the program and a section for every word, e.g.
+//   # factorial.5th
+//
+//   ## `fact`
+//   `( n -- n! )`
+//
+//   The factorial of n.
+//
+//   Defined on line 8.
+pub fn markdown(title: &str, program: &Program) -> String {
+    let mut out = format!("# {}\n", title);
+    for word in words(program) {
+        out.push_str(&format!("\n## `{}`\n", word.name));
+        if let Some(effect) = &word.effect {
+            out.push_str(&format!("`{}`\n", effect));
+        }
+        for paragraph in &word.paragraphs {
+            out.push_str(&format!("\n{}\n", paragraph));
+        }
+        out.push_str(&format!("\nDefined on line {}.\n", word.line_number));
+    }
+    out
+}
+
+// the same as markdown(), as a page of its own
+pub fn html(title: &str, program: &Program) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n",
+        escape(title)
+    );
+    for word in words(program) {
+        out.push_str(&format!(
+            "<h2 id=\"{0}\"><code>{0}</code></h2>\n",
+            escape(&word.name)
+        ));
+        if let Some(effect) = &word.effect {
+            out.push_str(&format!("<p><code>{}</code></p>\n", escape(effect)));
+        }
+        for paragraph in &word.paragraphs {
+            out.push_str(&format!("<p>{}</p>\n", escape(paragraph)));
+        }
+        out.push_str(&format!("<p>Defined on line {}.</p>\n", word.line_number));
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,47 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+// why ENV failed; the program gets its code as the status, which is 0 on success. The
+// codes are the ones of the file words for the same failures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvError {
+    // the program has no environment (the fifth command needs --allow-env)
+    Disabled = 1,
+    NotSet = 2,
+    // the name is empty or not UTF-8
+    BadName = 7,
+}
+
+impl EnvError {
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnvError::Disabled => write!(f, "The environment is disabled"),
+            EnvError::NotSet => write!(f, "Variable not set"),
+            EnvError::BadName => write!(f, "Bad variable name"),
+        }
+    }
+}
+
+// where ENV reads variables. Like the file system, a program has none until it's given
+// one
+pub trait Environment {
+    // the bytes of the variable's value, or None if it isn't set
+    fn var(&self, name: &str) -> Option<Vec<u8>>;
+}
+
+// the environment of the process
+#[cfg(feature = "std")]
+pub struct StdEnvironment;
+
+#[cfg(feature = "std")]
+impl Environment for StdEnvironment {
+    fn var(&self, name: &str) -> Option<Vec<u8>> {
+        std::env::var_os(name).map(|value| value.into_encoded_bytes())
+    }
+}
@@ -0,0 +1,31 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::interpreter::RuntimeError;
+
+// what a TRY leaves behind until its CATCH: where to continue on an error, and how much of
+// the stack and the call stack to keep
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Handler {
+    // the op after the CATCH
+    pub catch: usize,
+    pub depth: usize,
+    pub calls: usize,
+}
+
+// the value a handler gets for an error it catches: the exit code the error stops the
+// fifth command with, or what THROW popped. Errors in the program itself (invalid labels,
+// returns without a call, unclosed IFs), limits, task errors and failed output can't be
+// caught
+pub fn code(error: &RuntimeError) -> Option<u8> {
+    match error {
+        RuntimeError::StackOverflow(_) => Some(3),
+        RuntimeError::StackUnderflow(_) => Some(4),
+        RuntimeError::AssertionFailed(_) => Some(8),
+        RuntimeError::HostWordFailed(_, _) => Some(9),
+        RuntimeError::Thrown(_, code) => Some(*code),
+        RuntimeError::OutOfMemory(_) => Some(13),
+        _ => None,
+    }
+}
@@ -1,11 +1,96 @@
-use std::fs::File;
+use std::cell::RefCell;
+use std::fs::{self, File};
 use std::io;
-use std::io::Read;
-use std::path::Path;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use fifth::graphics::{Framebuffer, Screen};
 
 pub fn read_file_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
-    let mut file = File::open(path)?;
     let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    open_input(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    open_input(path)?.read_to_end(&mut contents)?;
     Ok(contents)
 }
+
+// "-" stands for standard input
+pub fn open_input<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read>> {
+    if path.as_ref() == Path::new("-") {
+        Ok(Box::new(io::stdin()))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+pub fn write_string_to_file<P: AsRef<Path>>(path: P, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+pub fn write_bytes_to_file<P: AsRef<Path>>(path: P, contents: &[u8]) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+// program output collected in memory, for the debug adapter to forward as output events
+// (stdout carries the protocol) and for golden tests to compare
+#[derive(Clone, Default)]
+pub struct SharedBuffer(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// set once standard output turned out to be a pipe whose reader exited, e.g. `| head`
+pub static BROKEN_PIPE: AtomicBool = AtomicBool::new(false);
+
+// a program's standard output, buffered like the one it has by default, noting a broken
+// pipe so that the fifth command can stop quietly rather than report an error
+pub struct Stdout(BufWriter<io::Stdout>);
+
+impl Stdout {
+    pub fn new() -> Self {
+        BROKEN_PIPE.store(false, Ordering::Relaxed);
+        Stdout(BufWriter::new(io::stdout()))
+    }
+}
+
+fn note_broken_pipe(err: &io::Error) {
+    if err.kind() == io::ErrorKind::BrokenPipe {
+        BROKEN_PIPE.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).inspect_err(note_broken_pipe)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().inspect_err(note_broken_pipe)
+    }
+}
+
+// the screen of the fifth command: every frame GFX_FLUSH shows replaces the last one in a
+// PPM file
+pub struct PpmFile(pub PathBuf);
+
+impl Screen for PpmFile {
+    fn show(&mut self, frame: &Framebuffer) -> Result<bool, String> {
+        write_bytes_to_file(&self.0, &frame.to_ppm())
+            .map(|()| true)
+            .map_err(|err| format!("Can't write {}: {}", self.0.display(), err))
+    }
+}
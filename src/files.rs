@@ -0,0 +1,157 @@
+use alloc::boxed::Box;
+use core::fmt;
+
+// file handles are bytes on the stack, so a program can't have more files open than this
+pub const MAX_FILES: usize = 256;
+
+// what FOPEN opens a file for, from the byte it pops
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Read,
+    // creates the file, or empties it if it exists
+    Write,
+    // creates the file, or writes after its end if it exists
+    Append,
+}
+
+impl Mode {
+    pub fn from_byte(byte: u8) -> Option<Mode> {
+        match byte {
+            0 => Some(Mode::Read),
+            1 => Some(Mode::Write),
+            2 => Some(Mode::Append),
+            _ => None,
+        }
+    }
+}
+
+// why a file word failed; the program gets its code as the status, which is 0 on success
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileError {
+    // the program has no file system (the fifth command needs --allow-fs)
+    Disabled = 1,
+    NotFound,
+    PermissionDenied,
+    // FREAD got to the end of the file
+    EndOfFile,
+    // not an open file, or one opened for writing to FREAD or for reading to FWRITE
+    BadHandle,
+    TooManyFiles,
+    // the name is empty or not UTF-8
+    BadName,
+    BadMode,
+    Io,
+}
+
+impl FileError {
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::Disabled => write!(f, "Files are disabled"),
+            FileError::NotFound => write!(f, "File not found"),
+            FileError::PermissionDenied => write!(f, "Permission denied"),
+            FileError::EndOfFile => write!(f, "End of file"),
+            FileError::BadHandle => write!(f, "Bad file handle"),
+            FileError::TooManyFiles => write!(f, "Too many open files"),
+            FileError::BadName => write!(f, "Bad file name"),
+            FileError::BadMode => write!(f, "Bad file mode"),
+            FileError::Io => write!(f, "I/O error"),
+        }
+    }
+}
+
+// where FOPEN opens files. A program has none until it's given one, so it can't touch
+// files unless its host allows it
+pub trait FileSystem {
+    fn open(&mut self, name: &str, mode: Mode) -> Result<Box<dyn File>, FileError>;
+}
+
+// a file FOPEN opened; reading one opened for writing, or the other way around, fails with
+// BadHandle
+pub trait File {
+    // None at the end of the file
+    fn read_byte(&mut self) -> Result<Option<u8>, FileError>;
+    fn write_byte(&mut self, byte: u8) -> Result<(), FileError>;
+    // writes out what's buffered
+    fn close(&mut self) -> Result<(), FileError>;
+}
+
+// the files of the operating system, with names relative to the working directory
+#[cfg(feature = "std")]
+pub struct StdFileSystem;
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for FileError {
+    fn from(error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => FileError::NotFound,
+            std::io::ErrorKind::PermissionDenied => FileError::PermissionDenied,
+            _ => FileError::Io,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl FileSystem for StdFileSystem {
+    fn open(&mut self, name: &str, mode: Mode) -> Result<Box<dyn File>, FileError> {
+        use std::fs::OpenOptions;
+        use std::io::{BufReader, BufWriter};
+
+        let mut options = OpenOptions::new();
+        match mode {
+            Mode::Read => options.read(true),
+            Mode::Write => options.write(true).create(true).truncate(true),
+            Mode::Append => options.append(true).create(true),
+        };
+        let file = options.open(name)?;
+        Ok(match mode {
+            Mode::Read => Box::new(BufReader::new(file)),
+            Mode::Write | Mode::Append => Box::new(BufWriter::new(file)),
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl File for std::io::BufReader<std::fs::File> {
+    fn read_byte(&mut self) -> Result<Option<u8>, FileError> {
+        use std::io::Read;
+
+        let mut buffer = [0u8];
+        match self.read(&mut buffer)? {
+            0 => Ok(None),
+            _ => Ok(Some(buffer[0])),
+        }
+    }
+
+    fn write_byte(&mut self, _: u8) -> Result<(), FileError> {
+        Err(FileError::BadHandle)
+    }
+
+    fn close(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl File for std::io::BufWriter<std::fs::File> {
+    fn read_byte(&mut self) -> Result<Option<u8>, FileError> {
+        Err(FileError::BadHandle)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), FileError> {
+        use std::io::Write;
+
+        Ok(self.write_all(&[byte])?)
+    }
+
+    fn close(&mut self) -> Result<(), FileError> {
+        use std::io::Write;
+
+        Ok(self.flush()?)
+    }
+}
@@ -0,0 +1,210 @@
+use crate::interpreter::{is_keyword, Diagnostics, Program};
+
+const INDENT: &str = "  ";
+
+// one line of output: indentation level, words and an optional trailing comment
+struct Line<'a> {
+    level: usize,
+    words: Vec<String>,
+    comment: Option<&'a str>,
+}
+
+// splits a line into its words and its comment, the same way Program::parse does
+fn split_line(line: &str) -> (Vec<&str>, Option<&str>) {
+    let mut words = Vec::new();
+    for word in line.split_whitespace() {
+        if word.starts_with('#') {
+            let start = word.as_ptr() as usize - line.as_ptr() as usize;
+            return (words, Some(line[start..].trim_end()));
+        }
+        words.push(word);
+    }
+    (words, None)
+}
+
+// breaks the words of a line in front of every label definition, so each label gets its own
+// line; a DATA block gets its own line too, since its name isn't a label and its values run
+// to the end of the line
+fn segments(words: Vec<&str>) -> Vec<Vec<&str>> {
+    let mut segments: Vec<Vec<&str>> = Vec::new();
+    for word in words {
+        match segments.last_mut() {
+            _ if word.eq_ignore_ascii_case("data") => segments.push(vec![word]),
+            Some(segment) if segment[0].eq_ignore_ascii_case("data") => segment.push(word),
+            Some(segment) if !word.ends_with(':') && !segment[0].ends_with(':') => {
+                segment.push(word)
+            }
+            _ => segments.push(vec![word]),
+        }
+    }
+    segments
+}
+
+// keywords are written in lowercase, and so are labels unless the program is
+// case-sensitive, where `Foo` and `foo` are different labels
+fn word_case(word: &str, case_sensitive: bool) -> String {
+    match case_sensitive && !is_keyword(word) {
+        true => word.to_string(),
+        false => word.to_lowercase(),
+    }
+}
+
+fn layout(source: &str, case_sensitive: bool) -> Vec<Option<Line<'_>>> {
+    // None stands for a blank line
+    let mut lines: Vec<Option<Line>> = Vec::new();
+    let mut depth = 0;
+    let mut in_label = false;
+    for source_line in source.lines() {
+        let (words, comment) = split_line(source_line);
+        if words.is_empty() {
+            match comment {
+                Some(comment) => lines.push(Some(Line {
+                    level: usize::from(in_label) + depth,
+                    words: Vec::new(),
+                    comment: Some(comment),
+                })),
+                None => lines.push(None),
+            }
+            continue;
+        }
+        let segments = segments(words);
+        let last = segments.len() - 1;
+        for (i, segment) in segments.into_iter().enumerate() {
+            let words: Vec<String> = segment
+                .iter()
+                .map(|word| word_case(word, case_sensitive))
+                .collect();
+            let level = if words[0].ends_with(':') {
+                in_label = true;
+                0
+            } else {
+                // ELSE and THEN line up with their IF, CATCH and ENDTRY with their TRY,
+                // ENDOF with its OF and ENDCASE with its CASE, and a RETURN outside of any
+                // IF closes the subroutine and lines up with its label
+                let dedent = match words[0].as_str() {
+                    "else" | "then" | "catch" | "endtry" | "endof" | "endcase" => 1,
+                    "return" if depth == 0 => usize::from(in_label),
+                    _ => 0,
+                };
+                let level = (usize::from(in_label) + depth).saturating_sub(dedent);
+                for word in &words {
+                    match word.as_str() {
+                        "if" | "try" | "case" | "of" => depth += 1,
+                        "then" | "endtry" | "endof" | "endcase" => depth = depth.saturating_sub(1),
+                        "return" if depth == 0 => in_label = false,
+                        _ => (),
+                    }
+                }
+                level
+            };
+            lines.push(Some(Line {
+                level,
+                words,
+                comment: if i == last { comment } else { None },
+            }));
+        }
+    }
+    lines
+}
+
+// renders the lines, aligning the trailing comments of consecutive lines
+fn render(lines: &[Option<Line>]) -> String {
+    let code = |line: &Line| format!("{}{}", INDENT.repeat(line.level), line.words.join(" "));
+    let mut out = String::new();
+    let mut previous_blank = true;
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(line) = &lines[i] else {
+            if !previous_blank {
+                out.push('\n');
+            }
+            previous_blank = true;
+            i += 1;
+            continue;
+        };
+        previous_blank = false;
+        match (line.words.is_empty(), line.comment) {
+            (true, comment) => {
+                let comment = comment.unwrap_or_default();
+                out.push_str(&format!("{}{}\n", INDENT.repeat(line.level), comment));
+                i += 1;
+                continue;
+            }
+            (false, None) => {
+                out.push_str(&format!("{}\n", code(line)));
+                i += 1;
+                continue;
+            }
+            (false, Some(_)) => (),
+        }
+        // a group of consecutive code lines with trailing comments
+        let group: Vec<&Line> = lines[i..]
+            .iter()
+            .map_while(|line| match line {
+                Some(line) if !line.words.is_empty() && line.comment.is_some() => Some(line),
+                _ => None,
+            })
+            .collect();
+        let width = group
+            .iter()
+            .map(|line| code(line).chars().count())
+            .max()
+            .unwrap_or(0);
+        for line in &group {
+            let text = code(line);
+            let padding = width - text.chars().count() + 1;
+            out.push_str(&format!(
+                "{}{}{}\n",
+                text,
+                " ".repeat(padding),
+                line.comment.unwrap()
+            ));
+        }
+        i += group.len();
+    }
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+    out
+}
+
+// the parsed instructions and labels, to make sure formatting didn't change the program
+fn fingerprint(program: &Program) -> (Vec<String>, Vec<(String, usize)>) {
+    let tokens = program
+        .tokens
+        .iter()
+        .map(|annotated_token| annotated_token.token.to_string())
+        .collect();
+    let mut labels: Vec<(String, usize)> = program
+        .labels()
+        .map(|(label, index)| (label.to_string(), index))
+        .collect();
+    labels.sort();
+    (tokens, labels)
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Parse(Diagnostics),
+    // the formatted program parses differently than the original, which is a bug
+    Changed,
+}
+
+// the canonical formatting of a program: lowercase words (but for the labels of a
+// case-sensitive program), subroutine bodies and IF/ELSE/THEN bodies indented by two spaces,
+// labels and their closing RETURN on their own line at the left margin and aligned trailing
+// comments
+pub fn format(source: &str, case_sensitive: bool) -> Result<String, FormatError> {
+    let mut original = Program::new(source, 0);
+    original.set_case_sensitive(case_sensitive);
+    original.parse().map_err(FormatError::Parse)?;
+
+    let formatted = render(&layout(source, case_sensitive));
+
+    let mut reparsed = Program::new(&formatted, 0);
+    reparsed.set_case_sensitive(case_sensitive);
+    if reparsed.parse().is_err() || fingerprint(&original) != fingerprint(&reparsed) {
+        return Err(FormatError::Changed);
+    }
+    Ok(formatted)
+}
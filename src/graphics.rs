@@ -0,0 +1,98 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// the picture GFX_INIT starts and PIXEL draws on, one gray level (0 black to 255 white)
+// per pixel, row by row from the top left
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    // all black
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    // pixels outside of the frame aren't drawn
+    pub fn set(&mut self, x: usize, y: usize, value: u8) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = value;
+        }
+    }
+
+    // a binary PPM image of the frame
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut bytes = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for &value in &self.pixels {
+            bytes.extend([value; 3]);
+        }
+        bytes
+    }
+}
+
+// where GFX_FLUSH shows the frame; a program without one doesn't show it anywhere
+pub trait Screen {
+    // false once the screen is gone, like a window that was closed, which halts the program;
+    // the error stops it
+    fn show(&mut self, frame: &Framebuffer) -> Result<bool, String>;
+}
+
+// a window showing the frame four times as large. It stays open after the program ends,
+// until it's closed, so the last frame can be looked at
+#[cfg(feature = "window")]
+#[derive(Default)]
+pub struct Window {
+    window: Option<minifb::Window>,
+}
+
+#[cfg(feature = "window")]
+impl Screen for Window {
+    fn show(&mut self, frame: &Framebuffer) -> Result<bool, String> {
+        let resized = self
+            .window
+            .as_ref()
+            .is_none_or(|window| window.get_size() != (frame.width * 4, frame.height * 4));
+        if resized {
+            let options = minifb::WindowOptions {
+                scale: minifb::Scale::X4,
+                ..minifb::WindowOptions::default()
+            };
+            let window = minifb::Window::new("fifth", frame.width, frame.height, options)
+                .map_err(|err| err.to_string())?;
+            self.window = Some(window);
+        }
+        let window = self.window.as_mut().unwrap();
+        if !window.is_open() {
+            return Ok(false);
+        }
+        let buffer: Vec<u32> = frame
+            .pixels
+            .iter()
+            .map(|&value| u32::from_be_bytes([0, value, value, value]))
+            .collect();
+        window
+            .update_with_buffer(&buffer, frame.width, frame.height)
+            .map_err(|err| err.to_string())?;
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "window")]
+impl Drop for Window {
+    fn drop(&mut self) {
+        if let Some(window) = &mut self.window {
+            while window.is_open() {
+                window.update();
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use tracing::{debug_span, trace, Span};
+
+use crate::bytecode::Op;
+use crate::interpreter::{label_name, ExecutionObserver, Program};
+
+// reports a run to the tracing crate, for embedders that already collect its spans and
+// events: a `task` span for every spawned task and a `call` span for every subroutine call,
+// open until the task ends or the call returns (at debug level), and a `step` event before
+// every instruction (at trace level). Parsing and running have spans of their own (at info
+// level) whether this is set or not, e.g.
+//   program.set_observer(Box::new(Tracer::default()));
+//   program.run()?;
+#[derive(Default)]
+pub struct Tracer {
+    tasks: BTreeMap<usize, Spans>,
+    // the task whose spans are entered
+    task: usize,
+}
+
+// the spans of a task: its own if it was spawned, then one for every call that hasn't
+// returned, outermost first
+struct Spans {
+    task: Option<Span>,
+    calls: Vec<Span>,
+}
+
+impl Spans {
+    fn iter(&self) -> impl DoubleEndedIterator<Item = &Span> {
+        self.task.iter().chain(&self.calls)
+    }
+}
+
+fn enter(span: &Span) {
+    span.with_subscriber(|(id, dispatch)| dispatch.enter(id));
+}
+
+fn exit(span: &Span) {
+    span.with_subscriber(|(id, dispatch)| dispatch.exit(id));
+}
+
+// the calls of the running task that haven't returned; a spawned task's first frame returns
+// to the end of the task
+fn calls(program: &Program) -> usize {
+    match program.task() {
+        0 => program.call_depth(),
+        _ => program.call_depth().saturating_sub(1),
+    }
+}
+
+impl Tracer {
+    // the spans of the running task, entered in place of the ones of the task that ran
+    // before, or new ones for a task that hasn't run yet
+    fn spans(&mut self, program: &Program) -> &mut Spans {
+        let task = program.task();
+        if task != self.task {
+            if let Some(spans) = self.tasks.get(&self.task) {
+                spans.iter().rev().for_each(exit);
+            }
+            if let Some(spans) = self.tasks.get(&task) {
+                spans.iter().for_each(enter);
+            }
+            self.task = task;
+        }
+        self.tasks.entry(task).or_insert_with(|| {
+            let span = (task != 0).then(|| {
+                debug_span!(
+                    "task",
+                    id = task,
+                    label = program
+                        .code
+                        .enclosing_label(program.pc)
+                        .map(label_name)
+                        .as_deref()
+                )
+            });
+            span.iter().for_each(enter);
+            Spans {
+                task: span,
+                calls: Vec::new(),
+            }
+        })
+    }
+
+    // exits the spans of the calls past the first `count`, which a RETURN, an exception or
+    // a tail call ended
+    fn truncate(&mut self, program: &Program, count: usize) {
+        let calls = &mut self.spans(program).calls;
+        for span in calls.drain(count.min(calls.len())..).rev() {
+            exit(&span);
+        }
+    }
+}
+
+impl ExecutionObserver for Tracer {
+    fn on_step(&mut self, program: &Program, index: usize, op: Op) {
+        let count = calls(program);
+        self.truncate(program, count);
+        // calls made before the tracer was set have no label
+        let calls = &mut self.spans(program).calls;
+        while calls.len() < count {
+            let span = debug_span!("call");
+            enter(&span);
+            calls.push(span);
+        }
+        let task = program.task();
+        trace!(
+            task,
+            pc = index,
+            op = %op,
+            line = program.code.token(index).line_number,
+            size = program.stack.len(),
+            "step"
+        );
+        // the RETURN that ends a spawned task, after which another task runs
+        if op == Op::Return && task != 0 && program.call_depth() == 1 {
+            if let Some(spans) = self.tasks.remove(&task) {
+                spans.iter().rev().for_each(exit);
+            }
+        }
+    }
+
+    fn on_call(&mut self, program: &Program, target: usize) {
+        // a tail call's frame is its caller's
+        self.truncate(program, calls(program).saturating_sub(1));
+        let span = debug_span!(
+            "call",
+            label = program
+                .code
+                .enclosing_label(target)
+                .map(label_name)
+                .as_deref()
+        );
+        enter(&span);
+        self.spans(program).calls.push(span);
+    }
+
+    fn on_return(&mut self, program: &Program) {
+        self.truncate(program, calls(program));
+    }
+}
@@ -1,4 +1,36 @@
-use std::collections::HashMap;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt::{self, Write as _};
+use core::iter::Peekable;
+use core::mem;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::io;
+
+use crate::bytecode::{self, Bytecode, Op, MAX_ADDRESSES, UNCLOSED};
+use crate::clock::Clock;
+#[cfg(feature = "std")]
+use crate::clock::SystemClock;
+#[cfg(not(feature = "std"))]
+use crate::clock::VirtualClock;
+use crate::environment::{EnvError, Environment};
+use crate::exceptions::{self, Handler};
+use crate::files::{File, FileError, FileSystem, Mode, MAX_FILES};
+use crate::graphics::{Framebuffer, Screen};
+use crate::limits::{Limit, Limits, Usage};
+use crate::net::{Connection, NetError, Network, Socket, MAX_SOCKETS};
+use crate::optimizer;
+use crate::rng::Rng;
+use crate::snapshot::{self, Snapshot, SnapshotError};
+use crate::source::Source;
+use crate::tasks::{Task, TaskError, CHANNEL_CAPACITY, MAX_CHANNELS, MAX_TASKS, TASK_END};
+use crate::terminal::{self, Keyboard};
+
+// memory cells are addressed by a byte on the stack
+pub const MEMORY_SIZE: usize = 256;
 
 #[derive(Debug, Clone)]
 pub enum Token {
@@ -8,41 +40,236 @@ pub enum Token {
     Swap,
     Rotate,
     Over,
-    Pick(usize),
+    Nip,
+    Tuck,
+    Pick(Option<usize>),
+    Roll(Option<usize>),
     BinOp(BinOp),
+    Random,
+    Assert,
+    AssertEq,
     PrintByte,
     PrintChar,
+    PrintHex,
+    PrintUtf8,
+    EprintByte,
+    EprintChar,
+    PrintNewline,
+    PrintSpace,
+    PrintStack,
+    ReadChar,
+    ReadLine,
+    ParseNum,
+    Eof,
+    Sleep,
+    Ticks,
+    Fopen,
+    Fread,
+    Fwrite,
+    Fclose,
+    Key,
+    KeyPoll,
+    ClearScreen,
+    CursorAt,
+    TcpListen,
+    TcpAccept,
+    TcpConnect,
+    TcpSend,
+    TcpRecv,
+    TcpClose,
+    GfxInit,
+    Pixel,
+    GfxFlush,
+    Env,
+    Fetch,
+    Store,
+    Allot,
+    FetchAt,
+    StoreAt,
     If,
     Else,
     Then,
+    Case,
+    Of,
+    EndOf,
+    EndCase,
     Call(String),
+    PushAddr(String),
+    Execute,
+    Spawn(String),
+    Yield,
+    Join,
+    ChanNew,
+    Send,
+    Recv,
+    Try,
+    Catch,
+    EndTry,
+    Throw,
     Return,
     Halt,
+    Exit,
 }
 
 impl Token {
-    pub fn to_string(&self) -> String {
-        match self {
+    // how many bytes the token pops and pushes; None for calls (and EXECUTE), whose effect
+    // depends on the callee
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        let effect = match self {
+            Token::Push(_)
+            | Token::Random
+            | Token::ReadChar
+            | Token::Eof
+            | Token::Key
+            | Token::PushAddr(_)
+            | Token::Spawn(_)
+            | Token::ChanNew => (0, 1),
+            Token::Pop
+            | Token::Assert
+            | Token::PrintByte
+            | Token::PrintChar
+            | Token::PrintHex
+            | Token::PrintUtf8
+            | Token::EprintByte
+            | Token::EprintChar
+            | Token::Sleep
+            | Token::Throw
+            | Token::EndCase
+            | Token::Exit => (1, 0),
+            Token::Dup => (1, 2),
+            Token::Ticks => (0, 2),
+            Token::Fopen => (3, 2),
+            Token::Fread => (1, 2),
+            Token::Fwrite => (2, 1),
+            Token::Fclose => (1, 1),
+            Token::KeyPoll => (0, 2),
+            Token::CursorAt | Token::GfxInit => (2, 0),
+            Token::TcpListen => (2, 2),
+            Token::TcpAccept | Token::TcpRecv => (1, 2),
+            Token::TcpConnect | Token::Env => (4, 2),
+            Token::TcpSend => (2, 1),
+            Token::TcpClose => (1, 1),
+            Token::Swap => (2, 2),
+            Token::Rotate => (3, 3),
+            Token::Over | Token::Tuck => (2, 3),
+            // OF pops the selector too if it matches
+            Token::Nip | Token::BinOp(_) | Token::Of | Token::FetchAt => (2, 1),
+            Token::Pick(Some(n)) => (n.saturating_add(1), n.saturating_add(2)),
+            // the index is only known at runtime, so only the popped index is accounted for
+            Token::Pick(None) => (1, 1),
+            Token::Roll(Some(n)) => (*n, *n),
+            Token::Roll(None) => (1, 0),
+            Token::AssertEq | Token::Send | Token::Store => (2, 0),
+            Token::If | Token::Join | Token::Recv | Token::Fetch | Token::Allot => (1, 1),
+            Token::StoreAt | Token::Pixel => (3, 0),
+            Token::ReadLine => (2, 1),
+            Token::ParseNum => (2, 2),
+            Token::Else
+            | Token::Then
+            | Token::Case
+            | Token::EndOf
+            | Token::PrintNewline
+            | Token::PrintSpace
+            | Token::PrintStack
+            | Token::ClearScreen
+            | Token::GfxFlush
+            | Token::Yield
+            | Token::Try
+            | Token::Catch
+            | Token::EndTry
+            | Token::Return
+            | Token::Halt => (0, 0),
+            Token::Call(_) | Token::Execute => return None,
+        };
+        Some(effect)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
             Token::Push(n) => format!("push {}", n),
             Token::Pop => "pop".to_string(),
             Token::Dup => "dup".to_string(),
             Token::Swap => "swap".to_string(),
             Token::Rotate => "rotate".to_string(),
             Token::Over => "over".to_string(),
-            Token::Pick(n) => format!("pick {}", n),
+            Token::Nip => "nip".to_string(),
+            Token::Tuck => "tuck".to_string(),
+            Token::Pick(Some(n)) => format!("pick {}", n),
+            Token::Pick(None) => "pick".to_string(),
+            Token::Roll(Some(n)) => format!("roll {}", n),
+            Token::Roll(None) => "roll".to_string(),
             Token::BinOp(op) => match op {
                 BinOp::Add => "add".to_string(),
                 BinOp::Sub => "sub".to_string(),
             },
+            Token::Random => "random".to_string(),
+            Token::Assert => "assert".to_string(),
+            Token::AssertEq => "assert_eq".to_string(),
             Token::PrintByte => "print_byte".to_string(),
             Token::PrintChar => "print_char".to_string(),
+            Token::PrintHex => "print_hex".to_string(),
+            Token::PrintUtf8 => "print_utf8".to_string(),
+            Token::EprintByte => "eprint_byte".to_string(),
+            Token::EprintChar => "eprint_char".to_string(),
+            Token::PrintNewline => "print_newline".to_string(),
+            Token::PrintSpace => "space".to_string(),
+            Token::PrintStack => "print_stack".to_string(),
+            Token::ReadChar => "read_char".to_string(),
+            Token::ReadLine => "read_line".to_string(),
+            Token::ParseNum => "parse_num".to_string(),
+            Token::Eof => "eof".to_string(),
+            Token::Sleep => "sleep".to_string(),
+            Token::Ticks => "ticks".to_string(),
+            Token::Fopen => "fopen".to_string(),
+            Token::Fread => "fread".to_string(),
+            Token::Fwrite => "fwrite".to_string(),
+            Token::Fclose => "fclose".to_string(),
+            Token::Key => "key".to_string(),
+            Token::KeyPoll => "key?".to_string(),
+            Token::ClearScreen => "clear_screen".to_string(),
+            Token::CursorAt => "cursor_at".to_string(),
+            Token::TcpListen => "tcp_listen".to_string(),
+            Token::TcpAccept => "tcp_accept".to_string(),
+            Token::TcpConnect => "tcp_connect".to_string(),
+            Token::TcpSend => "tcp_send".to_string(),
+            Token::TcpRecv => "tcp_recv".to_string(),
+            Token::TcpClose => "tcp_close".to_string(),
+            Token::GfxInit => "gfx_init".to_string(),
+            Token::Pixel => "pixel".to_string(),
+            Token::GfxFlush => "gfx_flush".to_string(),
+            Token::Env => "env".to_string(),
+            Token::Fetch => "fetch".to_string(),
+            Token::Store => "store".to_string(),
+            Token::Allot => "allot".to_string(),
+            Token::FetchAt => "fetch_at".to_string(),
+            Token::StoreAt => "store_at".to_string(),
             Token::If => "if".to_string(),
             Token::Else => "else".to_string(),
             Token::Then => "then".to_string(),
-            Token::Call(label) => label.to_lowercase(),
+            Token::Case => "case".to_string(),
+            Token::Of => "of".to_string(),
+            Token::EndOf => "endof".to_string(),
+            Token::EndCase => "endcase".to_string(),
+            Token::Call(label) => label_name(label),
+            Token::PushAddr(label) => format!("push_addr {}", label_name(label)),
+            Token::Execute => "execute".to_string(),
+            Token::Spawn(label) => format!("spawn {}", label_name(label)),
+            Token::Yield => "yield".to_string(),
+            Token::Join => "join".to_string(),
+            Token::ChanNew => "chan_new".to_string(),
+            Token::Send => "send".to_string(),
+            Token::Recv => "recv".to_string(),
+            Token::Try => "try".to_string(),
+            Token::Catch => "catch".to_string(),
+            Token::EndTry => "endtry".to_string(),
+            Token::Throw => "throw".to_string(),
             Token::Return => "return".to_string(),
             Token::Halt => "halt".to_string(),
-        }
+            Token::Exit => "exit".to_string(),
+        };
+        write!(f, "{}", text)
     }
 }
 
@@ -56,6 +283,7 @@ pub enum BinOp {
 pub struct AnnotatedToken {
     pub token: Token,
     pub line_number: usize,
+    pub column: usize,
 }
 
 #[derive(Debug)]
@@ -65,334 +293,2605 @@ pub enum RuntimeError {
     InvalidLabel(AnnotatedToken),
     CallStackUnderflow(AnnotatedToken),
     UnclosedIfStatement(AnnotatedToken),
+    AssertionFailed(AnnotatedToken),
+    // the message a host word failed with
+    HostWordFailed(AnnotatedToken, String),
+    // the token is the one that would have gone over the limit, and didn't run
+    LimitExceeded(AnnotatedToken, Limit),
+    TaskFailed(AnnotatedToken, TaskError),
+    // a THROW no handler caught, with the value it popped
+    Thrown(AnnotatedToken, u8),
+    // an ALLOT with more cells than the memory has left
+    OutOfMemory(AnnotatedToken),
+    // the output or the error output couldn't be written to, e.g. a pipe whose reader exited
+    OutputFailed(AnnotatedToken),
 }
 
-#[derive(Debug)]
+impl RuntimeError {
+    pub fn token(&self) -> &AnnotatedToken {
+        match self {
+            RuntimeError::StackOverflow(token)
+            | RuntimeError::StackUnderflow(token)
+            | RuntimeError::InvalidLabel(token)
+            | RuntimeError::CallStackUnderflow(token)
+            | RuntimeError::UnclosedIfStatement(token)
+            | RuntimeError::AssertionFailed(token)
+            | RuntimeError::HostWordFailed(token, _)
+            | RuntimeError::LimitExceeded(token, _)
+            | RuntimeError::TaskFailed(token, _)
+            | RuntimeError::Thrown(token, _)
+            | RuntimeError::OutOfMemory(token)
+            | RuntimeError::OutputFailed(token) => token,
+        }
+    }
+
+    // stable identifier for tooling
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuntimeError::StackOverflow(_) => "stack-overflow",
+            RuntimeError::StackUnderflow(_) => "stack-underflow",
+            RuntimeError::InvalidLabel(_) => "invalid-label",
+            RuntimeError::CallStackUnderflow(_) => "call-stack-underflow",
+            RuntimeError::UnclosedIfStatement(_) => "unclosed-if",
+            RuntimeError::AssertionFailed(_) => "assertion-failed",
+            RuntimeError::HostWordFailed(_, _) => "host-word-failed",
+            RuntimeError::LimitExceeded(_, _) => "limit-exceeded",
+            RuntimeError::TaskFailed(_, _) => "task-error",
+            RuntimeError::Thrown(_, _) => "uncaught-exception",
+            RuntimeError::OutOfMemory(_) => "out-of-memory",
+            RuntimeError::OutputFailed(_) => "output-failed",
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::StackOverflow(_) => write!(f, "Stack overflow"),
+            RuntimeError::StackUnderflow(_) => write!(f, "Stack underflow"),
+            RuntimeError::InvalidLabel(_) => write!(f, "Invalid label"),
+            RuntimeError::CallStackUnderflow(_) => write!(f, "Call stack underflow"),
+            RuntimeError::UnclosedIfStatement(_) => write!(f, "Unclosed IF statement"),
+            RuntimeError::AssertionFailed(_) => write!(f, "Assertion failed"),
+            RuntimeError::HostWordFailed(_, message) => write!(f, "{}", message),
+            RuntimeError::LimitExceeded(_, limit) => write!(f, "{}", limit),
+            RuntimeError::TaskFailed(_, error) => write!(f, "{}", error),
+            RuntimeError::Thrown(_, code) => write!(f, "Uncaught exception {}", code),
+            RuntimeError::OutOfMemory(_) => write!(f, "Out of memory"),
+            RuntimeError::OutputFailed(_) => write!(f, "Output failed"),
+        }
+    }
+}
+
+impl core::error::Error for RuntimeError {}
+
+#[derive(Clone, Debug)]
 pub enum ParseError {
-    InvalidArgument(String, usize),
-    MissingArgument(String, usize),
-    DuplicateLabel(String, usize),
-    InvalidCall(String, usize),
+    InvalidArgument(String, usize, usize),
+    MissingArgument(String, usize, usize),
+    DuplicateLabel(String, usize, usize),
+    ReservedLabelName(String, usize, usize),
+    InvalidCall(String, usize, usize),
     ElseWithoutIfStatement(AnnotatedToken),
     ThenWithoutIfStatement(AnnotatedToken),
     TooManyElseStatements(AnnotatedToken),
+    CatchWithoutTry(AnnotatedToken),
+    EndTryWithoutCatch(AnnotatedToken),
+    UnclosedTry(AnnotatedToken),
+    OfWithoutCase(AnnotatedToken),
+    EndOfWithoutOf(AnnotatedToken),
+    EndCaseWithoutCase(AnnotatedToken),
+    UnclosedOf(AnnotatedToken),
+    UnclosedCase(AnnotatedToken),
+    // the PUSH_ADDR that names one subroutine more than fits in a byte
+    TooManyAddresses(AnnotatedToken),
+    // a VARIABLE or DATA block with no memory left for it
+    OutOfMemory(String, usize, usize),
+}
+
+impl ParseError {
+    pub fn line_number(&self) -> usize {
+        match self {
+            ParseError::InvalidArgument(_, line, _)
+            | ParseError::MissingArgument(_, line, _)
+            | ParseError::DuplicateLabel(_, line, _)
+            | ParseError::ReservedLabelName(_, line, _)
+            | ParseError::InvalidCall(_, line, _)
+            | ParseError::OutOfMemory(_, line, _) => *line,
+            ParseError::ElseWithoutIfStatement(token)
+            | ParseError::ThenWithoutIfStatement(token)
+            | ParseError::TooManyElseStatements(token)
+            | ParseError::CatchWithoutTry(token)
+            | ParseError::EndTryWithoutCatch(token)
+            | ParseError::UnclosedTry(token)
+            | ParseError::OfWithoutCase(token)
+            | ParseError::EndOfWithoutOf(token)
+            | ParseError::EndCaseWithoutCase(token)
+            | ParseError::UnclosedOf(token)
+            | ParseError::UnclosedCase(token)
+            | ParseError::TooManyAddresses(token) => token.line_number,
+        }
+    }
+
+    // 1-based column of the offending word
+    pub fn column(&self) -> usize {
+        match self {
+            ParseError::InvalidArgument(_, _, column)
+            | ParseError::MissingArgument(_, _, column)
+            | ParseError::DuplicateLabel(_, _, column)
+            | ParseError::ReservedLabelName(_, _, column)
+            | ParseError::InvalidCall(_, _, column)
+            | ParseError::OutOfMemory(_, _, column) => *column,
+            ParseError::ElseWithoutIfStatement(token)
+            | ParseError::ThenWithoutIfStatement(token)
+            | ParseError::TooManyElseStatements(token)
+            | ParseError::CatchWithoutTry(token)
+            | ParseError::EndTryWithoutCatch(token)
+            | ParseError::UnclosedTry(token)
+            | ParseError::OfWithoutCase(token)
+            | ParseError::EndOfWithoutOf(token)
+            | ParseError::EndCaseWithoutCase(token)
+            | ParseError::UnclosedOf(token)
+            | ParseError::UnclosedCase(token)
+            | ParseError::TooManyAddresses(token) => token.column,
+        }
+    }
+
+    // stable identifier for tooling
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::InvalidArgument(..) => "invalid-argument",
+            ParseError::MissingArgument(..) => "missing-argument",
+            ParseError::DuplicateLabel(..) => "duplicate-label",
+            ParseError::ReservedLabelName(..) => "reserved-label-name",
+            ParseError::InvalidCall(..) => "undefined-label",
+            ParseError::ElseWithoutIfStatement(_) => "else-without-if",
+            ParseError::ThenWithoutIfStatement(_) => "then-without-if",
+            ParseError::TooManyElseStatements(_) => "too-many-else",
+            ParseError::CatchWithoutTry(_) => "catch-without-try",
+            ParseError::EndTryWithoutCatch(_) => "endtry-without-catch",
+            ParseError::UnclosedTry(_) => "unclosed-try",
+            ParseError::OfWithoutCase(_) => "of-without-case",
+            ParseError::EndOfWithoutOf(_) => "endof-without-of",
+            ParseError::EndCaseWithoutCase(_) => "endcase-without-case",
+            ParseError::UnclosedOf(_) => "unclosed-of",
+            ParseError::UnclosedCase(_) => "unclosed-case",
+            ParseError::TooManyAddresses(_) => "too-many-addresses",
+            ParseError::OutOfMemory(..) => "out-of-memory",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidArgument(arg, _, _) => write!(f, "Invalid argument '{}'", arg),
+            ParseError::MissingArgument(token, _, _) => {
+                write!(f, "Missing argument for '{}'", token)
+            }
+            ParseError::DuplicateLabel(label, _, _) => write!(f, "Duplicate label '{}'", label),
+            ParseError::ReservedLabelName(label, _, _) => {
+                write!(f, "Label '{}' is a reserved keyword", label)
+            }
+            ParseError::InvalidCall(label, _, _) => {
+                write!(f, "Call to undefined label '{}'", label)
+            }
+            ParseError::ElseWithoutIfStatement(_) => write!(f, "ELSE without IF"),
+            ParseError::ThenWithoutIfStatement(_) => write!(f, "THEN without IF"),
+            ParseError::TooManyElseStatements(_) => {
+                write!(f, "Multiple ELSE statements for single IF")
+            }
+            ParseError::CatchWithoutTry(_) => write!(f, "CATCH without TRY"),
+            ParseError::EndTryWithoutCatch(_) => write!(f, "ENDTRY without CATCH"),
+            ParseError::UnclosedTry(_) => write!(f, "TRY without ENDTRY"),
+            ParseError::OfWithoutCase(_) => write!(f, "OF without CASE"),
+            ParseError::EndOfWithoutOf(_) => write!(f, "ENDOF without OF"),
+            ParseError::EndCaseWithoutCase(_) => write!(f, "ENDCASE without CASE"),
+            ParseError::UnclosedOf(_) => write!(f, "OF without ENDOF"),
+            ParseError::UnclosedCase(_) => write!(f, "CASE without ENDCASE"),
+            ParseError::TooManyAddresses(_) => {
+                write!(
+                    f,
+                    "More than {} subroutines have their address taken",
+                    MAX_ADDRESSES
+                )
+            }
+            ParseError::OutOfMemory(name, _, _) => write!(
+                f,
+                "Not enough memory left for '{}', there are only {} cells",
+                name, MEMORY_SIZE
+            ),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+// turns a single word (and the arguments following it) into a token
+fn parse_token<'a, I: Iterator<Item = &'a str>>(
+    part: &str,
+    parts: &mut Peekable<I>,
+    line: &str,
+    line_number: usize,
+    case_sensitive: bool,
+) -> Result<Token, ParseError> {
+    // optional arguments are only taken if the next word looks like a number
+    let mut optional_argument = || -> Result<Option<usize>, ParseError> {
+        match parts.next_if(|arg| arg.starts_with(|c: char| c.is_ascii_digit())) {
+            None => Ok(None),
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(ParseError::InvalidArgument(
+                    arg.to_string(),
+                    line_number,
+                    column_in(line, arg),
+                )),
+            },
+        }
+    };
+    let token = match part.to_uppercase().as_str() {
+        "PUSH" => match parts.next() {
+            None => {
+                return Err(ParseError::MissingArgument(
+                    part.to_string(),
+                    line_number,
+                    column_in(line, part),
+                ))
+            }
+            Some(arg) => match arg.parse::<u8>() {
+                Ok(value) => Token::Push(value),
+                Err(_) => {
+                    return Err(ParseError::InvalidArgument(
+                        arg.to_string(),
+                        line_number,
+                        column_in(line, arg),
+                    ))
+                }
+            },
+        },
+        "POP" => Token::Pop,
+        "DUP" => Token::Dup,
+        "SWAP" => Token::Swap,
+        "OVER" => Token::Over,
+        "ROTATE" => Token::Rotate,
+        "NIP" => Token::Nip,
+        "TUCK" => Token::Tuck,
+        "PICK" => Token::Pick(optional_argument()?),
+        "ROLL" => Token::Roll(optional_argument()?),
+        "ADD" => Token::BinOp(BinOp::Add),
+        "SUB" => Token::BinOp(BinOp::Sub),
+        "RANDOM" => Token::Random,
+        "ASSERT" => Token::Assert,
+        "ASSERT_EQ" => Token::AssertEq,
+        "PRINT_BYTE" => Token::PrintByte,
+        "PRINT_CHAR" => Token::PrintChar,
+        "PRINT_HEX" => Token::PrintHex,
+        "PRINT_UTF8" => Token::PrintUtf8,
+        "EPRINT_BYTE" => Token::EprintByte,
+        "EPRINT_CHAR" => Token::EprintChar,
+        "PRINT_NEWLINE" | "CR" => Token::PrintNewline,
+        "SPACE" => Token::PrintSpace,
+        "PRINT_STACK" => Token::PrintStack,
+        "READ_CHAR" => Token::ReadChar,
+        "READ_LINE" => Token::ReadLine,
+        "PARSE_NUM" => Token::ParseNum,
+        "EOF" => Token::Eof,
+        "SLEEP" => Token::Sleep,
+        "TICKS" => Token::Ticks,
+        "FOPEN" => Token::Fopen,
+        "FREAD" => Token::Fread,
+        "FWRITE" => Token::Fwrite,
+        "FCLOSE" => Token::Fclose,
+        "KEY" => Token::Key,
+        "KEY?" => Token::KeyPoll,
+        "CLEAR_SCREEN" => Token::ClearScreen,
+        "CURSOR_AT" => Token::CursorAt,
+        "TCP_LISTEN" => Token::TcpListen,
+        "TCP_ACCEPT" => Token::TcpAccept,
+        "TCP_CONNECT" => Token::TcpConnect,
+        "TCP_SEND" => Token::TcpSend,
+        "TCP_RECV" => Token::TcpRecv,
+        "TCP_CLOSE" => Token::TcpClose,
+        "GFX_INIT" => Token::GfxInit,
+        "PIXEL" => Token::Pixel,
+        "GFX_FLUSH" => Token::GfxFlush,
+        "ENV" => Token::Env,
+        "FETCH" => Token::Fetch,
+        "STORE" => Token::Store,
+        "ALLOT" => Token::Allot,
+        "FETCH_AT" => Token::FetchAt,
+        "STORE_AT" => Token::StoreAt,
+        "IF" => Token::If,
+        "ELSE" => Token::Else,
+        "THEN" => Token::Then,
+        "CASE" => Token::Case,
+        "OF" => Token::Of,
+        "ENDOF" => Token::EndOf,
+        "ENDCASE" => Token::EndCase,
+        "SPAWN" | "PUSH_ADDR" => match parts.next() {
+            None => {
+                return Err(ParseError::MissingArgument(
+                    part.to_string(),
+                    line_number,
+                    column_in(line, part),
+                ))
+            }
+            Some(label) if part.eq_ignore_ascii_case("spawn") => {
+                Token::Spawn(label_key(label, case_sensitive))
+            }
+            Some(label) => Token::PushAddr(label_key(label, case_sensitive)),
+        },
+        "EXECUTE" => Token::Execute,
+        "YIELD" => Token::Yield,
+        "JOIN" => Token::Join,
+        "CHAN_NEW" => Token::ChanNew,
+        "SEND" => Token::Send,
+        "RECV" => Token::Recv,
+        "TRY" => Token::Try,
+        "CATCH" => Token::Catch,
+        "ENDTRY" => Token::EndTry,
+        "THROW" => Token::Throw,
+        "RETURN" => Token::Return,
+        "HALT" => Token::Halt,
+        "EXIT" => Token::Exit,
+        _ => Token::Call(label_key(part, case_sensitive)),
+    };
+    Ok(token)
+}
+
+// the name labels, variables and constants are known by: uppercase, unless the program is
+// case-sensitive (see Program::set_case_sensitive)
+fn label_key(name: &str, case_sensitive: bool) -> String {
+    match case_sensitive {
+        true => name.to_string(),
+        false => name.to_uppercase(),
+    }
+}
+
+// a label as it's shown in listings, traces and errors: lowercase like the keywords, unless
+// it was written with lowercase letters, which only a case-sensitive program keeps
+pub fn label_name(label: &str) -> String {
+    match label.chars().any(char::is_lowercase) {
+        true => label.to_string(),
+        false => label.to_lowercase(),
+    }
+}
+
+// 1-based column of a word sliced from the line (0 if it isn't part of the line)
+fn column_in(line: &str, word: &str) -> usize {
+    let offset = (word.as_ptr() as usize).wrapping_sub(line.as_ptr() as usize);
+    match line.get(..offset) {
+        Some(before) => before.chars().count() + 1,
+        None => 0,
+    }
+}
+
+// words that declare a name instead of compiling to an instruction
+const DECLARATIONS: [&str; 3] = ["VARIABLE", "CONSTANT", "DATA"];
+
+enum Declaration {
+    Variable,
+    Constant(u8),
+    // the values a DATA block puts in memory
+    Data(Vec<u8>),
+}
+
+// a VARIABLE <name>, CONSTANT <name> <value> or DATA <name>: <values> declaration, the
+// values of a DATA block being the rest of the line
+fn parse_declaration<'a, I: Iterator<Item = &'a str>>(
+    keyword: &str,
+    parts: &mut Peekable<I>,
+    line: &str,
+    line_number: usize,
+) -> Result<(&'a str, Declaration), ParseError> {
+    let missing =
+        || ParseError::MissingArgument(keyword.to_string(), line_number, column_in(line, keyword));
+    let invalid = |word: &str| {
+        ParseError::InvalidArgument(word.to_string(), line_number, column_in(line, word))
+    };
+    let mut name = parts.next().ok_or_else(missing)?;
+    let keyword = keyword.to_uppercase();
+    let values: Vec<&str> = match keyword.as_str() {
+        "CONSTANT" => vec![parts.next().ok_or_else(missing)?],
+        "DATA" => {
+            let values = parts.collect();
+            name = name.strip_suffix(':').ok_or_else(|| invalid(name))?;
+            values
+        }
+        _ => Vec::new(),
+    };
+    if is_keyword(name) {
+        return Err(ParseError::ReservedLabelName(
+            name.to_string(),
+            line_number,
+            column_in(line, name),
+        ));
+    }
+    let values = values
+        .into_iter()
+        .map(|value| value.parse::<u8>().map_err(|_| invalid(value)))
+        .collect::<Result<Vec<u8>, _>>()?;
+    Ok(match keyword.as_str() {
+        "CONSTANT" => (name, Declaration::Constant(values[0])),
+        "DATA" if values.is_empty() => return Err(missing()),
+        "DATA" => (name, Declaration::Data(values)),
+        _ => (name, Declaration::Variable),
+    })
+}
+
+// the first handle, an index into `slots`, that nothing open has, making room for it if
+// there are fewer than `max`
+fn free_handle<T>(slots: &mut Vec<Option<T>>, max: usize) -> Option<usize> {
+    match slots.iter().position(Option::is_none) {
+        Some(handle) => Some(handle),
+        None if slots.len() < max => {
+            slots.push(None);
+            Some(slots.len() - 1)
+        }
+        None => None,
+    }
+}
+
+// whether a word parses as an instruction rather than a call, so it can't be used as a label
+pub fn is_keyword(word: &str) -> bool {
+    DECLARATIONS.contains(&word.to_uppercase().as_str())
+        || !matches!(
+            parse_token(word, &mut core::iter::empty().peekable(), "", 0, false),
+            Ok(Token::Call(_))
+        )
+}
+
+// one entry of a backtrace: the subroutine (None for top-level code) and the line in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub label: Option<String>,
+    pub line_number: usize,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "'{}' line {}", label_name(label), self.line_number),
+            None => write!(f, "top level line {}", self.line_number),
+        }
+    }
+}
+
+// the machine right after Program::step_with_state() ran the instruction at `index`
+pub struct MachineState<'a> {
+    pub index: usize,
+    pub op: Op,
+    // the instruction that runs next
+    pub pc: usize,
+    pub stack: &'a [u8],
+    pub call_depth: usize,
+    pub halted: bool,
+}
+
+// why a host word failed; underflows and overflows are reported like the instructions
+pub enum HostError {
+    StackUnderflow,
+    StackOverflow,
+    Failed(String),
+}
+
+// the program's stack as a host word sees it
+pub struct HostStack<'a> {
+    stack: &'a mut Vec<u8>,
+    size: usize,
+}
+
+impl HostStack<'_> {
+    pub fn pop(&mut self) -> Result<u8, HostError> {
+        self.stack.pop().ok_or(HostError::StackUnderflow)
+    }
+
+    pub fn push(&mut self, value: u8) -> Result<(), HostError> {
+        if self.stack.len() >= self.size {
+            return Err(HostError::StackOverflow);
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // the values on the stack, bottom first
+    pub fn values(&self) -> &[u8] {
+        self.stack
+    }
+}
+
+type HostWord = Box<dyn FnMut(&mut HostStack) -> Result<(), HostError>>;
+
+// called back by a program while it runs, see Program::set_observer(). Pushes and pops are
+// what changed on the stack: values an instruction takes off and puts back in the same
+// place aren't reported, so `swap` of two equal values reports nothing
+pub trait ExecutionObserver {
+    // before the instruction at `index` runs
+    fn on_step(&mut self, _program: &Program, _index: usize, _op: Op) {}
+    fn on_push(&mut self, _value: u8) {}
+    fn on_pop(&mut self, _value: u8) {}
+    // after a call went to `target`; a tail call reuses its caller's frame
+    fn on_call(&mut self, _program: &Program, _target: usize) {}
+    fn on_return(&mut self, _program: &Program) {}
+}
+
+// an instruction that ran, as yielded by Program::steps()
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Step {
+    pub index: usize,
+    pub op: Op,
+}
+
+// runs a program one instruction per item until it halts; a runtime error is the last item
+pub struct Steps<'a> {
+    program: &'a mut Program,
+    failed: bool,
+}
+
+impl Iterator for Steps<'_> {
+    type Item = Result<Step, RuntimeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.program.step_with_state() {
+            Ok(state) => state.map(|state| {
+                Ok(Step {
+                    index: state.index,
+                    op: state.op,
+                })
+            }),
+            Err(error) => {
+                self.failed = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+// all errors found while parsing a program, ordered by line
+#[derive(Debug)]
+pub struct Diagnostics {
+    pub errors: Vec<ParseError>,
+}
+
+impl Diagnostics {
+    pub fn iter(&self) -> impl Iterator<Item = &ParseError> {
+        self.errors.iter()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, err) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "line {}: {}", err.line_number(), err)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for Diagnostics {}
+
+// what Program::check_invariants() found wrong with a program's state, which is always a
+// bug in the interpreter rather than in the program
+#[derive(Debug, PartialEq)]
+pub enum InvariantError {
+    // the pc of the running task or another one, or the CATCH address of a TRY, is past
+    // the end of the code
+    AddressOutOfRange(usize),
+    StackTooLarge(usize),
+    ReturnAddressOutOfRange(usize),
+    // the label's index doesn't match the one in the compiled code, or it's only in one of
+    // them
+    LabelMismatch(String),
+    MemoryTooLarge(usize),
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantError::AddressOutOfRange(address) => {
+                write!(f, "Address {} is past the end of the code", address)
+            }
+            InvariantError::StackTooLarge(size) => {
+                write!(f, "Stack has {} values, more than the stack size", size)
+            }
+            InvariantError::ReturnAddressOutOfRange(address) => {
+                write!(f, "Return address {} is past the end of the code", address)
+            }
+            InvariantError::LabelMismatch(label) => {
+                write!(f, "Label '{}' doesn't match the compiled code", label)
+            }
+            InvariantError::MemoryTooLarge(cells) => {
+                write!(f, "{} memory cells are in use, more than there are", cells)
+            }
+        }
+    }
+}
+
+impl core::error::Error for InvariantError {}
+
+// where READ_CHAR reads from; with the std feature, any io::Read
+pub trait Input {
+    // None at the end of the input, or if it can't be read
+    fn read_byte(&mut self) -> Option<u8>;
+}
+
+// where PRINT_BYTE and PRINT_CHAR (or, as the error output, EPRINT_BYTE, EPRINT_CHAR and
+// PRINT_STACK) write to; with the std feature, any io::Write
+pub trait Output {
+    fn write_text(&mut self, text: &str) -> fmt::Result;
+
+    // writes out what an output holds back, if it's buffered
+    fn flush(&mut self) -> fmt::Result {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: io::Read> Input for R {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut buffer = [0u8];
+        match self.read(&mut buffer) {
+            Ok(1) => Some(buffer[0]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> Output for W {
+    fn write_text(&mut self, text: &str) -> fmt::Result {
+        self.write_all(text.as_bytes()).map_err(|_| fmt::Error)
+    }
+
+    fn flush(&mut self) -> fmt::Result {
+        io::Write::flush(self).map_err(|_| fmt::Error)
+    }
+}
+
+// without std there's no stdin, stdout or stderr: programs read end of input and print
+// nowhere until set_input(), set_output() and set_error_output() are called
+#[cfg(not(feature = "std"))]
+struct NoInput;
+
+#[cfg(not(feature = "std"))]
+impl Input for NoInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+#[cfg(not(feature = "std"))]
+struct NoOutput;
+
+#[cfg(not(feature = "std"))]
+impl Output for NoOutput {
+    fn write_text(&mut self, _: &str) -> fmt::Result {
+        Ok(())
+    }
 }
 
 pub struct Program {
-    pub lines: Vec<String>,
+    pub source: Source,
     pub tokens: Vec<AnnotatedToken>,
+    // compiled by parse(); pc indexes its ops
+    pub code: Bytecode,
     pub pc: usize,
-    labels: HashMap<String, usize>,
+    labels: BTreeMap<String, usize>,
+    label_lines: BTreeMap<String, usize>,
+    // the source lines parse() went through, the errors in them and the initial contents of
+    // the memory cells their variables and DATA blocks took
+    parsed_lines: usize,
+    line_errors: Vec<ParseError>,
+    data: Vec<u8>,
+    // what the names of variables (their address) and constants stand for, by label_key()
+    names: BTreeMap<String, u8>,
+    // whether `Foo` and `foo` are different labels, variables and constants
+    case_sensitive: bool,
     call_stack: Vec<usize>,
     pub stack: Vec<u8>,
+    // the cells FETCH and STORE read and write, shared by every task
+    pub memory: Vec<u8>,
+    // the cells ALLOT handed out, after the ones the program declared
+    allotted: usize,
     pub stack_size: usize,
     pub halted: bool,
+    pub exit_code: Option<u8>,
+    rng: Rng,
+    input: Box<dyn Input>,
+    // whether the last READ_CHAR or READ_LINE ran out of input, for EOF
+    end_of_input: bool,
+    output: Box<dyn Output>,
+    // the bytes PRINT_UTF8 got of a character it hasn't printed yet
+    utf8: Vec<u8>,
+    error_output: Box<dyn Output>,
+    clock: Box<dyn Clock>,
+    // where ENV reads variables, if anywhere
+    environment: Option<Box<dyn Environment>>,
+    // where FOPEN opens files, if anywhere, and the open files by handle
+    file_system: Option<Box<dyn FileSystem>>,
+    files: Vec<Option<Box<dyn File>>>,
+    // where TCP_LISTEN and TCP_CONNECT open sockets, if anywhere, and the open ones by handle
+    network: Option<Box<dyn Network>>,
+    sockets: Vec<Option<Socket>>,
+    // the TCP_ACCEPTs and TCP_RECVs in a row that found nothing, see poll_later()
+    idle_polls: usize,
+    // where KEY and KEY? read keys, if not from the input
+    keyboard: Option<Box<dyn Keyboard>>,
+    // the picture GFX_INIT started, if any, and where GFX_FLUSH shows it
+    frame: Option<Framebuffer>,
+    screen: Option<Box<dyn Screen>>,
+    observer: Option<Box<dyn ExecutionObserver>>,
+    // registered host words by name (uppercase)
+    words: Vec<(String, HostWord)>,
+    usage: Option<Usage>,
+    // the instructions run since parse() or reset()
+    steps: u64,
+    interrupt: Option<&'static AtomicBool>,
+    // every task by id once the program spawned one, and the id of the running task
+    tasks: Vec<Task>,
+    task: usize,
+    // the values sent to every channel and not received yet, by id
+    channels: Vec<VecDeque<u8>>,
+    // the TRYs of the running task that haven't reached their CATCH, innermost last
+    handlers: Vec<Handler>,
 }
 
 impl Program {
     pub fn new(text: &str, stack_size: usize) -> Self {
-        let lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
         Self {
-            lines,
+            source: Source::new(text),
             tokens: Vec::new(),
+            code: Bytecode {
+                ops: Vec::new(),
+                positions: Vec::new(),
+                labels: Vec::new(),
+                natives: Vec::new(),
+                addresses: Vec::new(),
+                data: Vec::new(),
+            },
             pc: 0,
-            labels: HashMap::new(),
+            labels: BTreeMap::new(),
+            label_lines: BTreeMap::new(),
+            parsed_lines: 0,
+            line_errors: Vec::new(),
+            data: Vec::new(),
+            names: BTreeMap::new(),
+            case_sensitive: false,
             call_stack: Vec::new(),
             stack: Vec::with_capacity(stack_size),
+            memory: vec![0; MEMORY_SIZE],
+            allotted: 0,
             stack_size,
             halted: false,
+            exit_code: None,
+            rng: Rng::unseeded(),
+            #[cfg(feature = "std")]
+            input: Box::new(io::stdin()),
+            end_of_input: false,
+            utf8: Vec::new(),
+            #[cfg(feature = "std")]
+            output: Box::new(io::BufWriter::new(io::stdout())),
+            #[cfg(feature = "std")]
+            error_output: Box::new(io::stderr()),
+            #[cfg(not(feature = "std"))]
+            input: Box::new(NoInput),
+            #[cfg(not(feature = "std"))]
+            output: Box::new(NoOutput),
+            #[cfg(not(feature = "std"))]
+            error_output: Box::new(NoOutput),
+            #[cfg(feature = "std")]
+            clock: Box::new(SystemClock::new()),
+            #[cfg(not(feature = "std"))]
+            clock: Box::new(VirtualClock::default()),
+            environment: None,
+            file_system: None,
+            files: Vec::new(),
+            network: None,
+            sockets: Vec::new(),
+            idle_polls: 0,
+            keyboard: None,
+            frame: None,
+            screen: None,
+            observer: None,
+            words: Vec::new(),
+            usage: None,
+            steps: 0,
+            interrupt: None,
+            tasks: Vec::new(),
+            task: 0,
+            channels: Vec::new(),
+            handlers: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<(), ParseError> {
-        for (line_number, line) in (1..).zip(self.lines.iter()) {
-            let mut parts = line.split_whitespace();
-            if let Some(part) = parts.next() {
-                if part.starts_with('#') {
-                    continue;
+    // a program loaded from a compiled file; it has no source lines or tokens
+    pub fn from_bytecode(code: Bytecode, stack_size: usize) -> Self {
+        let mut program = Program::new("", stack_size);
+        program.code = code;
+        program.labels_from_code();
+        program.load_data();
+        program
+    }
+
+    // runs the optimizer over the compiled code; the tokens stay as they were parsed
+    pub fn optimize(&mut self, options: &optimizer::Options) {
+        optimizer::optimize(&mut self.code, options);
+        self.labels_from_code();
+    }
+
+    // the memory as the program starts with it: the DATA blocks, and 0 everywhere else,
+    // with nothing allotted
+    fn load_data(&mut self) {
+        self.allotted = 0;
+        self.memory.fill(0);
+        self.memory[..self.code.data.len()].copy_from_slice(&self.code.data);
+    }
+
+    fn labels_from_code(&mut self) {
+        self.labels = self
+            .code
+            .labels
+            .iter()
+            .map(|(label, index)| (label.clone(), *index))
+            .collect();
+    }
+
+    pub fn set_input(&mut self, input: impl Input + 'static) {
+        self.input = Box::new(input);
+        self.end_of_input = false;
+    }
+
+    pub fn set_output(&mut self, output: impl Output + 'static) {
+        self.output = Box::new(output);
+    }
+
+    // where EPRINT_BYTE, EPRINT_CHAR and PRINT_STACK write to, stderr by default
+    pub fn set_error_output(&mut self, output: impl Output + 'static) {
+        self.error_output = Box::new(output);
+    }
+
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    // lets TCP_LISTEN and TCP_CONNECT open sockets in `network`; without one, they fail with
+    // NetError::Disabled
+    pub fn set_network(&mut self, network: impl Network + 'static) {
+        self.network = Some(Box::new(network));
+    }
+
+    pub fn set_keyboard(&mut self, keyboard: impl Keyboard + 'static) {
+        self.keyboard = Some(Box::new(keyboard));
+    }
+
+    // where GFX_FLUSH shows the frame; without one, it isn't shown anywhere
+    pub fn set_screen(&mut self, screen: impl Screen + 'static) {
+        self.screen = Some(Box::new(screen));
+    }
+
+    // lets ENV read variables from `environment`; without one, it fails with
+    // EnvError::Disabled
+    pub fn set_environment(&mut self, environment: impl Environment + 'static) {
+        self.environment = Some(Box::new(environment));
+    }
+
+    // lets FOPEN open files in `file_system`; without one, it fails with FileError::Disabled
+    pub fn set_file_system(&mut self, file_system: impl FileSystem + 'static) {
+        self.file_system = Some(Box::new(file_system));
+    }
+
+    // makes `name` a word that runs `word` on the stack, for programs parsed afterwards.
+    // A label of the same name takes precedence, and keywords can't be replaced
+    pub fn register_word<F>(&mut self, name: &str, word: F)
+    where
+        F: FnMut(&mut HostStack) -> Result<(), HostError> + 'static,
+    {
+        let name = name.to_uppercase();
+        self.words.retain(|(registered, _)| *registered != name);
+        self.words.push((name, Box::new(word)));
+    }
+
+    // makes labels, variables and constants case-sensitive in the lines parsed afterwards,
+    // so `Foo:` isn't called by `foo`. Keywords and host words can be written in any case
+    pub fn set_case_sensitive(&mut self, case_sensitive: bool) {
+        self.case_sensitive = case_sensitive;
+    }
+
+    // stops the program with RuntimeError::LimitExceeded before it goes over a limit; what
+    // it used so far is forgotten, so setting limits again lets a stopped program continue
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.usage = Some(Usage::new(limits));
+    }
+
+    pub fn limits(&self) -> Option<Limits> {
+        self.usage.as_ref().map(|usage| usage.limits)
+    }
+
+    // the instructions run since parse() or reset(), not counting compiled code
+    pub fn steps_run(&self) -> u64 {
+        self.steps
+    }
+
+    // stops the program with RuntimeError::LimitExceeded(_, Limit::Interrupted) before the
+    // next instruction once `flag` is set, e.g. by a Ctrl-C handler or another thread. The
+    // instruction hasn't run, so clearing the flag and running again continues the program
+    pub fn set_interrupt(&mut self, flag: &'static AtomicBool) {
+        self.interrupt = Some(flag);
+    }
+
+    // calls `observer` back while running, instead of the observer set before
+    pub fn set_observer(&mut self, observer: Box<dyn ExecutionObserver>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn remove_observer(&mut self) -> Option<Box<dyn ExecutionObserver>> {
+        self.observer.take()
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    // parses the program, keeping the tokens of the lines without errors whether it fails or
+    // not; a program parses each line once, so parsing it again only checks it again
+    pub fn parse(&mut self) -> Result<(), Diagnostics> {
+        self.compile()?;
+        self.load_data();
+        Ok(())
+    }
+
+    // adds `text` to the source on a line of its own and parses it like parse(), but keeps the
+    // state of the program, so it can go on running with the new code: one that halted at
+    // the end of its code continues with it. The memory cells new variables and DATA blocks
+    // take get their values, and cells ALLOT handed out over them are forgotten. Text with
+    // errors is taken back, leaving the program as it was
+    pub fn feed(&mut self, text: &str) -> Result<(), Diagnostics> {
+        let end = self.code.ops.len();
+        let declared = self.code.data.len();
+        let parsed = (
+            self.source.clone(),
+            self.parsed_lines,
+            self.tokens.len(),
+            self.labels.clone(),
+            self.label_lines.clone(),
+            self.names.clone(),
+            self.data.len(),
+            self.line_errors.len(),
+        );
+        self.source.push(text);
+        if let Err(diagnostics) = self.compile() {
+            let (source, parsed_lines, tokens, labels, label_lines, names, data, line_errors) =
+                parsed;
+            self.source = source;
+            self.parsed_lines = parsed_lines;
+            self.tokens.truncate(tokens);
+            self.labels = labels;
+            self.label_lines = label_lines;
+            self.names = names;
+            self.data.truncate(data);
+            self.line_errors.truncate(line_errors);
+            return Err(diagnostics);
+        }
+        let data = &self.code.data;
+        self.memory[declared..data.len()].copy_from_slice(&data[declared..]);
+        self.allotted = self.allotted.min(MEMORY_SIZE - data.len());
+        if self.halted && self.pc == end && self.exit_code.is_none() {
+            self.halted = false;
+        }
+        Ok(())
+    }
+
+    // checks and compiles the tokens after parsing the lines added since the last time
+    fn compile(&mut self) -> Result<(), Diagnostics> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("parse", lines = self.source.len()).entered();
+        self.parse_lines();
+        let mut errors = self.line_errors.clone();
+        // variables and constants are pushed where they're named
+        for annotated_token in &mut self.tokens {
+            if let Token::Call(name) = &annotated_token.token {
+                if let Some(&value) = self.names.get(name) {
+                    annotated_token.token = Token::Push(value);
                 }
-                if part.ends_with(":") {
-                    match self
-                        .labels
-                        .entry(part[..part.len() - 1].to_uppercase().to_string())
-                    {
-                        std::collections::hash_map::Entry::Vacant(entry) => {
+            }
+        }
+        self.check_if_statements(&mut errors);
+        self.check_try_statements(&mut errors);
+        self.check_case_statements(&mut errors);
+        self.check_calls(&mut errors);
+        if errors.is_empty() {
+            let natives: Vec<String> = self.words.iter().map(|(name, _)| name.clone()).collect();
+            self.code = bytecode::compile(&self.tokens, self.labels(), &natives);
+            self.code.data = self.data.clone();
+            Ok(())
+        } else {
+            errors.sort_by_key(|err| err.line_number());
+            Err(Diagnostics { errors })
+        }
+    }
+
+    // turns the source lines not parsed yet into tokens, labels and names; an error skips
+    // the word it's in, and the rest of the line is parsed on
+    fn parse_lines(&mut self) {
+        let errors = &mut self.line_errors;
+        let data = &mut self.data;
+        let case_sensitive = self.case_sensitive;
+        let lines = self.source.lines().skip(self.parsed_lines);
+        for (line_number, line) in (self.parsed_lines + 1..).zip(lines) {
+            // a line may hold several words; everything after a '#' is a comment
+            let mut parts = line
+                .split_whitespace()
+                .take_while(|part| !part.starts_with('#'))
+                .peekable();
+            while let Some(part) = parts.next() {
+                if part.ends_with(':') {
+                    let name = part.strip_suffix(':').unwrap();
+                    if is_keyword(name) {
+                        errors.push(ParseError::ReservedLabelName(
+                            name.to_string(),
+                            line_number,
+                            column_in(line, part),
+                        ));
+                        continue;
+                    }
+                    match self.labels.entry(label_key(name, case_sensitive)) {
+                        alloc::collections::btree_map::Entry::Vacant(entry)
+                            if !self.names.contains_key(entry.key()) =>
+                        {
+                            self.label_lines.insert(entry.key().clone(), line_number);
                             entry.insert(self.tokens.len());
                         }
-                        std::collections::hash_map::Entry::Occupied(_) => {
-                            return Err(ParseError::DuplicateLabel(part.to_string(), line_number))
-                        }
+                        _ => errors.push(ParseError::DuplicateLabel(
+                            part.to_string(),
+                            line_number,
+                            column_in(line, part),
+                        )),
                     }
                     continue;
                 };
-                let token = match part.to_uppercase().as_str() {
-                    "PUSH" => match parts.next() {
-                        None => {
-                            return Err(ParseError::MissingArgument(part.to_string(), line_number))
-                        }
-                        Some(arg) => match arg.parse::<u8>() {
-                            Ok(value) => Token::Push(value),
-                            Err(_) => {
-                                return Err(ParseError::InvalidArgument(
-                                    arg.to_string(),
-                                    line_number,
-                                ))
+                if DECLARATIONS.contains(&part.to_uppercase().as_str()) {
+                    let (name, declaration) =
+                        match parse_declaration(part, &mut parts, line, line_number) {
+                            Ok(declaration) => declaration,
+                            Err(err) => {
+                                errors.push(err);
+                                continue;
                             }
-                        },
-                    },
-                    "POP" => Token::Pop,
-                    "DUP" => Token::Dup,
-                    "SWAP" => Token::Swap,
-                    "OVER" => Token::Over,
-                    "ROTATE" => Token::Rotate,
-                    "PICK" => match parts.next() {
-                        None => {
-                            return Err(ParseError::MissingArgument(part.to_string(), line_number))
+                        };
+                    let key = label_key(name, case_sensitive);
+                    let column = column_in(line, name);
+                    if self.names.contains_key(&key) || self.labels.contains_key(&key) {
+                        errors.push(ParseError::DuplicateLabel(
+                            name.to_string(),
+                            line_number,
+                            column,
+                        ));
+                        continue;
+                    }
+                    let values = match declaration {
+                        Declaration::Constant(value) => {
+                            self.names.insert(key, value);
+                            continue;
                         }
-                        Some(arg) => match arg.parse::<usize>() {
-                            Ok(value) => Token::Pick(value),
-                            Err(_) => {
-                                return Err(ParseError::InvalidArgument(
-                                    arg.to_string(),
-                                    line_number,
-                                ))
-                            }
-                        },
-                    },
-                    "ADD" => Token::BinOp(BinOp::Add),
-                    "SUB" => Token::BinOp(BinOp::Sub),
-                    "PRINT_BYTE" => Token::PrintByte,
-                    "PRINT_CHAR" => Token::PrintChar,
-                    "IF" => Token::If,
-                    "ELSE" => Token::Else,
-                    "THEN" => Token::Then,
-                    "RETURN" => Token::Return,
-                    "HALT" => Token::Halt,
-                    other => Token::Call(other.to_string()),
-                };
-                self.tokens.push(AnnotatedToken { token, line_number })
+                        Declaration::Variable => vec![0],
+                        Declaration::Data(values) => values,
+                    };
+                    if data.len() + values.len() > MEMORY_SIZE {
+                        errors.push(ParseError::OutOfMemory(
+                            name.to_string(),
+                            line_number,
+                            column,
+                        ));
+                        continue;
+                    }
+                    self.names.insert(key, data.len() as u8);
+                    data.extend(values);
+                    continue;
+                }
+                match parse_token(part, &mut parts, line, line_number, case_sensitive) {
+                    Ok(token) => self.tokens.push(AnnotatedToken {
+                        token,
+                        line_number,
+                        column: column_in(line, part),
+                    }),
+                    Err(err) => errors.push(err),
+                }
             }
         }
-        if let Err(parse_error) = self.check_if_statements() {
-            return Err(parse_error);
-        };
-        if let Err(parse_error) = self.check_calls() {
-            return Err(parse_error);
-        };
-        Ok(())
+        self.parsed_lines = self.source.len();
     }
 
-    fn check_calls(&self) -> Result<(), ParseError> {
+    fn check_calls(&self, errors: &mut Vec<ParseError>) {
+        let mut addressed: Vec<&str> = Vec::new();
         for annotated_token in &self.tokens {
-            if let Token::Call(label) = &annotated_token.token {
-                if let None = self.labels.get(label) {
-                    return Err(ParseError::InvalidCall(
-                        label.to_string(),
-                        annotated_token.line_number,
-                    ));
+            if let Token::PushAddr(label) = &annotated_token.token {
+                if !addressed.contains(&label.as_str()) {
+                    addressed.push(label);
+                    if addressed.len() == MAX_ADDRESSES + 1 {
+                        errors.push(ParseError::TooManyAddresses(annotated_token.clone()));
+                    }
+                }
+            }
+            // host words can be called, but not spawned or executed
+            let (label, defined) = match &annotated_token.token {
+                Token::Call(label) => (
+                    label,
+                    self.labels.contains_key(label)
+                        || self
+                            .words
+                            .iter()
+                            .any(|(name, _)| *name == label.to_uppercase()),
+                ),
+                Token::Spawn(label) | Token::PushAddr(label) => {
+                    (label, self.labels.contains_key(label))
                 }
+                _ => continue,
+            };
+            if !defined {
+                errors.push(ParseError::InvalidCall(
+                    label.to_string(),
+                    annotated_token.line_number,
+                    annotated_token.column,
+                ));
             }
         }
-        Ok(())
     }
 
-    fn check_if_statements(&self) -> Result<(), ParseError> {
+    fn check_if_statements(&self, errors: &mut Vec<ParseError>) {
         let mut else_statements: Vec<u32> = Vec::new();
         for annotated_token in &self.tokens {
             match annotated_token.token {
                 Token::If => {
                     else_statements.push(0);
                 }
-                Token::Else => {
-                    let num_else_statements_at_depth = match else_statements.pop() {
-                        None => {
-                            return Err(ParseError::ElseWithoutIfStatement(annotated_token.clone()))
+                Token::Else => match else_statements.pop() {
+                    None => {
+                        errors.push(ParseError::ElseWithoutIfStatement(annotated_token.clone()))
+                    }
+                    Some(num_else_statements_at_depth) => {
+                        if num_else_statements_at_depth > 0 {
+                            errors.push(ParseError::TooManyElseStatements(annotated_token.clone()));
                         }
-                        Some(n) => n,
-                    };
-                    if num_else_statements_at_depth > 0 {
-                        return Err(ParseError::TooManyElseStatements(annotated_token.clone()));
+                        else_statements.push(num_else_statements_at_depth + 1);
                     }
-                    else_statements.push(num_else_statements_at_depth + 1);
-                }
-                Token::Then => {
-                    if let None = else_statements.pop() {
-                        return Err(ParseError::ThenWithoutIfStatement(annotated_token.clone()));
-                    };
+                },
+                // the guard closes the innermost IF, reporting a THEN that has none
+                Token::Then if else_statements.pop().is_none() => {
+                    errors.push(ParseError::ThenWithoutIfStatement(annotated_token.clone()));
                 }
                 _ => (),
             }
         }
-        Ok(())
     }
 
-    pub fn step(&mut self) -> Result<(), RuntimeError> {
-        if self.pc >= self.tokens.len() || self.halted {
-            return Ok(());
+    // every TRY needs a CATCH and then an ENDTRY
+    fn check_try_statements(&self, errors: &mut Vec<ParseError>) {
+        // the open TRYs and whether their CATCH came yet
+        let mut open: Vec<(&AnnotatedToken, bool)> = Vec::new();
+        for annotated_token in &self.tokens {
+            match annotated_token.token {
+                Token::Try => open.push((annotated_token, false)),
+                Token::Catch => match open.last_mut() {
+                    Some((_, caught)) if !*caught => *caught = true,
+                    _ => errors.push(ParseError::CatchWithoutTry(annotated_token.clone())),
+                },
+                Token::EndTry => match open.pop() {
+                    Some((_, true)) => (),
+                    _ => errors.push(ParseError::EndTryWithoutCatch(annotated_token.clone())),
+                },
+                _ => (),
+            }
+        }
+        for (try_token, _) in open {
+            errors.push(ParseError::UnclosedTry(try_token.clone()));
+        }
+    }
+
+    // every OF needs an ENDOF before the next OF, and both go directly inside a CASE
+    fn check_case_statements(&self, errors: &mut Vec<ParseError>) {
+        // the open CASEs and the OF of each that hasn't reached its ENDOF yet
+        let mut open: Vec<(&AnnotatedToken, Option<&AnnotatedToken>)> = Vec::new();
+        for annotated_token in &self.tokens {
+            match annotated_token.token {
+                Token::Case => open.push((annotated_token, None)),
+                Token::Of => match open.last_mut() {
+                    Some((_, of @ None)) => *of = Some(annotated_token),
+                    _ => errors.push(ParseError::OfWithoutCase(annotated_token.clone())),
+                },
+                Token::EndOf => match open.last_mut() {
+                    Some((_, of @ Some(_))) => *of = None,
+                    _ => errors.push(ParseError::EndOfWithoutOf(annotated_token.clone())),
+                },
+                Token::EndCase => match open.pop() {
+                    None => errors.push(ParseError::EndCaseWithoutCase(annotated_token.clone())),
+                    Some((_, Some(of))) => errors.push(ParseError::UnclosedOf(of.clone())),
+                    Some((_, None)) => (),
+                },
+                _ => (),
+            }
+        }
+        for (case, _) in open {
+            errors.push(ParseError::UnclosedCase(case.clone()));
+        }
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.labels
+            .iter()
+            .map(|(label, &index)| (label.as_str(), index))
+    }
+
+    pub fn label_index(&self, label: &str) -> Option<usize> {
+        self.labels
+            .get(&label_key(label, self.case_sensitive))
+            .copied()
+    }
+
+    pub fn label_line(&self, label: &str) -> Option<usize> {
+        self.label_lines
+            .get(&label_key(label, self.case_sensitive))
+            .copied()
+    }
+
+    // number of subroutine calls that haven't returned yet
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    // the current position followed by every pending call site, innermost first
+    pub fn backtrace(&self) -> Vec<Frame> {
+        let ops = &self.code.ops;
+        let mut positions = vec![self.pc.min(ops.len().saturating_sub(1))];
+        positions.extend(
+            self.call_stack
+                .iter()
+                .rev()
+                .map(|&return_index| return_index.wrapping_sub(1))
+                .filter(|&index| matches!(ops.get(index), Some(Op::Call(_) | Op::Execute))),
+        );
+        positions
+            .into_iter()
+            .filter(|&index| index < ops.len())
+            .map(|index| Frame {
+                label: self.code.enclosing_label(index).map(str::to_string),
+                line_number: self.code.positions[index].0,
+            })
+            .collect()
+    }
+
+    // runs the subroutine at the given label on a fresh stack, halting once it returns
+    pub fn call(&mut self, label: &str) -> Result<(), RuntimeError> {
+        let label = label_key(label, self.case_sensitive);
+        let index = match self.code.label_index(&label) {
+            Some(index) => index,
+            None => {
+                return Err(RuntimeError::InvalidLabel(AnnotatedToken {
+                    token: Token::Call(label),
+                    line_number: 0,
+                    column: 0,
+                }))
+            }
+        };
+        self.reset();
+        self.call_stack.push(self.code.ops.len());
+        self.pc = index;
+        Ok(())
+    }
+
+    // gives up on a run that failed like Forth's ABORT: the stack, the calls, the TRYs and
+    // the tasks are dropped, but the memory and open files stay, and the program is halted
+    pub fn abort(&mut self) {
+        self.stack.clear();
+        self.call_stack.clear();
+        self.handlers.clear();
+        self.tasks.clear();
+        self.task = 0;
+        self.channels.clear();
+        self.halted = true;
+    }
+
+    // back to where the program starts after parse(), with an empty stack, so it can run
+    // again without parsing it again; the input, the output and RANDOM carry on as they are
+    pub fn reset(&mut self) {
+        if let Some(usage) = &mut self.usage {
+            *usage = Usage::new(usage.limits);
+        }
+        self.steps = 0;
+        self.pc = 0;
+        self.stack.clear();
+        self.call_stack.clear();
+        self.load_data();
+        self.halted = false;
+        self.exit_code = None;
+        self.tasks.clear();
+        self.task = 0;
+        self.channels.clear();
+        self.handlers.clear();
+        self.frame = None;
+        // buffered writes are lost if closing fails, as there's no one to tell
+        for mut file in self.files.drain(..).flatten() {
+            let _ = file.close();
+        }
+        for socket in self.sockets.drain(..).flatten() {
+            if let Socket::Connection(mut connection) = socket {
+                let _ = connection.close();
+            }
+        }
+    }
+
+    // the text in the `length` cells from `address` on, if it's UTF-8 and not empty
+    fn text(&self, address: u8, length: u8) -> Option<String> {
+        let text: Vec<u8> = (0..length)
+            .map(|index| self.memory[usize::from(address.wrapping_add(index))])
+            .collect();
+        String::from_utf8(text).ok().filter(|text| !text.is_empty())
+    }
+
+    // opens the file named by the `length` cells from `address` on, and returns its handle:
+    // the first one no open file has
+    fn open_file(&mut self, address: u8, length: u8, mode: u8) -> Result<u8, FileError> {
+        if self.file_system.is_none() {
+            return Err(FileError::Disabled);
+        }
+        let name = self.text(address, length).ok_or(FileError::BadName)?;
+        let mode = Mode::from_byte(mode).ok_or(FileError::BadMode)?;
+        let handle = free_handle(&mut self.files, MAX_FILES).ok_or(FileError::TooManyFiles)?;
+        let file_system = self.file_system.as_mut().unwrap();
+        self.files[handle] = Some(file_system.open(&name, mode)?);
+        Ok(handle as u8)
+    }
+
+    // the value of the variable named by the `length` cells from `address` on
+    fn env(&self, address: u8, length: u8) -> Result<Vec<u8>, EnvError> {
+        let environment = self.environment.as_ref().ok_or(EnvError::Disabled)?;
+        let name = self.text(address, length).ok_or(EnvError::BadName)?;
+        environment.var(&name).ok_or(EnvError::NotSet)
+    }
+
+    // the handle of a new socket, like the handle of a file
+    fn add_socket(&mut self, socket: Socket) -> Result<u8, NetError> {
+        let handle = free_handle(&mut self.sockets, MAX_SOCKETS).ok_or(NetError::TooManySockets)?;
+        self.sockets[handle] = Some(socket);
+        Ok(handle as u8)
+    }
+
+    fn socket(&mut self, handle: u8) -> Result<&mut Socket, NetError> {
+        self.sockets
+            .get_mut(usize::from(handle))
+            .and_then(Option::as_mut)
+            .ok_or(NetError::BadHandle)
+    }
+
+    fn connection(&mut self, handle: u8) -> Result<&mut Box<dyn Connection>, NetError> {
+        match self.socket(handle)? {
+            Socket::Connection(connection) => Ok(connection),
+            Socket::Listener(_) => Err(NetError::BadHandle),
         }
-        let current_token = &self.tokens[self.pc];
+    }
+
+    // whether a task besides the running one can go on, so that a socket word that would
+    // have to wait can let it run first
+    fn others_can_run(&self) -> bool {
+        self.tasks.iter().enumerate().any(|(id, task)| {
+            id != self.task && !task.finished() && !self.waiting(task.pc, &task.stack)
+        })
+    }
 
-        match &current_token.token {
-            Token::Push(value) => {
-                if self.stack.len() < self.stack_size {
-                    self.pc += 1;
-                    self.stack.push(value.clone());
-                } else {
-                    return Err(RuntimeError::StackOverflow(current_token.clone()));
+    // lets the other tasks run before a socket word that found nothing tries again. Once
+    // every task did that in turn, none of them has anything to do, so rather than polling
+    // their sockets in a busy loop, the program sleeps a little first
+    fn poll_later(&mut self) -> Result<(), RuntimeError> {
+        self.idle_polls += 1;
+        if self.idle_polls >= self.tasks.len() {
+            self.flush()?;
+            self.clock.sleep(1);
+            self.idle_polls = 0;
+        }
+        self.switch_task()
+    }
+
+    fn file(&mut self, handle: u8) -> Result<&mut Box<dyn File>, FileError> {
+        self.files
+            .get_mut(usize::from(handle))
+            .and_then(Option::as_mut)
+            .ok_or(FileError::BadHandle)
+    }
+
+    // sleeps on the clock, but not past the timeout: the program stops with the time limit
+    // once it's up. A clock that doesn't take real time sleeps the rest afterwards
+    fn sleep(&mut self, milliseconds: u64) -> Result<(), RuntimeError> {
+        #[cfg(feature = "std")]
+        if let Some(left) = self.usage.as_ref().and_then(Usage::time_left) {
+            let left = left.as_millis() as u64 + 1;
+            if milliseconds > left {
+                self.clock.sleep(left);
+                if self.usage.as_ref().is_some_and(Usage::timed_out) {
+                    return Err(self.limit_exceeded(Limit::Time));
                 }
+                self.clock.sleep(milliseconds - left);
+                return Ok(());
+            }
+        }
+        self.clock.sleep(milliseconds);
+        Ok(())
+    }
+
+    // runs the program from the start on a stack holding `initial`, bottom first
+    pub fn run_with_stack(&mut self, initial: &[u8]) -> Result<(), RuntimeError> {
+        self.reset();
+        if initial.len() > self.stack_size {
+            return Err(self.error(RuntimeError::StackOverflow));
+        }
+        self.stack.extend_from_slice(initial);
+        self.run()
+    }
+
+    // the state of the run so far, for restore() to continue from later
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            code: snapshot::fingerprint(&self.code),
+            pc: self.pc,
+            stack: self.stack.clone(),
+            call_stack: self.call_stack.clone(),
+            handlers: self.handlers.clone(),
+            halted: self.halted,
+            exit_code: self.exit_code,
+            rng: self.rng.state(),
+            task: self.task,
+            tasks: self.tasks.clone(),
+            channels: self
+                .channels
+                .iter()
+                .map(|queue| queue.iter().copied().collect())
+                .collect(),
+            memory: self.memory.clone(),
+            allotted: self.allotted,
+        }
+    }
+
+    // checks that the pc, the CATCH addresses and the return addresses of every task are in
+    // the code (the end of the code halts), its stack fits the stack size, the labels match
+    // the compiled code and the allotted cells fit the memory. Debug builds check this
+    // after every instruction
+    pub fn check_invariants(&self) -> Result<(), InvariantError> {
+        let end = self.code.ops.len();
+        let others = self
+            .tasks
+            .iter()
+            .enumerate()
+            .filter(|&(id, _)| id != self.task);
+        let tasks = core::iter::once((self.pc, &self.stack, &self.call_stack, &self.handlers))
+            .chain(
+                others.map(|(_, task)| (task.pc, &task.stack, &task.call_stack, &task.handlers)),
+            );
+        for (pc, stack, call_stack, handlers) in tasks {
+            if let Some(address) = core::iter::once(pc)
+                .chain(handlers.iter().map(|handler| handler.catch))
+                .find(|&address| address > end)
+            {
+                return Err(InvariantError::AddressOutOfRange(address));
+            }
+            if stack.len() > self.stack_size {
+                return Err(InvariantError::StackTooLarge(stack.len()));
+            }
+            if let Some(&address) = call_stack
+                .iter()
+                .find(|&&address| address > end && address != TASK_END)
+            {
+                return Err(InvariantError::ReturnAddressOutOfRange(address));
+            }
+        }
+        if let Some((label, _)) = self
+            .code
+            .labels
+            .iter()
+            .find(|(label, index)| self.labels.get(label) != Some(index))
+        {
+            return Err(InvariantError::LabelMismatch(label.clone()));
+        }
+        if self.labels.len() != self.code.labels.len() {
+            let label = self.labels.keys().find(|&label| {
+                !self
+                    .code
+                    .labels
+                    .iter()
+                    .any(|(code_label, _)| code_label == label)
+            });
+            return Err(InvariantError::LabelMismatch(
+                label.cloned().unwrap_or_default(),
+            ));
+        }
+        let used = self.code.data.len() + self.allotted;
+        if used > MEMORY_SIZE {
+            return Err(InvariantError::MemoryTooLarge(used));
+        }
+        Ok(())
+    }
+
+    // continues from a snapshot of a program with the same code; nothing changes if it
+    // doesn't fit this program
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.code != snapshot::fingerprint(&self.code) {
+            return Err(SnapshotError::OtherProgram);
+        }
+        let end = self.code.ops.len();
+        let handlers = snapshot
+            .tasks
+            .iter()
+            .flat_map(|task| &task.handlers)
+            .chain(&snapshot.handlers);
+        let pcs = snapshot
+            .tasks
+            .iter()
+            .map(|task| &task.pc)
+            .chain(handlers.map(|handler| &handler.catch));
+        let returns = snapshot.tasks.iter().flat_map(|task| &task.call_stack);
+        if let Some(&address) = core::iter::once(&snapshot.pc)
+            .chain(pcs)
+            .find(|&&address| address > end)
+            .or_else(|| {
+                snapshot
+                    .call_stack
+                    .iter()
+                    .chain(returns)
+                    .find(|&&address| address > end && address != TASK_END)
+            })
+        {
+            return Err(SnapshotError::InvalidAddress(address));
+        }
+        let stacks = snapshot.tasks.iter().map(|task| &task.stack);
+        if let Some(stack) = core::iter::once(&snapshot.stack)
+            .chain(stacks)
+            .find(|stack| stack.len() > self.stack_size)
+        {
+            return Err(SnapshotError::StackTooLarge(stack.len()));
+        }
+        let task_count = snapshot.tasks.len().max(1);
+        if snapshot.task >= task_count || task_count > MAX_TASKS {
+            return Err(SnapshotError::InvalidTask(snapshot.task));
+        }
+        if snapshot.memory.len() > MEMORY_SIZE
+            || self.code.data.len() + snapshot.allotted > MEMORY_SIZE
+        {
+            return Err(SnapshotError::MemoryTooLarge(snapshot.memory.len()));
+        }
+        self.pc = snapshot.pc;
+        self.stack.clone_from(&snapshot.stack);
+        self.call_stack.clone_from(&snapshot.call_stack);
+        self.handlers.clone_from(&snapshot.handlers);
+        self.halted = snapshot.halted;
+        self.exit_code = snapshot.exit_code;
+        self.rng = Rng::from_state(snapshot.rng);
+        self.tasks.clone_from(&snapshot.tasks);
+        self.task = snapshot.task;
+        self.channels = snapshot
+            .channels
+            .iter()
+            .map(|values| values.iter().copied().collect())
+            .collect();
+        self.memory.fill(0);
+        self.memory[..snapshot.memory.len()].copy_from_slice(&snapshot.memory);
+        self.allotted = snapshot.allotted;
+        Ok(())
+    }
+
+    // the instruction that runs next
+    pub fn current_token(&self) -> Option<AnnotatedToken> {
+        (self.pc < self.code.ops.len()).then(|| self.code.token(self.pc))
+    }
+
+    fn error(&self, error: fn(AnnotatedToken) -> RuntimeError) -> RuntimeError {
+        error(self.code.token(self.pc))
+    }
+
+    fn pop(&mut self) -> Result<u8, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.error(RuntimeError::StackUnderflow)),
+        }
+    }
+
+    // writes to the output, or the error output, within the output limit
+    fn print(&mut self, text: &str, error_output: bool) -> Result<(), RuntimeError> {
+        if let Some(usage) = &mut self.usage {
+            if let Err(limit) = usage.output(text.len()) {
+                return Err(self.limit_exceeded(limit));
             }
-            Token::Pop => match self.stack.pop() {
-                None => return Err(RuntimeError::StackUnderflow(current_token.clone())),
-                Some(_) => {
-                    self.pc += 1;
+        }
+        let written = if error_output {
+            // what the program printed before comes out first
+            self.flush()?;
+            self.error_output.write_text(text)
+        } else {
+            self.output.write_text(text)
+        };
+        written.map_err(|_| self.error(RuntimeError::OutputFailed))
+    }
+
+    // PRINT_UTF8: prints a character once `byte` completes its UTF-8 sequence, and U+FFFD
+    // for bytes that can't be part of one
+    fn print_utf8(&mut self, byte: u8) -> Result<(), RuntimeError> {
+        let mut pending = mem::take(&mut self.utf8);
+        pending.push(byte);
+        let result = loop {
+            match core::str::from_utf8(&pending) {
+                Ok(text) => {
+                    let result = self.print(text, false);
+                    pending.clear();
+                    break result;
                 }
-            },
-            Token::Dup => match self.stack.last() {
-                None => return Err(RuntimeError::StackUnderflow(current_token.clone())),
-                Some(&top) => {
-                    self.stack.push(top);
-                    self.pc += 1;
+                Err(error) => {
+                    // an unfinished sequence goes on in the next byte
+                    let Some(length) = error.error_len() else {
+                        break Ok(());
+                    };
+                    let invalid: Vec<u8> = pending.drain(..error.valid_up_to() + length).collect();
+                    if let Err(error) = self.print(&String::from_utf8_lossy(&invalid), false) {
+                        break Err(error);
+                    }
                 }
+            }
+        };
+        self.utf8 = pending;
+        result
+    }
+
+    // writes out what the output holds back: the standard output is buffered, and only
+    // written when the program reads input, sleeps, prints to the error output or halts,
+    // and when run() returns
+    pub fn flush(&mut self) -> Result<(), RuntimeError> {
+        self.output
+            .flush()
+            .map_err(|_| self.error(RuntimeError::OutputFailed))
+    }
+
+    fn limit_exceeded(&self, limit: Limit) -> RuntimeError {
+        RuntimeError::LimitExceeded(self.code.token(self.pc), limit)
+    }
+
+    // fails unless `count` more values fit on the stack
+    fn room(&self, count: usize) -> Result<(), RuntimeError> {
+        let size = self.stack.len() + count;
+        if size > self.stack_size {
+            return Err(self.error(RuntimeError::StackOverflow));
+        }
+        match &self.usage {
+            Some(usage) if usage.limits.max_stack.is_some_and(|max| size > max) => {
+                Err(self.limit_exceeded(Limit::Stack))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn push(&mut self, value: u8) -> Result<(), RuntimeError> {
+        self.room(1)?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // where the value `depth` places below the top one (0 for the top) is in the stack, for
+    // the words that index into it; the depth comes from the program, so it can be anything
+    fn below_top(&self, depth: usize) -> Result<usize, RuntimeError> {
+        match depth
+            .checked_add(1)
+            .and_then(|count| self.stack.len().checked_sub(count))
+        {
+            Some(position) => Ok(position),
+            None => Err(self.error(RuntimeError::StackUnderflow)),
+        }
+    }
+
+    // the top `N` bytes, removed from the stack; nothing is removed if there are fewer
+    fn pop_n<const N: usize>(&mut self) -> Result<[u8; N], RuntimeError> {
+        let Some(&top) = self.stack.last_chunk::<N>() else {
+            return Err(self.error(RuntimeError::StackUnderflow));
+        };
+        self.stack.truncate(self.stack.len() - N);
+        Ok(top)
+    }
+
+    // SPAWN: a task starting at `target` with a copy of the stack, whose id is pushed
+    fn spawn(&mut self, target: usize) -> Result<(), RuntimeError> {
+        if self.tasks.is_empty() {
+            self.tasks.push(Task::default());
+        }
+        let id = self.tasks.len();
+        if id >= MAX_TASKS {
+            return Err(self.task_failed(TaskError::TooManyTasks));
+        }
+        self.room(1)?;
+        let mut stack = Vec::with_capacity(self.stack_size);
+        stack.extend_from_slice(&self.stack);
+        self.tasks.push(Task {
+            pc: target,
+            stack,
+            call_stack: vec![TASK_END],
+            handlers: Vec::new(),
+            result: None,
+        });
+        self.stack.push(id as u8);
+        Ok(())
+    }
+
+    // whether a task at `pc` with `stack` can't go on yet: it's in a JOIN for a task that
+    // hasn't finished, a SEND to a full channel or a RECV from an empty one
+    fn waiting(&self, pc: usize, stack: &[u8]) -> bool {
+        let channel = |id: &u8| self.channels.get(*id as usize);
+        match (self.code.ops.get(pc), stack) {
+            (Some(Op::Join), [.., id]) => self
+                .tasks
+                .get(*id as usize)
+                .is_some_and(|task| !task.finished()),
+            (Some(Op::Send), [.., _, id]) => {
+                channel(id).is_some_and(|queue| queue.len() >= CHANNEL_CAPACITY)
+            }
+            (Some(Op::Recv), [.., id]) => channel(id).is_some_and(VecDeque::is_empty),
+            _ => false,
+        }
+    }
+
+    // saves the running task and continues the next one that can go on, round-robin, which
+    // is the running task again if no other can; fails if none can
+    fn switch_task(&mut self) -> Result<(), RuntimeError> {
+        let count = self.tasks.len().max(1);
+        let runnable = |id: usize| match self.tasks.get(id) {
+            Some(task) if task.finished() => false,
+            _ if id == self.task => !self.waiting(self.pc, &self.stack),
+            Some(task) => !self.waiting(task.pc, &task.stack),
+            None => false,
+        };
+        let Some(next) = (1..=count)
+            .map(|offset| (self.task + offset) % count)
+            .find(|&id| runnable(id))
+        else {
+            return Err(self.task_failed(TaskError::Deadlock));
+        };
+        if next == self.task {
+            return Ok(());
+        }
+        let current = &mut self.tasks[self.task];
+        current.pc = self.pc;
+        mem::swap(&mut current.stack, &mut self.stack);
+        mem::swap(&mut current.call_stack, &mut self.call_stack);
+        mem::swap(&mut current.handlers, &mut self.handlers);
+        let next_task = &mut self.tasks[next];
+        self.pc = next_task.pc;
+        mem::swap(&mut next_task.stack, &mut self.stack);
+        mem::swap(&mut next_task.call_stack, &mut self.call_stack);
+        mem::swap(&mut next_task.handlers, &mut self.handlers);
+        self.task = next;
+        Ok(())
+    }
+
+    // a spawned task returned from the subroutine it started at
+    fn finish_task(&mut self) -> Result<(), RuntimeError> {
+        let result = self.stack.last().copied().unwrap_or(0);
+        if let Some(task) = self.tasks.get_mut(self.task) {
+            task.result = Some(result);
+        }
+        self.stack.clear();
+        self.switch_task()
+    }
+
+    fn task_failed(&self, error: TaskError) -> RuntimeError {
+        RuntimeError::TaskFailed(self.code.token(self.pc), error)
+    }
+
+    // the id of the running task, 0 for the program itself
+    pub fn task(&self) -> usize {
+        self.task
+    }
+
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        match self.code.ops.get(self.pc) {
+            Some(&op) if !self.halted => {
+                self.count_step()?;
+                self.execute(op)
+            }
+            _ => {
+                self.halted = true;
+                self.flush()
+            }
+        }
+    }
+
+    // like step(), but shows the machine after the instruction ran; None once the program
+    // has halted, so `while let Some(state) = program.step_with_state()?` runs it to the end
+    pub fn step_with_state(&mut self) -> Result<Option<MachineState<'_>>, RuntimeError> {
+        let index = self.pc;
+        let op = match self.code.ops.get(index) {
+            Some(&op) if !self.halted => op,
+            _ => {
+                self.halted = true;
+                self.flush()?;
+                return Ok(None);
+            }
+        };
+        self.count_step()?;
+        self.execute(op)?;
+        Ok(Some(MachineState {
+            index,
+            op,
+            pc: self.pc,
+            stack: &self.stack,
+            call_depth: self.call_stack.len(),
+            halted: self.halted,
+        }))
+    }
+
+    pub fn steps(&mut self) -> Steps<'_> {
+        Steps {
+            program: self,
+            failed: false,
+        }
+    }
+
+    // runs until the program halts or fails; count_step() inlined, keeping the count in a
+    // local until the end since this is the loop most programs spend their time in
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("run").entered();
+        let interrupt = self.interrupt;
+        let mut steps = 0;
+        let result = loop {
+            if self.halted {
+                break Ok(());
+            }
+            let Some(&op) = self.code.ops.get(self.pc) else {
+                self.halted = true;
+                continue;
+            };
+            if interrupt.is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+                break Err(self.limit_exceeded(Limit::Interrupted));
+            }
+            steps += 1;
+            if let Err(error) = self.execute(op) {
+                break Err(error);
+            }
+        };
+        self.steps += steps;
+        // what the program printed comes out before its error, if it failed
+        let flushed = self.flush();
+        result.and(flushed)
+    }
+
+    // counts an instruction that's about to run, unless the program was interrupted
+    fn count_step(&mut self) -> Result<(), RuntimeError> {
+        if self
+            .interrupt
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+        {
+            return Err(self.limit_exceeded(Limit::Interrupted));
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    // execute() counting the step against the limits and calling the observer back; the
+    // observer is taken out while the op runs, so it can look at the program
+    #[cold]
+    fn execute_instrumented(&mut self, op: Op) -> Result<(), RuntimeError> {
+        if let Some(usage) = &mut self.usage {
+            if let Err(limit) = usage.step() {
+                return Err(self.limit_exceeded(limit));
+            }
+        }
+        let Some(mut observer) = self.observer.take() else {
+            return self.execute_op(op);
+        };
+        observer.on_step(self, self.pc, op);
+        let before = self.stack.clone();
+        let result = self.execute_op(op);
+        let kept = before
+            .iter()
+            .zip(&self.stack)
+            .take_while(|(old, new)| old == new)
+            .count();
+        for &value in before[kept..].iter().rev() {
+            observer.on_pop(value);
+        }
+        for &value in &self.stack[kept..] {
+            observer.on_push(value);
+        }
+        match op {
+            Op::Call(target) if result.is_ok() => observer.on_call(self, target as usize),
+            Op::Execute if result.is_ok() => observer.on_call(self, self.pc),
+            Op::Return if result.is_ok() => observer.on_return(self),
+            _ => (),
+        }
+        self.observer = Some(observer);
+        result
+    }
+
+    fn execute(&mut self, op: Op) -> Result<(), RuntimeError> {
+        let result = if self.usage.is_some() || self.observer.is_some() {
+            self.execute_instrumented(op)
+        } else {
+            self.execute_op(op)
+        };
+        let result = match result {
+            Err(error) if !self.handlers.is_empty() => self.catch(error),
+            result => result,
+        };
+        // scanning everything after every step would make deep recursion quadratic, so
+        // that's only done once the run stops
+        #[cfg(debug_assertions)]
+        {
+            let checked = if self.halted || result.is_err() {
+                self.check_invariants()
+            } else {
+                self.check_step()
+            };
+            if let Err(error) = checked {
+                panic!("{} after {} at op {}", error, op.name(), self.pc);
+            }
+        }
+        result
+    }
+
+    // the part of check_invariants() a single op can break: the pc and stack of the running
+    // task, its newest return address and TRY, and the memory in use. Older return addresses
+    // and TRYs were checked when they were added, and other tasks while they ran
+    #[cfg(debug_assertions)]
+    fn check_step(&self) -> Result<(), InvariantError> {
+        let end = self.code.ops.len();
+        if let Some(address) = core::iter::once(self.pc)
+            .chain(self.handlers.last().map(|handler| handler.catch))
+            .find(|&address| address > end)
+        {
+            return Err(InvariantError::AddressOutOfRange(address));
+        }
+        if self.stack.len() > self.stack_size {
+            return Err(InvariantError::StackTooLarge(self.stack.len()));
+        }
+        if let Some(&address) = self
+            .call_stack
+            .last()
+            .filter(|&&address| address > end && address != TASK_END)
+        {
+            return Err(InvariantError::ReturnAddressOutOfRange(address));
+        }
+        let used = self.code.data.len() + self.allotted;
+        if used > MEMORY_SIZE {
+            return Err(InvariantError::MemoryTooLarge(used));
+        }
+        Ok(())
+    }
+
+    // continues after the CATCH of the innermost TRY with the error's code on the stack
+    // (see exceptions::code()), or fails with the error if nothing catches it. The stack
+    // and the call stack go back to their depth at the TRY; values popped since then come
+    // back as 0s
+    #[cold]
+    fn catch(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        let Some(code) = exceptions::code(&error) else {
+            return Err(error);
+        };
+        while let Some(handler) = self.handlers.pop() {
+            // RETURN drops the TRYs of the frames it leaves, but a snapshot from before it did
+            // can still have them
+            if handler.calls > self.call_stack.len() || handler.depth >= self.stack_size {
+                continue;
+            }
+            self.stack.resize(handler.depth, 0);
+            self.stack.push(code);
+            self.call_stack.truncate(handler.calls);
+            self.pc = handler.catch;
+            return Ok(());
+        }
+        Err(error)
+    }
+
+    // forgets the TRYs of frames deeper than `calls`: a subroutine that returned from inside a
+    // TRY would otherwise have its CATCH get the errors of whatever runs next at that depth
+    fn drop_handlers(&mut self, calls: usize) {
+        while self
+            .handlers
+            .last()
+            .is_some_and(|handler| handler.calls > calls)
+        {
+            self.handlers.pop();
+        }
+    }
+
+    fn execute_op(&mut self, op: Op) -> Result<(), RuntimeError> {
+        let mut next = self.pc + 1;
+        match op {
+            Op::Push(value) => self.push(value)?,
+            Op::Pop => {
+                self.pop()?;
+            }
+            Op::Dup => match self.stack.last() {
+                Some(&top) => self.push(top)?,
+                None => return Err(self.error(RuntimeError::StackUnderflow)),
             },
-            Token::Swap => match (self.stack.pop(), self.stack.pop()) {
-                (None, _) | (_, None) => {
-                    return Err(RuntimeError::StackUnderflow(current_token.clone()))
-                }
-                (Some(top), Some(bottom)) => {
-                    self.stack.push(top);
-                    self.stack.push(bottom);
-                    self.pc += 1;
-                }
+            Op::Swap => {
+                let [bottom, top] = self.pop_n()?;
+                self.stack.extend([top, bottom]);
+            }
+            Op::Over => match self.stack.last_chunk::<2>() {
+                Some(&[bottom, _]) => self.push(bottom)?,
+                None => return Err(self.error(RuntimeError::StackUnderflow)),
             },
-            Token::Over => match self.stack.last_chunk::<2>() {
-                None => return Err(RuntimeError::StackUnderflow(current_token.clone())),
-                Some(last_two) => {
-                    self.stack.push(last_two[0]);
-                    self.pc += 1;
+            Op::Nip => {
+                let [_, top] = self.pop_n()?;
+                self.stack.push(top);
+            }
+            Op::Tuck => {
+                let [bottom, top] = self.pop_n()?;
+                self.room(3)?;
+                self.stack.extend([top, bottom, top]);
+            }
+            Op::Rotate => {
+                let [bottom, middle, top] = self.pop_n()?;
+                self.stack.extend([middle, top, bottom]);
+            }
+            Op::PickN(_) | Op::Pick => {
+                let index = match op {
+                    Op::PickN(index) => index as usize,
+                    _ => self.pop()? as usize,
+                };
+                let value = self.stack[self.below_top(index)?];
+                self.push(value)?;
+            }
+            Op::RollN(_) | Op::Roll => {
+                let count = match op {
+                    Op::RollN(count) => count as usize,
+                    _ => self.pop()? as usize,
+                };
+                if let Some(depth) = count.checked_sub(1) {
+                    let value = self.stack.remove(self.below_top(depth)?);
+                    self.stack.push(value);
                 }
-            },
-            Token::Rotate => match (self.stack.pop(), self.stack.pop(), self.stack.pop()) {
-                (None, _, _) | (_, None, _) | (_, _, None) => {
-                    return Err(RuntimeError::StackUnderflow(current_token.clone()))
+            }
+            Op::Add => {
+                let [bottom, top] = self.pop_n()?;
+                self.stack.push(top.wrapping_add(bottom));
+            }
+            Op::Sub => {
+                let [bottom, top] = self.pop_n()?;
+                self.stack.push(bottom.wrapping_sub(top));
+            }
+            Op::Random => {
+                let value = self.rng.next_byte();
+                self.push(value)?;
+            }
+            Op::Assert => {
+                if self.pop()? == 0 {
+                    return Err(self.error(RuntimeError::AssertionFailed));
                 }
-                (Some(top), Some(middle), Some(bottom)) => {
-                    self.stack.push(middle);
-                    self.stack.push(top);
-                    self.stack.push(bottom);
-                    self.pc += 1;
+            }
+            Op::AssertEq => {
+                let [bottom, top] = self.pop_n()?;
+                if top != bottom {
+                    return Err(self.error(RuntimeError::AssertionFailed));
                 }
-            },
-            Token::Pick(index) => {
-                let value = match self.stack.get(self.stack.len() - 1 - index) {
-                    None => return Err(RuntimeError::StackUnderflow(current_token.clone())),
-                    Some(&value) => value,
+            }
+            Op::PrintByte
+            | Op::PrintChar
+            | Op::PrintHex
+            | Op::EprintByte
+            | Op::EprintChar
+            | Op::PrintNewline
+            | Op::PrintSpace => {
+                let mut buffer = [0; 4];
+                let text = match (op, self.stack.last()) {
+                    (Op::PrintNewline, _) => "\n",
+                    (Op::PrintSpace, _) => " ",
+                    (_, None) => return Err(self.error(RuntimeError::StackUnderflow)),
+                    (_, Some(&top)) => value_text(op, top, &mut buffer),
                 };
-                self.stack.push(value);
-                self.pc += 1;
+                self.print(text, matches!(op, Op::EprintByte | Op::EprintChar))?;
+                if !matches!(op, Op::PrintNewline | Op::PrintSpace) {
+                    self.stack.pop();
+                }
+            }
+            Op::PrintUtf8 => {
+                let Some(&byte) = self.stack.last() else {
+                    return Err(self.error(RuntimeError::StackUnderflow));
+                };
+                self.print_utf8(byte)?;
+                self.stack.pop();
             }
-            Token::BinOp(bin_op) => match (self.stack.pop(), self.stack.pop()) {
-                (None, _) | (_, None) => {
-                    return Err(RuntimeError::StackUnderflow(current_token.clone()))
+            // like .s in Forth: the depth and the values from the bottom up
+            Op::PrintStack => {
+                let mut text = format!("<{}>", self.stack.len());
+                for value in &self.stack {
+                    let _ = write!(text, " {}", value);
                 }
-                (Some(top), Some(bottom)) => {
-                    let result = match bin_op {
-                        BinOp::Add => top.overflowing_add(bottom).0,
-                        BinOp::Sub => bottom.overflowing_sub(top).0,
-                    };
-                    self.stack.push(result);
-                    self.pc += 1;
+                text.push('\n');
+                self.print(&text, true)?;
+            }
+            Op::ReadChar => {
+                self.room(1)?;
+                self.flush()?;
+                // end of input (or an unreadable input) reads as 0, and EOF tells it apart
+                let value = self.input.read_byte();
+                self.end_of_input = value.is_none();
+                self.stack.push(value.unwrap_or(0));
+            }
+            // the line goes into memory without its newline, wrapping around past address 255
+            Op::ReadLine => {
+                let [address, max] = self.pop_n()?;
+                self.flush()?;
+                let mut length = 0;
+                self.end_of_input = false;
+                while length < max {
+                    match self.input.read_byte() {
+                        None => {
+                            self.end_of_input = true;
+                            break;
+                        }
+                        Some(b'\n') => break,
+                        Some(byte) => self.memory[usize::from(address.wrapping_add(length))] = byte,
+                    }
+                    length += 1;
                 }
-            },
-            Token::PrintByte | Token::PrintChar => match self.stack.pop() {
-                None => return Err(RuntimeError::StackUnderflow(current_token.clone())),
-                Some(top) => {
-                    if let Token::PrintByte = &current_token.token {
-                        print!("{}", top);
-                    };
-                    if let Token::PrintChar = &current_token.token {
-                        let character = char::from(top);
-                        print!("{}", character);
+                self.stack.push(length);
+            }
+            // the value and 1, or 0 and 0 unless there are only digits and they fit in a byte
+            Op::ParseNum => {
+                let [address, length] = self.pop_n()?;
+                let mut value = (length > 0).then_some(0u8);
+                for index in 0..length {
+                    let digit = self.memory[usize::from(address.wrapping_add(index))];
+                    value = value
+                        .filter(|_| digit.is_ascii_digit())
+                        .and_then(|value| value.checked_mul(10)?.checked_add(digit - b'0'));
+                }
+                self.stack
+                    .extend([value.unwrap_or(0), u8::from(value.is_some())]);
+            }
+            Op::Sleep => {
+                let [milliseconds] = self.pop_n()?;
+                self.flush()?;
+                self.sleep(milliseconds.into())?;
+            }
+            // the milliseconds since the program started, modulo 65536, with the low byte on top
+            Op::Ticks => {
+                self.room(2)?;
+                let [high, low] = (self.clock.now() as u16).to_be_bytes();
+                self.stack.extend([high, low]);
+            }
+            // every file word pushes a status, 0 or the code of the FileError
+            Op::Fopen => {
+                let [address, length, mode] = self.pop_n()?;
+                let (handle, status) = match self.open_file(address, length, mode) {
+                    Ok(handle) => (handle, 0),
+                    Err(error) => (0, error.code()),
+                };
+                self.stack.extend([handle, status]);
+            }
+            // a value longer than the maximum is cut off after it
+            Op::Env => {
+                let [name, name_length, address, max] = self.pop_n()?;
+                let (length, status) = match self.env(name, name_length) {
+                    Ok(value) => {
+                        let length = value.len().min(max.into()) as u8;
+                        for (index, &byte) in (0..length).zip(&value) {
+                            self.memory[usize::from(address.wrapping_add(index))] = byte;
+                        }
+                        (length, 0)
                     }
-                    self.pc += 1;
+                    Err(error) => (0, error.code()),
+                };
+                self.stack.extend([length, status]);
+            }
+            Op::Fread => {
+                let [handle] = self.pop_n()?;
+                self.room(1)?;
+                let (byte, status) = match self.file(handle).and_then(|file| file.read_byte()) {
+                    Ok(Some(byte)) => (byte, 0),
+                    Ok(None) => (0, FileError::EndOfFile.code()),
+                    Err(error) => (0, error.code()),
+                };
+                self.stack.extend([byte, status]);
+            }
+            Op::Fwrite => {
+                let [byte, handle] = self.pop_n()?;
+                let result = self.file(handle).and_then(|file| file.write_byte(byte));
+                self.stack.push(result.err().map_or(0, FileError::code));
+            }
+            // the handle is free again even if the buffered bytes couldn't be written
+            Op::Fclose => {
+                let [handle] = self.pop_n()?;
+                let result = self.file(handle).and_then(|file| file.close());
+                if result != Err(FileError::BadHandle) {
+                    self.files[usize::from(handle)] = None;
                 }
-            },
-            Token::If => {
-                let top = match self.stack.last() {
-                    Some(&top) => top,
-                    None => return Err(RuntimeError::StackUnderflow(current_token.clone())),
+                self.stack.push(result.err().map_or(0, FileError::code));
+            }
+            // every socket word pushes a status like the file words, the code of the NetError
+            Op::TcpListen => {
+                let [high, low] = self.pop_n()?;
+                let result = match &mut self.network {
+                    Some(network) => network.listen(u16::from_be_bytes([high, low])),
+                    None => Err(NetError::Disabled),
                 };
-
-                if top > 0 {
-                    self.pc += 1;
-                } else {
-                    let mut depth = 1;
-                    let mut found_else = false;
-                    let mut found_then = false;
-                    while !(depth == 0 && found_then || depth == 1 && found_else) {
-                        self.pc += 1;
-                        if self.pc >= self.tokens.len() {
-                            return Err(RuntimeError::UnclosedIfStatement(current_token.clone()));
+                let result =
+                    result.and_then(|listener| self.add_socket(Socket::Listener(listener)));
+                let (handle, status) =
+                    result.map_or_else(|error| (0, error.code()), |handle| (handle, 0));
+                self.stack.extend([handle, status]);
+            }
+            Op::TcpConnect => {
+                let [address, length, high, low] = self.pop_n()?;
+                let result = match (self.network.is_some(), self.text(address, length)) {
+                    (false, _) => Err(NetError::Disabled),
+                    (true, None) => Err(NetError::BadName),
+                    (true, Some(host)) => {
+                        let network = self.network.as_mut().unwrap();
+                        network.connect(&host, u16::from_be_bytes([high, low]))
+                    }
+                };
+                let result =
+                    result.and_then(|connection| self.add_socket(Socket::Connection(connection)));
+                let (handle, status) =
+                    result.map_or_else(|error| (0, error.code()), |handle| (handle, 0));
+                self.stack.extend([handle, status]);
+            }
+            // TCP_ACCEPT and TCP_RECV don't wait while another task can go on: they stay where
+            // they are and run again after it, like JOIN, SEND and RECV
+            Op::TcpAccept => {
+                let Some(&handle) = self.stack.last() else {
+                    return Err(self.error(RuntimeError::StackUnderflow));
+                };
+                self.room(1)?;
+                let wait = !self.others_can_run();
+                let result = match self.socket(handle) {
+                    Ok(Socket::Listener(listener)) => listener.accept(wait),
+                    Ok(Socket::Connection(_)) => Err(NetError::BadHandle),
+                    Err(error) => Err(error),
+                };
+                let result = match result {
+                    Ok(None) => return self.poll_later(),
+                    Ok(Some(connection)) => self.add_socket(Socket::Connection(connection)),
+                    Err(error) => Err(error),
+                };
+                let (handle, status) =
+                    result.map_or_else(|error| (0, error.code()), |handle| (handle, 0));
+                self.idle_polls = 0;
+                self.stack.pop();
+                self.stack.extend([handle, status]);
+            }
+            Op::TcpRecv => {
+                let Some(&handle) = self.stack.last() else {
+                    return Err(self.error(RuntimeError::StackUnderflow));
+                };
+                self.room(1)?;
+                let wait = !self.others_can_run();
+                let (byte, status) = match self
+                    .connection(handle)
+                    .and_then(|connection| connection.receive(wait))
+                {
+                    Ok(None) => return self.poll_later(),
+                    Ok(Some(byte)) => (byte, 0),
+                    Err(error) => (0, error.code()),
+                };
+                self.idle_polls = 0;
+                self.stack.pop();
+                self.stack.extend([byte, status]);
+            }
+            Op::TcpSend => {
+                let [byte, handle] = self.pop_n()?;
+                let result = self
+                    .connection(handle)
+                    .and_then(|connection| connection.send(byte));
+                self.stack.push(result.err().map_or(0, NetError::code));
+            }
+            // like FCLOSE, the handle is free again even if closing failed
+            Op::TcpClose => {
+                let [handle] = self.pop_n()?;
+                let result = match self.socket(handle) {
+                    Ok(Socket::Connection(connection)) => connection.close(),
+                    Ok(Socket::Listener(_)) => Ok(()),
+                    Err(error) => Err(error),
+                };
+                if result != Err(NetError::BadHandle) {
+                    self.sockets[usize::from(handle)] = None;
+                }
+                self.stack.push(result.err().map_or(0, NetError::code));
+            }
+            Op::Key => {
+                self.room(1)?;
+                self.flush()?;
+                let key = match &mut self.keyboard {
+                    Some(keyboard) => keyboard.read_key(),
+                    None => self.input.read_byte(),
+                };
+                // no more keys reads as 0, and EOF tells it apart
+                self.end_of_input = key.is_none();
+                self.stack.push(key.unwrap_or(0));
+            }
+            // [key][1], or [0][0] if no key was pressed
+            Op::KeyPoll => {
+                self.room(2)?;
+                self.flush()?;
+                let key = match &mut self.keyboard {
+                    Some(keyboard) => keyboard.poll_key(),
+                    None => {
+                        let key = self.input.read_byte();
+                        self.end_of_input = key.is_none();
+                        key
+                    }
+                };
+                self.stack
+                    .extend([key.unwrap_or(0), u8::from(key.is_some())]);
+            }
+            Op::ClearScreen => self.print(terminal::CLEAR_SCREEN, false)?,
+            Op::CursorAt => {
+                let [column, row] = self.pop_n()?;
+                self.print(&terminal::cursor_at(column, row), false)?;
+            }
+            // a new frame, all black, of the width and height it pops
+            Op::GfxInit => {
+                let [width, height] = self.pop_n()?;
+                self.frame = Some(Framebuffer::new(width.into(), height.into()));
+            }
+            // [x][y][value]; nothing is drawn before GFX_INIT
+            Op::Pixel => {
+                let [x, y, value] = self.pop_n()?;
+                if let Some(frame) = &mut self.frame {
+                    frame.set(x.into(), y.into(), value);
+                }
+            }
+            Op::GfxFlush => {
+                if let (Some(screen), Some(frame)) = (&mut self.screen, &self.frame) {
+                    match screen.show(frame) {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            self.halted = true;
+                            next = self.pc;
                         }
-                        found_else = false;
-                        found_then = false;
-                        match self.tokens[self.pc].token {
-                            Token::If => {
-                                depth += 1;
-                            }
-                            Token::Else => {
-                                found_else = true;
-                            }
-                            Token::Then => {
-                                found_then = true;
-                                depth -= 1;
-                            }
-                            _ => (),
+                        Err(message) => {
+                            return Err(RuntimeError::HostWordFailed(
+                                self.code.token(self.pc),
+                                message,
+                            ))
                         }
                     }
-                    self.pc += 1;
                 }
             }
-            Token::Else => {
-                let mut depth = 1;
-                let mut found_then = false;
-                while !(depth == 0 && found_then) {
-                    self.pc += 1;
-                    if self.pc >= self.tokens.len() {
-                        return Err(RuntimeError::UnclosedIfStatement(current_token.clone()));
+            Op::Eof => {
+                self.room(1)?;
+                self.stack.push(u8::from(self.end_of_input));
+            }
+            // addresses are bytes, so every one of them has a cell
+            Op::Fetch => {
+                let address = self.pop()?;
+                self.stack.push(self.memory[usize::from(address)]);
+            }
+            Op::Store => {
+                let [value, address] = self.pop_n()?;
+                self.memory[usize::from(address)] = value;
+            }
+            // the cells after the declared ones, in the order they're allotted; the base
+            // address has to fit in a byte even for 0 cells
+            Op::Allot => {
+                let Some(&count) = self.stack.last() else {
+                    return Err(self.error(RuntimeError::StackUnderflow));
+                };
+                let base = self.code.data.len() + self.allotted;
+                let end = base + usize::from(count);
+                if let Some(usage) = &self.usage {
+                    if usage.limits.max_memory.is_some_and(|max| end > max) {
+                        return Err(self.limit_exceeded(Limit::Memory));
                     }
-                    found_then = false;
-                    match self.tokens[self.pc].token {
-                        Token::If => {
-                            depth += 1;
-                        }
-                        Token::Then => {
-                            found_then = true;
-                            depth -= 1;
+                }
+                if end > MEMORY_SIZE || base == MEMORY_SIZE {
+                    return Err(self.error(RuntimeError::OutOfMemory));
+                }
+                self.allotted += usize::from(count);
+                *self.stack.last_mut().unwrap() = base as u8;
+            }
+            // the index wraps around like the rest of the arithmetic
+            Op::FetchAt => {
+                let [base, index] = self.pop_n()?;
+                self.stack
+                    .push(self.memory[usize::from(base.wrapping_add(index))]);
+            }
+            Op::StoreAt => {
+                let [value, base, index] = self.pop_n()?;
+                self.memory[usize::from(base.wrapping_add(index))] = value;
+            }
+            Op::JumpIfZero(target) => match self.stack.last() {
+                None => return Err(self.error(RuntimeError::StackUnderflow)),
+                Some(0) if target == UNCLOSED => {
+                    return Err(self.error(RuntimeError::UnclosedIfStatement))
+                }
+                Some(0) => next = target as usize,
+                Some(_) => (),
+            },
+            Op::Jump(target) => {
+                if target == UNCLOSED {
+                    return Err(self.error(RuntimeError::UnclosedIfStatement));
+                }
+                next = target as usize;
+            }
+            Op::Nop => (),
+            Op::Of(end_of) => {
+                if end_of == UNCLOSED {
+                    return Err(self.error(RuntimeError::UnclosedIfStatement));
+                }
+                let [selector, value] = self.pop_n()?;
+                if selector != value {
+                    self.stack.push(selector);
+                    next = end_of as usize;
+                }
+            }
+            Op::Try(catch) => {
+                if catch == UNCLOSED {
+                    return Err(self.error(RuntimeError::UnclosedIfStatement));
+                }
+                self.handlers.push(Handler {
+                    catch: catch as usize,
+                    depth: self.stack.len(),
+                    calls: self.call_stack.len(),
+                });
+            }
+            Op::Catch(end) => {
+                if end == UNCLOSED {
+                    return Err(self.error(RuntimeError::UnclosedIfStatement));
+                }
+                self.handlers.pop();
+                next = end as usize;
+            }
+            Op::Throw => {
+                let code = self.pop()?;
+                return Err(RuntimeError::Thrown(self.code.token(self.pc), code));
+            }
+            Op::Call(_) | Op::Execute => {
+                let target = match op {
+                    Op::Call(target) => target,
+                    // EXECUTE calls the subroutine at the address it pops
+                    _ => {
+                        let address = self.pop()?;
+                        let target = self.code.addresses.get(usize::from(address));
+                        target.map_or(UNCLOSED, |&target| target as u32)
+                    }
+                };
+                if target == UNCLOSED {
+                    return Err(self.error(RuntimeError::InvalidLabel));
+                }
+                // a tail call (only THENs between it and a RETURN) returns straight to our
+                // caller, so the frame is reused and recursion in tail position runs in
+                // constant space. Without a caller the RETURN has to run, and fail
+                let mut rest = self.code.ops[next..].iter().filter(|&&op| op != Op::Nop);
+                let tail_call = rest.next() == Some(&Op::Return);
+                if !tail_call || self.call_stack.is_empty() {
+                    if let Some(usage) = &self.usage {
+                        if usage
+                            .limits
+                            .max_call_depth
+                            .is_some_and(|max| self.call_stack.len() >= max)
+                        {
+                            return Err(self.limit_exceeded(Limit::CallDepth));
                         }
-                        _ => (),
                     }
+                    self.call_stack.push(next);
                 }
+                next = target as usize;
             }
-            Token::Then => {
-                self.pc += 1;
+            Op::Address(address) => self.push(address as u8)?,
+            Op::Native(index) => {
+                let name = self.code.natives.get(index as usize);
+                let Some((_, word)) = self
+                    .words
+                    .iter_mut()
+                    .find(|(registered, _)| Some(registered) == name)
+                else {
+                    return Err(self.error(RuntimeError::InvalidLabel));
+                };
+                // host words see the stack limit as the stack size
+                let limit = self.usage.as_ref().and_then(|usage| usage.limits.max_stack);
+                let mut stack = HostStack {
+                    stack: &mut self.stack,
+                    size: limit.map_or(self.stack_size, |max| max.min(self.stack_size)),
+                };
+                match word(&mut stack) {
+                    Ok(()) => (),
+                    Err(HostError::StackUnderflow) => {
+                        return Err(self.error(RuntimeError::StackUnderflow))
+                    }
+                    Err(HostError::StackOverflow) => {
+                        return Err(self.error(RuntimeError::StackOverflow))
+                    }
+                    Err(HostError::Failed(message)) => {
+                        return Err(RuntimeError::HostWordFailed(
+                            self.code.token(self.pc),
+                            message,
+                        ))
+                    }
+                }
+            }
+            Op::Spawn(target) => {
+                if target == UNCLOSED {
+                    return Err(self.error(RuntimeError::InvalidLabel));
+                }
+                self.spawn(target as usize)?;
+            }
+            Op::Yield => {
+                self.idle_polls = 0;
+                self.pc = next;
+                return self.switch_task();
+            }
+            // JOIN, SEND and RECV that have to wait stay where they are and run again once
+            // the scheduler finds they can go on
+            Op::Join => {
+                let Some(&id) = self.stack.last() else {
+                    return Err(self.error(RuntimeError::StackUnderflow));
+                };
+                let Some(joined) = self.tasks.get(id as usize) else {
+                    return Err(self.task_failed(TaskError::UnknownTask(id)));
+                };
+                let Some(result) = joined.result else {
+                    return self.switch_task();
+                };
+                self.stack.pop();
+                self.stack.push(result);
+            }
+            Op::ChanNew => {
+                let id = self.channels.len();
+                if id >= MAX_CHANNELS {
+                    return Err(self.task_failed(TaskError::TooManyChannels));
+                }
+                self.room(1)?;
+                self.channels.push(VecDeque::new());
+                self.stack.push(id as u8);
             }
-            Token::Call(label) => match self.labels.get(label) {
-                None => return Err(RuntimeError::InvalidLabel(current_token.clone())),
+            Op::Send => {
+                let Some(&[value, id]) = self.stack.last_chunk::<2>() else {
+                    return Err(self.error(RuntimeError::StackUnderflow));
+                };
+                let Some(queue) = self.channels.get_mut(id as usize) else {
+                    return Err(self.task_failed(TaskError::UnknownChannel(id)));
+                };
+                if queue.len() >= CHANNEL_CAPACITY {
+                    return self.switch_task();
+                }
+                queue.push_back(value);
+                self.stack.truncate(self.stack.len() - 2);
+            }
+            Op::Recv => {
+                let Some(&id) = self.stack.last() else {
+                    return Err(self.error(RuntimeError::StackUnderflow));
+                };
+                let Some(queue) = self.channels.get_mut(id as usize) else {
+                    return Err(self.task_failed(TaskError::UnknownChannel(id)));
+                };
+                let Some(value) = queue.pop_front() else {
+                    return self.switch_task();
+                };
+                self.stack.pop();
+                self.stack.push(value);
+            }
+            Op::Return => match self.call_stack.pop() {
+                Some(TASK_END) => return self.finish_task(),
                 Some(index) => {
-                    self.call_stack.push(self.pc + 1);
-                    self.pc = index.clone();
+                    self.drop_handlers(self.call_stack.len());
+                    next = index;
                 }
+                None => return Err(self.error(RuntimeError::CallStackUnderflow)),
             },
-            Token::Return => {
-                self.pc = match self.call_stack.pop() {
-                    Some(index) => index,
-                    None => return Err(RuntimeError::CallStackUnderflow(current_token.clone())),
-                };
+            Op::Halt => {
+                self.flush()?;
+                self.halted = true;
+                next = self.pc;
             }
-            Token::Halt => {
+            Op::Exit => {
+                self.exit_code = Some(self.pop()?);
+                self.flush()?;
                 self.halted = true;
+                next = self.pc;
             }
-        };
+        }
+        self.pc = next;
         Ok(())
     }
 
@@ -401,7 +2900,7 @@ impl Program {
     }
 
     pub fn _lines_as_string(&self) -> String {
-        format!("{:?}", &self.lines)
+        format!("{:?}", self.source.lines().collect::<Vec<_>>())
     }
 
     pub fn _tokens_as_string(&self) -> String {
@@ -412,3 +2911,30 @@ impl Program {
         format!("{:?}", &self.stack)
     }
 }
+
+// what the print word `op` prints for `value`, written into `buffer` so printing in a loop
+// doesn't allocate: the number in decimal or hex, or the character
+fn value_text(op: Op, value: u8, buffer: &mut [u8; 4]) -> &str {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    match op {
+        Op::PrintByte | Op::EprintByte => {
+            let mut start = 3;
+            let mut rest = value;
+            loop {
+                start -= 1;
+                buffer[start] = b'0' + rest % 10;
+                rest /= 10;
+                if rest == 0 {
+                    break;
+                }
+            }
+            core::str::from_utf8(&buffer[start..3]).unwrap()
+        }
+        Op::PrintHex => {
+            buffer[0] = HEX[usize::from(value >> 4)];
+            buffer[1] = HEX[usize::from(value & 15)];
+            core::str::from_utf8(&buffer[..2]).unwrap()
+        }
+        _ => char::from(value).encode_utf8(buffer),
+    }
+}
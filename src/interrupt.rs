@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// set by Ctrl-C while a program runs, which stops it before the next instruction with
+// Limit::Interrupted (see Program::set_interrupt)
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// catches Ctrl-C until it's dropped. A second Ctrl-C before the program stops, when it's
+// waiting for input or sleeping, ends the process as usual
+pub struct Handler;
+
+impl Handler {
+    pub fn install() -> Self {
+        reset();
+        Handler
+    }
+}
+
+impl Drop for Handler {
+    fn drop(&mut self) {
+        sys::uninstall();
+    }
+}
+
+// forgets an interrupt that was handled, catching the next one
+pub fn reset() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+    sys::install();
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::{Ordering, INTERRUPTED};
+
+    const SIGINT: i32 = 2;
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+
+    extern "C" fn on_interrupt(_: i32) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        // SAFETY: signal() may be called from a signal handler
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+        }
+    }
+
+    pub fn install() {
+        // SAFETY: the handler only stores to an atomic and calls signal()
+        unsafe {
+            signal(SIGINT, on_interrupt as extern "C" fn(i32) as usize);
+        }
+    }
+
+    pub fn uninstall() {
+        // SAFETY: restores the default action
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::{Ordering, INTERRUPTED};
+
+    const CTRL_C_EVENT: u32 = 0;
+
+    type HandlerRoutine = unsafe extern "system" fn(u32) -> i32;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<HandlerRoutine>, add: i32) -> i32;
+    }
+
+    // runs on a thread of its own; returning 0 lets the next handler, the default one that
+    // ends the process, have the event
+    unsafe extern "system" fn on_interrupt(event: u32) -> i32 {
+        if event != CTRL_C_EVENT || INTERRUPTED.swap(true, Ordering::Relaxed) {
+            return 0;
+        }
+        1
+    }
+
+    pub fn install() {
+        // SAFETY: adding the same handler twice only calls it twice, so it's removed first
+        unsafe {
+            SetConsoleCtrlHandler(Some(on_interrupt), 0);
+            SetConsoleCtrlHandler(Some(on_interrupt), 1);
+        }
+    }
+
+    pub fn uninstall() {
+        // SAFETY: removes the handler added by install()
+        unsafe {
+            SetConsoleCtrlHandler(Some(on_interrupt), 0);
+        }
+    }
+}
+
+// elsewhere Ctrl-C isn't caught
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    pub fn install() {}
+
+    pub fn uninstall() {}
+}
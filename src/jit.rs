@@ -0,0 +1,414 @@
+use std::mem;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{self, types, AbiParam, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use crate::bytecode::{Op, UNCLOSED};
+use crate::interpreter::{Program, RuntimeError};
+
+// how often the interpreter runs an instruction before the code starting there is compiled
+const HOT_THRESHOLD: u32 = 50;
+// the longest ROLL that is compiled, it gets unrolled
+const MAX_NATIVE_ROLL: u32 = 8;
+// set in a native block's result when it stopped in front of an instruction that would
+// fail, which the interpreter then runs to report the error
+const BAIL: u64 = 1 << 63;
+
+// runs the ops of a straight-line piece of code on the stack's buffer and length, and
+// returns the index of the next op to run
+type NativeBlock = unsafe extern "C" fn(*mut u8, *mut u64) -> u64;
+
+enum Block {
+    // run by the interpreter this many times so far
+    Cold(u32),
+    Native(NativeBlock),
+    // nothing to compile at this op
+    Interpreted,
+}
+
+// whether native code can run the op; ops that leave the straight line end a block
+fn is_native(op: Op) -> bool {
+    match op {
+        Op::Push(_)
+        | Op::Pop
+        | Op::Dup
+        | Op::Swap
+        | Op::Rotate
+        | Op::Over
+        | Op::Nip
+        | Op::Tuck
+        | Op::PickN(_)
+        | Op::Add
+        | Op::Sub
+        | Op::Assert
+        | Op::AssertEq
+        | Op::Nop => true,
+        Op::RollN(count) => count <= MAX_NATIVE_ROLL,
+        Op::JumpIfZero(_) => true,
+        Op::Jump(target) => target != UNCLOSED,
+        _ => false,
+    }
+}
+
+// compiles the code that runs often to native code while the interpreter runs the rest
+pub struct Jit {
+    module: JITModule,
+    context: FunctionBuilderContext,
+    stack_size: usize,
+}
+
+// emits the ops of one block; `length` is the stack length and `exit` returns its
+// second parameter after storing the first as the new length
+struct Emitter<'a, 'b> {
+    builder: &'a mut FunctionBuilder<'b>,
+    base: Value,
+    length: Variable,
+    exit: ir::Block,
+    stack_size: usize,
+}
+
+impl Emitter<'_, '_> {
+    fn length(&mut self) -> Value {
+        self.builder.use_var(self.length)
+    }
+
+    fn set_length(&mut self, change: i64) {
+        let length = self.length();
+        let length = self.builder.ins().iadd_imm(length, change);
+        self.builder.def_var(self.length, length);
+    }
+
+    // address of the byte `offset` places below the top of the stack
+    fn slot(&mut self, offset: i64) -> Value {
+        let length = self.length();
+        let address = self.builder.ins().iadd(self.base, length);
+        self.builder.ins().iadd_imm(address, -offset)
+    }
+
+    fn load(&mut self, offset: i64) -> Value {
+        let address = self.slot(offset);
+        self.builder
+            .ins()
+            .uload8(types::I32, MemFlags::trusted(), address, 0)
+    }
+
+    fn store(&mut self, offset: i64, value: Value) {
+        let address = self.slot(offset);
+        self.builder
+            .ins()
+            .istore8(MemFlags::trusted(), value, address, 0);
+    }
+
+    // leaves the block with `next` as the next op
+    fn leave(&mut self, next: u64) {
+        let length = self.length();
+        let next = self.builder.ins().iconst(types::I64, next as i64);
+        self.builder.ins().jump(self.exit, &[length, next]);
+    }
+
+    // bails out in front of the op at `index` if the condition holds
+    fn bail_if(&mut self, condition: Value, index: usize) {
+        let bail = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, bail, &[], next, &[]);
+        self.builder.switch_to_block(bail);
+        self.builder.seal_block(bail);
+        self.leave(index as u64 | BAIL);
+        self.builder.switch_to_block(next);
+        self.builder.seal_block(next);
+    }
+
+    fn need(&mut self, count: i64, index: usize) {
+        let length = self.length();
+        let condition = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::UnsignedLessThan, length, count);
+        self.bail_if(condition, index);
+    }
+
+    fn room(&mut self, count: i64, index: usize) {
+        let length = self.length();
+        let condition = match (self.stack_size as u64).checked_sub(count as u64) {
+            Some(limit) => {
+                self.builder
+                    .ins()
+                    .icmp_imm(IntCC::UnsignedGreaterThan, length, limit as i64)
+            }
+            // a stack smaller than that always overflows
+            None => self.builder.ins().iconst(types::I8, 1),
+        };
+        self.bail_if(condition, index);
+    }
+
+    fn push(&mut self, value: Value) {
+        self.store(0, value);
+        self.set_length(1);
+    }
+
+    // emits one op; returns false if it ended the block
+    fn op(&mut self, index: usize, op: Op) -> bool {
+        match op {
+            Op::Push(value) => {
+                self.room(1, index);
+                let value = self.builder.ins().iconst(types::I32, i64::from(value));
+                self.push(value);
+            }
+            Op::Pop => {
+                self.need(1, index);
+                self.set_length(-1);
+            }
+            Op::Dup => {
+                self.need(1, index);
+                self.room(1, index);
+                let top = self.load(1);
+                self.push(top);
+            }
+            Op::Swap => {
+                self.need(2, index);
+                let (bottom, top) = (self.load(2), self.load(1));
+                self.store(2, top);
+                self.store(1, bottom);
+            }
+            Op::Rotate => {
+                self.need(3, index);
+                let (bottom, middle, top) = (self.load(3), self.load(2), self.load(1));
+                self.store(3, middle);
+                self.store(2, top);
+                self.store(1, bottom);
+            }
+            Op::Over => {
+                self.need(2, index);
+                self.room(1, index);
+                let bottom = self.load(2);
+                self.push(bottom);
+            }
+            Op::Nip => {
+                self.need(2, index);
+                let top = self.load(1);
+                self.store(2, top);
+                self.set_length(-1);
+            }
+            Op::Tuck => {
+                self.need(2, index);
+                self.room(1, index);
+                let (bottom, top) = (self.load(2), self.load(1));
+                self.store(2, top);
+                self.store(1, bottom);
+                self.push(top);
+            }
+            Op::PickN(depth) => {
+                self.need(i64::from(depth) + 1, index);
+                self.room(1, index);
+                let value = self.load(i64::from(depth) + 1);
+                self.push(value);
+            }
+            Op::RollN(count) => {
+                self.need(count.into(), index);
+                if count > 0 {
+                    let values: Vec<Value> = (1..=count.into())
+                        .rev()
+                        .map(|offset| self.load(offset))
+                        .collect();
+                    // the bottom one goes to the top
+                    for (offset, &value) in (1..=count.into())
+                        .rev()
+                        .zip(values[1..].iter().chain(&values[..1]))
+                    {
+                        self.store(offset, value);
+                    }
+                }
+            }
+            Op::Add | Op::Sub => {
+                self.need(2, index);
+                let (bottom, top) = (self.load(2), self.load(1));
+                let result = match op {
+                    Op::Add => self.builder.ins().iadd(bottom, top),
+                    _ => self.builder.ins().isub(bottom, top),
+                };
+                self.store(2, result);
+                self.set_length(-1);
+            }
+            Op::Assert => {
+                self.need(1, index);
+                let top = self.load(1);
+                let failed = self.builder.ins().icmp_imm(IntCC::Equal, top, 0);
+                self.bail_if(failed, index);
+                self.set_length(-1);
+            }
+            Op::AssertEq => {
+                self.need(2, index);
+                let (bottom, top) = (self.load(2), self.load(1));
+                let failed = self.builder.ins().icmp(IntCC::NotEqual, bottom, top);
+                self.bail_if(failed, index);
+                self.set_length(-2);
+            }
+            Op::Nop => (),
+            Op::JumpIfZero(target) => {
+                self.need(1, index);
+                let top = self.load(1);
+                let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, top, 0);
+                let jump = self.builder.create_block();
+                let next = self.builder.create_block();
+                self.builder.ins().brif(is_zero, jump, &[], next, &[]);
+                self.builder.switch_to_block(jump);
+                self.builder.seal_block(jump);
+                // an IF without THEN fails when it would jump
+                match target {
+                    UNCLOSED => self.leave(index as u64 | BAIL),
+                    target => self.leave(target.into()),
+                }
+                self.builder.switch_to_block(next);
+                self.builder.seal_block(next);
+                self.leave(index as u64 + 1);
+                return false;
+            }
+            Op::Jump(target) => {
+                self.leave(target.into());
+                return false;
+            }
+            _ => unreachable!("not a native op"),
+        }
+        true
+    }
+}
+
+impl Jit {
+    // fails if cranelift doesn't support this machine
+    pub fn new(stack_size: usize) -> Result<Self, String> {
+        let mut flags = settings::builder();
+        flags
+            .set("opt_level", "speed")
+            .map_err(|err| err.to_string())?;
+        let isa = cranelift_native::builder()?
+            .finish(settings::Flags::new(flags))
+            .map_err(|err| err.to_string())?;
+        Ok(Jit {
+            module: JITModule::new(JITBuilder::with_isa(isa, default_libcall_names())),
+            context: FunctionBuilderContext::new(),
+            stack_size,
+        })
+    }
+
+    // compiles the ops from `start` up to the first one native code can't run
+    fn compile(&mut self, ops: &[Op], start: usize) -> Option<NativeBlock> {
+        let count = ops[start..].iter().take_while(|&&op| is_native(op)).count();
+        if count == 0 {
+            return None;
+        }
+
+        let pointer = self.module.target_config().pointer_type();
+        let mut context = self.module.make_context();
+        let signature = &mut context.func.signature;
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::I64));
+
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut self.context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        let exit = builder.create_block();
+        builder.append_block_param(exit, types::I64);
+        builder.append_block_param(exit, types::I64);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let base = builder.block_params(entry)[0];
+        let length_pointer = builder.block_params(entry)[1];
+        let length = Variable::from_u32(0);
+        builder.declare_var(length, types::I64);
+        let initial_length = builder
+            .ins()
+            .load(types::I64, MemFlags::trusted(), length_pointer, 0);
+        builder.def_var(length, initial_length);
+
+        let mut emitter = Emitter {
+            builder: &mut builder,
+            base,
+            length,
+            exit,
+            stack_size: self.stack_size,
+        };
+        let mut open = true;
+        for (index, &op) in ops.iter().enumerate().skip(start).take(count) {
+            open = emitter.op(index, op);
+            if !open {
+                break;
+            }
+        }
+        // the op after the block is one native code can't run
+        if open {
+            emitter.leave((start + count) as u64);
+        }
+
+        builder.switch_to_block(exit);
+        builder.seal_block(exit);
+        let [final_length, next] = [builder.block_params(exit)[0], builder.block_params(exit)[1]];
+        builder
+            .ins()
+            .store(MemFlags::trusted(), final_length, length_pointer, 0);
+        builder.ins().return_(&[next]);
+        builder.finalize();
+
+        let id = self
+            .module
+            .declare_anonymous_function(&context.func.signature)
+            .ok()?;
+        self.module.define_function(id, &mut context).ok()?;
+        self.module.clear_context(&mut context);
+        self.module.finalize_definitions().ok()?;
+        let code = self.module.get_finalized_function(id);
+        // the signature was declared above
+        Some(unsafe { mem::transmute::<*const u8, NativeBlock>(code) })
+    }
+
+    // runs the program like Program::run
+    pub fn run(mut self, program: &mut Program) -> Result<(), RuntimeError> {
+        let mut blocks: Vec<Block> = (0..program.code.ops.len())
+            .map(|_| Block::Cold(0))
+            .collect();
+        let result = (|| {
+            while !program.halted {
+                let pc = program.pc;
+                match blocks.get_mut(pc) {
+                    Some(Block::Native(block)) => {
+                        // native code writes up to the stack size without growing the
+                        // stack; every task has a stack of its own
+                        let room = program.stack_size.saturating_sub(program.stack.len());
+                        program.stack.reserve(room);
+                        let mut length = program.stack.len() as u64;
+                        // native code only writes the bytes below the new length, which
+                        // stays within the stack size
+                        let next = unsafe {
+                            let next = block(program.stack.as_mut_ptr(), &mut length);
+                            program.stack.set_len(length as usize);
+                            next
+                        };
+                        program.pc = (next & !BAIL) as usize;
+                        if next & BAIL != 0 {
+                            program.step()?;
+                        }
+                        continue;
+                    }
+                    Some(Block::Cold(count)) if *count >= HOT_THRESHOLD => {
+                        blocks[pc] = match self.compile(&program.code.ops, pc) {
+                            Some(block) => Block::Native(block),
+                            None => Block::Interpreted,
+                        };
+                        continue;
+                    }
+                    Some(Block::Cold(count)) => *count += 1,
+                    _ => (),
+                }
+                program.step()?;
+            }
+            Ok(())
+        })();
+        // nothing points at the native code anymore
+        unsafe { self.module.free_memory() };
+        result
+    }
+}
@@ -0,0 +1,30 @@
+// without the default std feature the interpreter only needs alloc, for embedding it where
+// there's no operating system; file, stdin and stdout handling is left to the binary
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod bytecode;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod clock;
+pub mod environment;
+pub mod exceptions;
+pub mod files;
+pub mod graphics;
+#[cfg(feature = "tracing")]
+pub mod instrument;
+pub mod interpreter;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod limits;
+pub mod net;
+pub mod optimizer;
+mod rng;
+pub mod snapshot;
+pub mod source;
+pub mod tasks;
+pub mod terminal;
+pub mod wasm;
+#[cfg(feature = "wasm")]
+pub mod web;
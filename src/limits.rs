@@ -0,0 +1,106 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+// what a program may use before it's stopped with RuntimeError::LimitExceeded, for running
+// untrusted code; None is no limit. See Program::set_limits()
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    // values on the stack, below the stack size the program was created with
+    pub max_stack: Option<usize>,
+    pub max_call_depth: Option<usize>,
+    // bytes printed, to the output or the error output
+    pub max_output_bytes: Option<u64>,
+    // memory cells in use, the declared ones and the ones ALLOT handed out
+    pub max_memory: Option<usize>,
+    // needs a clock, so only with the std feature
+    #[cfg(feature = "std")]
+    pub timeout: Option<Duration>,
+}
+
+// the limit a program ran into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps,
+    Stack,
+    CallDepth,
+    Output,
+    Memory,
+    Time,
+    Interrupted,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps => write!(f, "Step limit exceeded"),
+            Limit::Stack => write!(f, "Stack limit exceeded"),
+            Limit::CallDepth => write!(f, "Call depth limit exceeded"),
+            Limit::Output => write!(f, "Output limit exceeded"),
+            Limit::Memory => write!(f, "Memory limit exceeded"),
+            Limit::Time => write!(f, "Time limit exceeded"),
+            Limit::Interrupted => write!(f, "Interrupted"),
+        }
+    }
+}
+
+// the clock is only read every this many steps
+#[cfg(feature = "std")]
+const CLOCK_INTERVAL: u64 = 1024;
+
+// what a program used since its limits were set
+pub struct Usage {
+    pub limits: Limits,
+    steps: u64,
+    output_bytes: u64,
+    #[cfg(feature = "std")]
+    started: Instant,
+}
+
+impl Usage {
+    pub fn new(limits: Limits) -> Self {
+        Usage {
+            limits,
+            steps: 0,
+            output_bytes: 0,
+            #[cfg(feature = "std")]
+            started: Instant::now(),
+        }
+    }
+
+    // counts a step that's about to run
+    pub fn step(&mut self) -> Result<(), Limit> {
+        if self.limits.max_steps.is_some_and(|max| self.steps >= max) {
+            return Err(Limit::Steps);
+        }
+        #[cfg(feature = "std")]
+        if self.steps.is_multiple_of(CLOCK_INTERVAL) && self.timed_out() {
+            return Err(Limit::Time);
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn timed_out(&self) -> bool {
+        self.time_left().is_some_and(|left| left.is_zero())
+    }
+
+    // the time until the timeout, None without one
+    #[cfg(feature = "std")]
+    pub fn time_left(&self) -> Option<Duration> {
+        let timeout = self.limits.timeout?;
+        Some(timeout.saturating_sub(self.started.elapsed()))
+    }
+
+    // counts output that's about to be written
+    pub fn output(&mut self, bytes: usize) -> Result<(), Limit> {
+        let total = self.output_bytes + bytes as u64;
+        if self.limits.max_output_bytes.is_some_and(|max| total > max) {
+            return Err(Limit::Output);
+        }
+        self.output_bytes = total;
+        Ok(())
+    }
+}
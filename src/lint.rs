@@ -0,0 +1,153 @@
+use std::fmt;
+
+use crate::analysis::{self, Warning};
+use crate::bytecode;
+use crate::interpreter::{label_name, Program, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    MissingHalt,
+    DupPop,
+    UnusedLabel,
+    UnreachableCode,
+    EmptyBranch,
+    MixedCaseKeyword,
+}
+
+pub const RULES: [Rule; 6] = [
+    Rule::MissingHalt,
+    Rule::DupPop,
+    Rule::UnusedLabel,
+    Rule::UnreachableCode,
+    Rule::EmptyBranch,
+    Rule::MixedCaseKeyword,
+];
+
+impl Rule {
+    // stable identifier used with --allow and --deny
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::MissingHalt => "missing-halt",
+            Rule::DupPop => "dup-pop",
+            Rule::UnusedLabel => "unused-label",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::EmptyBranch => "empty-branch",
+            Rule::MixedCaseKeyword => "mixed-case-keyword",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Rule> {
+        RULES.into_iter().find(|rule| rule.id() == id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+#[derive(Debug)]
+pub struct Lint {
+    pub rule: Rule,
+    pub line_number: usize,
+    pub column: usize,
+    message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// control reaching a label without a HALT, EXIT or RETURN in front of it
+fn missing_halt(program: &Program, lints: &mut Vec<Lint>) {
+    let targets = bytecode::branch_targets(&program.tokens);
+    let reached = analysis::reachable(program);
+    let mut labels: Vec<(&str, usize)> = program.labels().collect();
+    labels.sort_by_key(|&(label, index)| (index, program.label_line(label)));
+    labels.dedup_by_key(|(_, index)| *index);
+    for (label, index) in labels {
+        if index == 0 || index >= program.tokens.len() || !reached[index - 1] {
+            continue;
+        }
+        if analysis::successors(program, &targets, index - 1).contains(&index) {
+            lints.push(Lint {
+                rule: Rule::MissingHalt,
+                line_number: program.label_line(label).unwrap_or(0),
+                column: 0,
+                message: format!(
+                    "Execution falls through into label '{}', is a HALT missing?",
+                    label_name(label)
+                ),
+            });
+        }
+    }
+}
+
+// a DUP whose copy is thrown away right away
+fn dup_pop(program: &Program, lints: &mut Vec<Lint>) {
+    for (index, pair) in program.tokens.windows(2).enumerate() {
+        let is_label = program
+            .labels()
+            .any(|(_, label_index)| label_index == index + 1);
+        if matches!((&pair[0].token, &pair[1].token), (Token::Dup, Token::Pop)) && !is_label {
+            lints.push(Lint {
+                rule: Rule::DupPop,
+                line_number: pair[0].line_number,
+                column: pair[0].column,
+                message: "DUP immediately followed by POP has no effect".to_string(),
+            });
+        }
+    }
+}
+
+// IF or ELSE directly followed by the end of its branch
+fn empty_branch(program: &Program, lints: &mut Vec<Lint>) {
+    for pair in program.tokens.windows(2) {
+        let message = match (&pair[0].token, &pair[1].token) {
+            (Token::If, Token::Else | Token::Then) => "IF with an empty branch",
+            (Token::Else, Token::Then) => "ELSE with an empty branch",
+            _ => continue,
+        };
+        lints.push(Lint {
+            rule: Rule::EmptyBranch,
+            line_number: pair[0].line_number,
+            column: pair[0].column,
+            message: message.to_string(),
+        });
+    }
+}
+
+// mixed-case-keyword is only checked with `strict_case`
+pub fn lint(program: &Program, strict_case: bool) -> Vec<Lint> {
+    let mut lints: Vec<Lint> = analysis::warnings(program, strict_case)
+        .into_iter()
+        .map(|warning| Lint {
+            rule: match warning {
+                Warning::UnusedLabel(..) => Rule::UnusedLabel,
+                Warning::UnreachableCode(..) => Rule::UnreachableCode,
+                Warning::MixedCaseKeyword(..) => Rule::MixedCaseKeyword,
+            },
+            line_number: warning.line_number(),
+            column: warning.column(),
+            message: warning.to_string(),
+        })
+        .collect();
+    missing_halt(program, &mut lints);
+    dup_pop(program, &mut lints);
+    empty_branch(program, &mut lints);
+    lints.sort_by_key(|lint| (lint.line_number, lint.column));
+    lints
+}
+
+// the level of a rule after applying --allow and --deny in order; rules warn by default
+pub fn level(rule: Rule, overrides: &[(Rule, Level)]) -> Level {
+    overrides
+        .iter()
+        .rev()
+        .find(|(overridden, _)| *overridden == rule)
+        .map_or(Level::Warn, |&(_, level)| level)
+}
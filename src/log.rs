@@ -0,0 +1,53 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::rc::Rc;
+
+// how much the interpreter says about a run, besides errors: -q nothing, not even warnings,
+// -v also how the run ended and -vv also every step
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    Trace,
+}
+
+// where the interpreter's own messages go (traces, the debugger and how a run ended), so
+// that stdout only carries what the program prints: stderr, or the file given with
+// --log-file. Clones write to the same place
+#[derive(Clone)]
+pub struct Log {
+    verbosity: Verbosity,
+    out: Rc<RefCell<Box<dyn Write>>>,
+}
+
+impl Log {
+    pub fn new(verbosity: Verbosity, file: Option<&str>) -> io::Result<Self> {
+        let out: Box<dyn Write> = match file {
+            Some(path) => Box::new(LineWriter::new(File::create(path)?)),
+            None => Box::new(io::stderr()),
+        };
+        Ok(Log {
+            verbosity,
+            out: Rc::new(RefCell::new(out)),
+        })
+    }
+
+    pub fn enabled(&self, level: Verbosity) -> bool {
+        level <= self.verbosity
+    }
+
+    // writes the text if the verbosity is at least `level`; a log that can't be written to
+    // doesn't stop the program
+    pub fn write(&self, level: Verbosity, text: &str) {
+        if self.enabled(level) {
+            let _ = self.out.borrow_mut().write_all(text.as_bytes());
+        }
+    }
+
+    // waits for what was written to reach the file, before reading the debugger's commands
+    pub fn flush(&self) {
+        let _ = self.out.borrow_mut().flush();
+    }
+}
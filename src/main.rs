@@ -1,192 +1,793 @@
+mod analysis;
+mod bench;
+mod callgraph;
+mod cfg;
+mod cli;
+mod condition;
+mod console;
+mod dap;
+mod debugger;
+mod diagnostics;
+mod disasm;
+mod doc;
 mod file_io;
-mod interpreter;
+mod formatter;
+mod interrupt;
+mod json;
+mod lint;
+mod log;
+mod profile;
+mod project;
+mod record;
+mod repl;
+mod stack_analysis;
+mod stats;
+mod test_runner;
+mod trace;
+mod xref;
 
 use std::env;
-use std::io::{self, Write};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
 use std::process;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
 
-use interpreter::{ParseError, Program, RuntimeError};
+use analysis::Warning;
+use bytecode::Bytecode;
+use cli::{Command, Config, Graph, OnInterrupt, ProfileFormat, Target};
+use debugger::Debugger;
+use diagnostics::{Diagnostic, Reporter, Severity};
+use fifth::environment::StdEnvironment;
+use fifth::files::StdFileSystem;
+use fifth::limits::{Limit, Limits};
+use fifth::net::StdNetwork;
+use fifth::snapshot::Snapshot;
+use fifth::source::Source;
+use fifth::{bytecode, interpreter, optimizer, wasm};
+use formatter::FormatError;
+use interpreter::{Diagnostics, Frame, ParseError, Program, RuntimeError};
+use json::Value;
+use lint::Level;
+use log::{Log, Verbosity};
+use profile::Profile;
+use record::Recording;
+use stats::Stats;
 
-struct Config {
-    filename: String,
-    stack_size: usize,
-    verbose: bool,
-    step: bool,
+// exit codes, documented in the README; EXIT passes its own code through
+const EXIT_TEST_FAILURE: i32 = 1;
+const EXIT_UNFORMATTED: i32 = 1;
+const EXIT_PARSE_ERROR: i32 = 2;
+const EXIT_STACK_OVERFLOW: i32 = 3;
+const EXIT_STACK_UNDERFLOW: i32 = 4;
+const EXIT_INVALID_LABEL: i32 = 5;
+const EXIT_CALL_STACK_UNDERFLOW: i32 = 6;
+const EXIT_UNCLOSED_IF: i32 = 7;
+const EXIT_ASSERTION_FAILED: i32 = 8;
+const EXIT_HOST_WORD_FAILED: i32 = 9;
+const EXIT_LIMIT_EXCEEDED: i32 = 10;
+const EXIT_TASK_ERROR: i32 = 11;
+const EXIT_UNCAUGHT_EXCEPTION: i32 = 12;
+const EXIT_OUT_OF_MEMORY: i32 = 13;
+const EXIT_OUTPUT_FAILED: i32 = 14;
+const EXIT_USAGE: i32 = 64;
+// like a shell's for a process ended by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+// like a shell's for a process ended by SIGPIPE, after output to a pipe nobody reads
+const EXIT_BROKEN_PIPE: i32 = 141;
+const EXIT_IO_ERROR: i32 = 74;
+
+// how often --watch checks whether the file changed
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+// what a command ended with; run_once() turns this into output and an exit code
+enum Outcome {
+    ParseFailed(Diagnostics),
+    RuntimeFailed {
+        error: RuntimeError,
+        stack: Vec<u8>,
+        backtrace: Vec<Frame>,
+    },
+    Halted {
+        exit_code: Option<u8>,
+    },
+    WarningsDenied {
+        count: usize,
+    },
+    StackErrors {
+        count: usize,
+    },
+    LintErrors {
+        count: usize,
+    },
+    Checked,
+    Unformatted,
+    Tested {
+        failed: usize,
+    },
 }
 
 fn main() {
-    let config = match parse_args() {
+    let args: Vec<String> = env::args().collect();
+    let config = match cli::parse_args(&args) {
         Ok(config) => config,
         Err(err) => {
             eprintln!("Error: {}", err);
-            eprintln!("Usage: program [OPTIONS] <filename>");
-            eprintln!("Options:");
-            eprintln!("  --stack-size=<size>  Set stack size (default: 256)");
-            eprintln!("  -v, --verbose        Print every step");
-            eprintln!("  -s, --step           Wait for user input after every step");
-            process::exit(1);
+            eprint!("{}", cli::usage());
+            process::exit(EXIT_USAGE);
         }
     };
 
-    match run(config) {
-        Ok(_) => process::exit(0),
-        Err(err) => {
+    if config.command == Command::Dap {
+        if let Err(err) = dap::serve(&config) {
             eprintln!("Error: {}", err);
-            process::exit(1);
+            process::exit(EXIT_IO_ERROR);
         }
+        return;
+    }
+
+    // golden tests run the programs in a directory rather than one file
+    if config.golden {
+        let code = match test_runner::run_golden(&config) {
+            Ok(Outcome::Tested { failed: 0 }) => 0,
+            Ok(_) => EXIT_TEST_FAILURE,
+            Err(err) => {
+                Reporter::new(&config, "").emit(&Diagnostic::io_error(err.as_ref()));
+                EXIT_IO_ERROR
+            }
+        };
+        process::exit(code);
     }
+
+    if config.watch {
+        watch(&config);
+    }
+    process::exit(run_once(&config));
 }
 
-fn parse_args() -> Result<Config, String> {
-    let args: Vec<String> = env::args().collect();
-    let mut config = Config {
-        filename: String::new(),
-        stack_size: 256,
-        verbose: false,
-        step: false,
+// runs the program again every time its file changes, until interrupted
+fn watch(config: &Config) -> ! {
+    let modified = || {
+        fs::metadata(&config.filename)
+            .and_then(|metadata| metadata.modified())
+            .ok()
     };
+    loop {
+        let last_modified = modified();
+        // clear the screen and move the cursor to the top left
+        print!("\x1b[2J\x1b[H");
+        let _ = io::stdout().flush();
+        let code = run_once(config);
+        let _ = io::stdout().flush();
+        eprintln!(
+            "\n[exited with {}, waiting for {} to change]",
+            code, config.filename
+        );
+        // an editor can remove the file for a moment while saving it
+        while modified() == last_modified || modified().is_none() {
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
 
-    let mut i = 1;
-    while i < args.len() {
-        match args[i].as_str() {
-            "-v" | "--verbose" => {
-                config.verbose = true;
-                i += 1;
+// runs the command on the program and returns the exit code
+fn run_once(config: &Config) -> i32 {
+    let contents = match config.contents() {
+        Ok(contents) => contents,
+        Err(err) => {
+            Reporter::new(config, "").emit(&Diagnostic::io_error(&err));
+            return EXIT_IO_ERROR;
+        }
+    };
+    // compiled programs have no source to show in errors
+    let compiled = bytecode::is_compiled(&contents);
+    let source = match std::str::from_utf8(&contents) {
+        _ if compiled => "",
+        Ok(source) => source,
+        Err(err) => {
+            Reporter::new(config, "").emit(&Diagnostic::io_error(&err));
+            return EXIT_IO_ERROR;
+        }
+    };
+    let reporter = Reporter::new(config, source);
+
+    let result = match config.command {
+        Command::Run | Command::Debug if compiled => run_compiled(config, &contents),
+        Command::Disasm if compiled => disasm_compiled(config, &contents),
+        _ if compiled => Err(format!(
+            "{} is a compiled program, only run, debug and disasm accept it",
+            config.source_name()
+        )
+        .into()),
+        Command::Run | Command::Debug => run(config, source),
+        Command::Test => test_runner::run_tests(config, source),
+        Command::Check => check(config, source),
+        Command::Fmt => fmt(config, source),
+        Command::Lint => lint(config, source),
+        Command::Compile => compile(config, source),
+        Command::Disasm => disasm(config, source),
+        Command::Bench => bench::bench(config, source),
+        Command::Graph => graph(config, source),
+        Command::Xref => xref(config, source),
+        Command::Doc => doc(config, source),
+        Command::Repl => repl::repl(config, source),
+        Command::Dap => unreachable!(),
+    };
+
+    let code = match result {
+        Ok(Outcome::ParseFailed(diagnostics)) => {
+            for err in diagnostics.iter() {
+                reporter.emit(&Diagnostic::parse_error(err));
             }
-            "-s" | "--step" => {
-                config.step = true;
-                i += 1;
+            EXIT_PARSE_ERROR
+        }
+        Ok(Outcome::RuntimeFailed {
+            error,
+            stack,
+            backtrace,
+        }) => {
+            reporter.emit(&Diagnostic::runtime_error(&error, backtrace, stack));
+            runtime_exit_code(&error)
+        }
+        Ok(Outcome::Halted { exit_code }) => exit_code.map_or(0, i32::from),
+        Ok(Outcome::WarningsDenied { count }) => {
+            if !reporter.is_json() {
+                eprintln!("Error: {} warning(s) denied by --deny-warnings", count);
             }
-            arg if arg.starts_with("--stack-size=") => {
-                let size_str = &arg["--stack-size=".len()..];
-                config.stack_size = size_str
-                    .parse()
-                    .map_err(|_| format!("Invalid stack size: {}", size_str))?;
-                i += 1;
+            EXIT_PARSE_ERROR
+        }
+        Ok(Outcome::StackErrors { count }) => {
+            if !reporter.is_json() {
+                eprintln!("Error: stack analysis found {} error(s)", count);
             }
-            arg if arg.starts_with("-") => {
-                return Err(format!("Unknown option: {}", arg));
+            EXIT_PARSE_ERROR
+        }
+        Ok(Outcome::LintErrors { count }) => {
+            if !reporter.is_json() {
+                eprintln!("Error: lint found {} error(s)", count);
             }
-            _ => {
-                if config.filename.is_empty() {
-                    config.filename = args[i].clone();
-                } else {
-                    return Err("Multiple filenames specified".to_string());
-                }
-                i += 1;
+            EXIT_PARSE_ERROR
+        }
+        Ok(Outcome::Checked) => 0,
+        Ok(Outcome::Unformatted) => {
+            eprintln!("{}: not formatted", config.source_name());
+            EXIT_UNFORMATTED
+        }
+        Ok(Outcome::Tested { failed: 0 }) => 0,
+        Ok(Outcome::Tested { .. }) => EXIT_TEST_FAILURE,
+        // whatever reads the output is done with it, so there's no one to tell
+        Err(err)
+            if err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe) =>
+        {
+            EXIT_BROKEN_PIPE
+        }
+        Err(err) => {
+            reporter.emit(&Diagnostic::io_error(err.as_ref()));
+            EXIT_IO_ERROR
+        }
+    };
+    code
+}
+
+fn runtime_exit_code(error: &RuntimeError) -> i32 {
+    match error {
+        RuntimeError::StackOverflow(_) => EXIT_STACK_OVERFLOW,
+        RuntimeError::StackUnderflow(_) => EXIT_STACK_UNDERFLOW,
+        RuntimeError::InvalidLabel(_) => EXIT_INVALID_LABEL,
+        RuntimeError::CallStackUnderflow(_) => EXIT_CALL_STACK_UNDERFLOW,
+        RuntimeError::UnclosedIfStatement(_) => EXIT_UNCLOSED_IF,
+        RuntimeError::AssertionFailed(_) => EXIT_ASSERTION_FAILED,
+        RuntimeError::HostWordFailed(_, _) => EXIT_HOST_WORD_FAILED,
+        RuntimeError::LimitExceeded(_, Limit::Interrupted) => EXIT_INTERRUPTED,
+        RuntimeError::LimitExceeded(_, _) => EXIT_LIMIT_EXCEEDED,
+        RuntimeError::TaskFailed(_, _) => EXIT_TASK_ERROR,
+        RuntimeError::Thrown(_, _) => EXIT_UNCAUGHT_EXCEPTION,
+        RuntimeError::OutOfMemory(_) => EXIT_OUT_OF_MEMORY,
+        RuntimeError::OutputFailed(_) => EXIT_OUTPUT_FAILED,
+    }
+}
+
+// a program of the source with the options that change how it's parsed
+fn new_program(config: &Config, source: &str) -> Program {
+    let mut program = Program::new(source, config.stack_size);
+    program.set_case_sensitive(config.case_sensitive);
+    program
+}
+
+// with --deny-warnings, reports the program's warnings and stops if there are any; with
+// --strict-case alone, reports the keywords in mixed case and goes on
+fn deny_warnings(config: &Config, source: &str, program: &Program) -> Option<Outcome> {
+    if !config.deny_warnings && !config.strict_case {
+        return None;
+    }
+    let mut warnings = analysis::warnings(program, config.strict_case);
+    if !config.deny_warnings {
+        warnings.retain(|warning| matches!(warning, Warning::MixedCaseKeyword(..)));
+    }
+    if warnings.is_empty() {
+        return None;
+    }
+    let reporter = Reporter::new(config, source);
+    for warning in &warnings {
+        reporter.emit(&Diagnostic::warning(warning));
+    }
+    config.deny_warnings.then_some(Outcome::WarningsDenied {
+        count: warnings.len(),
+    })
+}
+
+fn check(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let reporter = Reporter::new(config, source);
+    let mut program = new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
+    }
+    let warnings = analysis::warnings(&program, config.strict_case);
+    for warning in &warnings {
+        reporter.emit(&Diagnostic::warning(warning));
+    }
+    if config.deny_warnings && !warnings.is_empty() {
+        return Ok(Outcome::WarningsDenied {
+            count: warnings.len(),
+        });
+    }
+    if config.stack_analysis {
+        let initial_depth = config.stack_init.len() + config.program_args.len();
+        let report = stack_analysis::analyze(&program, initial_depth);
+        for issue in &report.issues {
+            reporter.emit(&Diagnostic::stack_issue(issue));
+        }
+        if !report.issues.is_empty() {
+            return Ok(Outcome::StackErrors {
+                count: report.issues.len(),
+            });
+        }
+        println!(
+            "{}: stack depth stays between {} and {}",
+            config.source_name(),
+            report.min_depth,
+            report.max_depth
+        );
+    }
+    println!("{}: ok", config.source_name());
+    Ok(Outcome::Checked)
+}
+
+fn lint(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let reporter = Reporter::new(config, source);
+    let mut program = new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
+    }
+    let mut denied = 0;
+    for lint in lint::lint(&program, config.strict_case) {
+        let severity = match lint::level(lint.rule, &config.lint_levels) {
+            Level::Allow => continue,
+            Level::Warn if !config.deny_warnings => Severity::Warning,
+            Level::Warn | Level::Deny => {
+                denied += 1;
+                Severity::Error
             }
+        };
+        reporter.emit(&Diagnostic::lint(&lint, severity));
+    }
+    if denied > 0 {
+        return Ok(Outcome::LintErrors { count: denied });
+    }
+    println!("{}: ok", config.source_name());
+    Ok(Outcome::Checked)
+}
+
+fn fmt(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let formatted = match formatter::format(source, config.case_sensitive) {
+        Ok(formatted) => formatted,
+        Err(FormatError::Parse(diagnostics)) => return Ok(Outcome::ParseFailed(diagnostics)),
+        Err(FormatError::Changed) => return Err("formatting would change the program".into()),
+    };
+    if config.fmt_check {
+        return Ok(if formatted == source {
+            Outcome::Checked
+        } else {
+            Outcome::Unformatted
+        });
+    }
+    // programs that don't come from a file can only be printed
+    if config.fmt_stdout || config.eval.is_some() || config.filename == "-" {
+        io::stdout().write_all(formatted.as_bytes())?;
+    } else if formatted != source {
+        file_io::write_string_to_file(&config.filename, &formatted)?;
+    }
+    Ok(Outcome::Checked)
+}
+
+// with -O, optimizes the program; --dump-optimized shows the bytecode before and after
+fn optimize(config: &Config, program: &mut Program) {
+    if config.opt_level == 0 {
+        return;
+    }
+    let before = config
+        .dump_optimized
+        .then(|| disasm::disassemble(&program.code, &program.source));
+    program.optimize(&optimizer::Options {
+        level: config.opt_level,
+        inline_threshold: config.inline_threshold,
+    });
+    if let Some(before) = before {
+        eprint!(
+            "before optimizing:\n{}after optimizing:\n{}",
+            before,
+            disasm::disassemble(&program.code, &program.source)
+        );
+    }
+}
+
+// with --listing, writes the bytecode that runs or is compiled as an assembler listing
+fn write_listing(config: &Config, program: &Program) -> io::Result<()> {
+    if !config.listing {
+        return Ok(());
+    }
+    let listing = disasm::listing(&program.code, &program.source);
+    file_io::write_string_to_file(config.listing_path(), &listing)
+}
+
+// the call graph or control flow graph, printed or written to the output file; with
+// --profile the program runs first, which writes the call graph with call counts
+fn graph(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
+    }
+    if config.profile.is_some() {
+        return execute(config, program);
+    }
+    let graph = match config.graph {
+        Graph::Calls => callgraph::calls(&program.code, None),
+        Graph::Cfg => cfg::cfg(&program.code),
+    };
+    match &config.output {
+        Some(output) => file_io::write_string_to_file(output, &graph)?,
+        None => io::stdout().write_all(graph.as_bytes())?,
+    }
+    Ok(Outcome::Checked)
+}
+
+// calls to undefined labels are part of the report rather than errors
+fn xref(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        if !diagnostics
+            .iter()
+            .all(|err| matches!(err, ParseError::InvalidCall(..)))
+        {
+            return Ok(Outcome::ParseFailed(diagnostics));
         }
     }
+    io::stdout().write_all(xref::report(&program).as_bytes())?;
+    Ok(Outcome::Checked)
+}
 
-    if config.filename.is_empty() {
-        return Err("No filename specified".to_string());
+fn doc(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
     }
+    let name = config.source_name();
+    let title = Path::new(name)
+        .file_name()
+        .map_or(name.into(), |file_name| file_name.to_string_lossy());
+    let doc = if config.html {
+        doc::html(&title, &program)
+    } else {
+        doc::markdown(&title, &program)
+    };
+    match &config.output {
+        Some(output) => file_io::write_string_to_file(output, &doc)?,
+        None => io::stdout().write_all(doc.as_bytes())?,
+    }
+    Ok(Outcome::Checked)
+}
 
-    Ok(config)
+fn compile(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
+    }
+    if let Some(outcome) = deny_warnings(config, source, &program) {
+        return Ok(outcome);
+    }
+    optimize(config, &mut program);
+    write_listing(config, &program)?;
+    let output = match config.target {
+        Target::Bytecode => program.code.to_bytes(),
+        Target::Wasm => wasm::compile(&program.code, config.stack_size),
+    };
+    file_io::write_bytes_to_file(config.output_path(), &output)?;
+    Ok(Outcome::Checked)
 }
 
-fn run(config: Config) -> Result<(), Box<dyn std::error::Error>> {
-    let content = file_io::read_file_to_string(&config.filename)?;
+fn disasm(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
+    }
+    if config.dump_tokens {
+        io::stdout().write_all(disasm::dump_tokens(&program.tokens).as_bytes())?;
+    } else {
+        optimize(config, &mut program);
+        io::stdout().write_all(disasm::disassemble(&program.code, &program.source).as_bytes())?;
+    }
+    Ok(Outcome::Checked)
+}
 
-    let mut program = Program::new(&content, config.stack_size);
+fn disasm_compiled(
+    config: &Config,
+    contents: &[u8],
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    if config.dump_tokens {
+        return Err(format!(
+            "{} is a compiled program without tokens",
+            config.source_name()
+        )
+        .into());
+    }
+    let mut program = Program::from_bytecode(Bytecode::from_bytes(contents)?, config.stack_size);
+    optimize(config, &mut program);
+    io::stdout().write_all(disasm::disassemble(&program.code, &Source::default()).as_bytes())?;
+    Ok(Outcome::Checked)
+}
 
-    match program.parse() {
-        Ok(_) => (),
-        Err(err) => {
-            match err {
-                ParseError::InvalidArgument(arg, line) => {
-                    eprintln!("Parse error at line {}: Invalid argument '{}'", line, arg);
-                }
-                ParseError::MissingArgument(token, line) => {
-                    eprintln!(
-                        "Parse error at line {}: Missing argument for '{}'",
-                        line, token
-                    );
-                }
-                ParseError::DuplicateLabel(label, line) => {
-                    eprintln!("Parse error at line {}: Duplicate label '{}'", line, label);
-                }
-                ParseError::InvalidCall(label, line) => {
-                    eprintln!(
-                        "Parse error at line {}: Call to undefined label '{}'",
-                        line, label
-                    );
-                }
-                ParseError::ElseWithoutIfStatement(token) => {
-                    eprintln!("Parse error at line {}: ELSE without IF", token.line_number);
-                }
-                ParseError::ThenWithoutIfStatement(token) => {
-                    eprintln!("Parse error at line {}: THEN without IF", token.line_number);
-                }
-                ParseError::TooManyElseStatements(token) => {
-                    eprintln!(
-                        "Parse error at line {}: Multiple ELSE statements for single IF",
-                        token.line_number
-                    );
-                }
+fn run(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
+    }
+    if let Some(outcome) = deny_warnings(config, source, &program) {
+        return Ok(outcome);
+    }
+    optimize(config, &mut program);
+    write_listing(config, &program)?;
+    execute(config, program)
+}
+
+fn run_compiled(config: &Config, contents: &[u8]) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = Program::from_bytecode(Bytecode::from_bytes(contents)?, config.stack_size);
+    optimize(config, &mut program);
+    write_listing(config, &program)?;
+    execute(config, program)
+}
+
+// runs the program with --jit
+#[cfg(feature = "jit")]
+fn run_jit(program: &mut Program) -> Result<Result<(), RuntimeError>, Box<dyn std::error::Error>> {
+    Ok(fifth::jit::Jit::new(program.stack_size)?.run(program))
+}
+
+// --jit is refused without the jit feature
+#[cfg(not(feature = "jit"))]
+fn run_jit(_: &mut Program) -> Result<Result<(), RuntimeError>, Box<dyn std::error::Error>> {
+    unreachable!("--jit needs the jit feature")
+}
+
+// runs a parsed or loaded program with the configured input, seed and initial stack
+fn execute(config: &Config, mut program: Program) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut recording = None;
+    if let Some(replay) = &config.replay {
+        let replayed = Recording::load(replay)?;
+        program.seed(replayed.seed());
+        program.set_input(replayed.replay());
+        // a replay reads the recorded TICKS and doesn't wait for SLEEP
+        program.set_clock(replayed.replay_clock());
+    } else {
+        let input: Box<dyn Read> = match &config.input_file {
+            Some(input_file) => file_io::open_input(input_file)?,
+            None => Box::new(io::stdin()),
+        };
+        if config.record.is_some() {
+            let recorded = Recording::new(config.seed);
+            program.seed(recorded.seed());
+            program.set_input(recorded.record(input));
+            program.set_clock(recorded.record_clock());
+            recording = Some(recorded);
+        } else {
+            if let Some(seed) = config.seed {
+                program.seed(seed);
             }
-            process::exit(1);
+            program.set_input(input);
+        }
+    }
+    program.set_output(file_io::Stdout::new());
+
+    if config.allow_fs {
+        program.set_file_system(StdFileSystem);
+    }
+    if config.allow_net {
+        program.set_network(StdNetwork);
+    }
+    if config.allow_env {
+        program.set_environment(StdEnvironment);
+    }
+    // frames go to a window with the window feature, unless they're asked for in a file
+    if program.code.ops.contains(&bytecode::Op::GfxFlush) {
+        #[cfg(feature = "window")]
+        if config.gfx_output.is_none() {
+            program.set_screen(fifth::graphics::Window::default());
+        } else {
+            program.set_screen(file_io::PpmFile(config.gfx_output_path()));
+        }
+        #[cfg(not(feature = "window"))]
+        program.set_screen(file_io::PpmFile(config.gfx_output_path()));
+    }
+    // KEY and KEY? read the terminal as keys are pressed, unless the input comes from
+    // somewhere else or is recorded
+    #[cfg(all(feature = "terminal", unix))]
+    if config.input_file.is_none()
+        && config.record.is_none()
+        && config.replay.is_none()
+        && program
+            .code
+            .ops
+            .iter()
+            .any(|op| matches!(op, bytecode::Op::Key | bytecode::Op::KeyPoll))
+    {
+        if let Some(keyboard) = fifth::terminal::RawKeyboard::new() {
+            program.set_keyboard(keyboard);
         }
     }
 
-    while !program.halted {
-        if config.verbose || config.step {
-            let current_token = &program.tokens[program.pc];
-            println!("Stack: {:?}", program.stack);
-            println!(
-                "Line {}: {}",
-                current_token.line_number,
-                current_token.token.to_string()
-            );
+    if let Some(resume) = &config.resume {
+        program.restore(&Snapshot::from_bytes(&file_io::read_file(resume)?)?)?;
+    } else {
+        program.stack.extend(&config.stack_init);
+        program.stack.extend(&config.program_args);
+    }
 
-            if config.step {
-                io::stdout().flush()?;
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
+    if config.max_output.is_some() || config.timeout.is_some() {
+        program.set_limits(Limits {
+            max_output_bytes: config.max_output,
+            timeout: config.timeout,
+            ..program.limits().unwrap_or_default()
+        });
+    }
+    program.set_interrupt(&interrupt::INTERRUPTED);
+    let _interrupt = interrupt::Handler::install();
+
+    let log = Log::new(config.verbosity, config.log_file.as_deref())?;
+    let mut result = if config.step || !config.breakpoints.is_empty() {
+        let mut debugger = Debugger::new(program);
+        let resolved = debugger.set_breakpoints(&config.breakpoints);
+        for (breakpoint, resolved) in config.breakpoints.iter().zip(resolved) {
+            if resolved.is_none() {
+                log.write(
+                    Verbosity::Normal,
+                    &format!("No instruction at or after line {}\n", breakpoint.line),
+                );
             }
         }
-
-        match program.step() {
-            Ok(_) => (),
-            Err(err) => {
-                match err {
-                    RuntimeError::StackOverflow(token) => {
-                        eprintln!(
-                            "Runtime error at line {}: Stack overflow",
-                            token.line_number
-                        );
-                    }
-                    RuntimeError::StackUnderflow(token) => {
-                        eprintln!(
-                            "Runtime error at line {}: Stack underflow",
-                            token.line_number
-                        );
-                    }
-                    RuntimeError::InvalidLabel(token) => {
-                        eprintln!("Runtime error at line {}: Invalid label", token.line_number);
-                    }
-                    RuntimeError::CallStackUnderflow(token) => {
-                        eprintln!(
-                            "Runtime error at line {}: Call stack underflow",
-                            token.line_number
-                        );
-                    }
-                    RuntimeError::UnclosedIfStatement(token) => {
-                        eprintln!(
-                            "Runtime error at line {}: Unclosed IF statement",
-                            token.line_number
-                        );
-                    }
-                }
-                process::exit(1);
+        // with only breakpoints the program runs until it hits one
+        let result = console::run(&mut debugger, config.step, &log)?;
+        program = debugger.program;
+        result
+    } else if log.enabled(Verbosity::Trace) {
+        trace::run(&mut program, log.clone())
+    } else if let Some(format) = config.profile {
+        let mut profile = Profile::new(&program);
+        let result = profile.run(&mut program);
+        match format {
+            ProfileFormat::Report => eprint!("{}", profile.report(&program)),
+            ProfileFormat::Flamegraph => {
+                file_io::write_string_to_file(config.output_path(), &profile.folded(&program))?
             }
+            ProfileFormat::CallGraph => {
+                let graph = callgraph::calls(&program.code, Some(profile.edges()));
+                file_io::write_string_to_file(config.output_path(), &graph)?
+            }
+        }
+        result
+    } else if config.stats {
+        let mut stats = Stats::default();
+        let result = stats.run(&mut program);
+        eprint!("{}", stats.report());
+        result
+    } else if config.jit {
+        run_jit(&mut program)?
+    } else {
+        program.run()
+    };
+    // the instruction Ctrl-C stopped in front of hasn't run, so the debugger can go on from
+    // there, and Ctrl-C stops in it again after continuing
+    while let Err(RuntimeError::LimitExceeded(_, Limit::Interrupted)) = result {
+        log.write(
+            Verbosity::Quiet,
+            &format!("Interrupted after {} steps\n", program.steps_run()),
+        );
+        if config.on_interrupt != OnInterrupt::Break {
+            break;
         }
+        interrupt::reset();
+        let mut debugger = Debugger::new(program);
+        result = console::run(&mut debugger, true, &log)?;
+        program = debugger.program;
+    }
+    // run() flushes the output, but not every way of running does
+    let flushed = program.flush();
+    let result = result.and(flushed);
+    // failed runs are the ones worth replaying
+    if let (Some(recording), Some(record)) = (&recording, &config.record) {
+        recording.save(record)?;
+    }
+    if config.snapshot_on_exit {
+        file_io::write_bytes_to_file(config.output_path(), &program.snapshot().to_bytes())?;
+    }
+    if config.output_state {
+        eprintln!("{}", state(config, &program, &result));
+    }
+    if matches!(result, Err(RuntimeError::OutputFailed(_)))
+        && file_io::BROKEN_PIPE.load(Ordering::Relaxed)
+    {
+        return Err(io::Error::from(io::ErrorKind::BrokenPipe).into());
+    }
+    if let Err(error) = result {
+        return Ok(Outcome::RuntimeFailed {
+            backtrace: program.backtrace(),
+            error,
+            stack: program.stack,
+        });
     }
 
-    if config.verbose || config.step {
-        println!("Program halted.");
-        println!("Final stack: {:?}", program.stack);
+    // stepping through a program already shows how it ends
+    let level = if config.step {
+        Verbosity::Normal
+    } else {
+        Verbosity::Verbose
+    };
+    log.write(level, "Program halted.\n");
+    log.write(level, &format!("Final stack: {:?}\n", program.stack));
+    if let Some(code) = program.exit_code {
+        log.write(level, &format!("Exit code: {}\n", code));
     }
+    Ok(Outcome::Halted {
+        exit_code: program.exit_code,
+    })
+}
 
-    Ok(())
+// what --output-state prints when a run stops, e.g.
+//   {"stack":[1,2],"steps":91,"halted":true,"exit_value":null,"error":null}
+// where halted is false and error the error's code if it failed. Compiled code doesn't
+// count its steps, so they're null with --jit
+fn state(config: &Config, program: &Program, result: &Result<(), RuntimeError>) -> Value {
+    let steps = match config.jit {
+        false => Value::Number(program.steps_run() as i64),
+        true => Value::Null,
+    };
+    Value::object(vec![
+        (
+            "stack",
+            Value::Array(
+                program
+                    .stack
+                    .iter()
+                    .map(|&value| Value::Number(value.into()))
+                    .collect(),
+            ),
+        ),
+        ("steps", steps),
+        ("halted", Value::Bool(program.halted)),
+        (
+            "exit_value",
+            program
+                .exit_code
+                .map_or(Value::Null, |code| Value::Number(code.into())),
+        ),
+        (
+            "error",
+            result
+                .as_ref()
+                .err()
+                .map_or(Value::Null, |error| Value::String(error.code().to_string())),
+        ),
+    ])
 }
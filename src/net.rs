@@ -0,0 +1,195 @@
+use alloc::boxed::Box;
+use core::fmt;
+
+// socket handles are bytes on the stack, so a program can't have more sockets open than this
+pub const MAX_SOCKETS: usize = 256;
+
+// why a socket word failed; the program gets its code as the status, which is 0 on success.
+// The codes that mean the same as a FileError's are the same
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetError {
+    // the program has no network (the fifth command needs --allow-net)
+    Disabled = 1,
+    // nothing listens at the address, or the host wasn't found
+    Refused = 2,
+    // TCP_LISTEN's port is taken, or not allowed
+    AddressInUse = 3,
+    // the other end closed the connection
+    Closed = 4,
+    // not an open socket, or a listener to a word for connections or the other way around
+    BadHandle = 5,
+    TooManySockets = 6,
+    // the host name is empty or not UTF-8
+    BadName = 7,
+    Io = 9,
+}
+
+impl NetError {
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::Disabled => write!(f, "Network is disabled"),
+            NetError::Refused => write!(f, "Connection refused"),
+            NetError::AddressInUse => write!(f, "Address in use"),
+            NetError::Closed => write!(f, "Connection closed"),
+            NetError::BadHandle => write!(f, "Bad socket handle"),
+            NetError::TooManySockets => write!(f, "Too many open sockets"),
+            NetError::BadName => write!(f, "Bad host name"),
+            NetError::Io => write!(f, "I/O error"),
+        }
+    }
+}
+
+// where TCP_LISTEN and TCP_CONNECT open sockets. A program has none until it's given one,
+// so it can't use the network unless its host allows it
+pub trait Network {
+    fn listen(&mut self, port: u16) -> Result<Box<dyn Listener>, NetError>;
+    fn connect(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>, NetError>;
+}
+
+// the words that can have to wait get `wait`: without it, they return None instead of
+// waiting, so other tasks can run in the meantime
+pub trait Listener {
+    fn accept(&mut self, wait: bool) -> Result<Option<Box<dyn Connection>>, NetError>;
+}
+
+pub trait Connection {
+    // may hold the byte back until the next receive or close
+    fn send(&mut self, byte: u8) -> Result<(), NetError>;
+    fn receive(&mut self, wait: bool) -> Result<Option<u8>, NetError>;
+    fn close(&mut self) -> Result<(), NetError>;
+}
+
+// what a socket handle stands for
+pub enum Socket {
+    Listener(Box<dyn Listener>),
+    Connection(Box<dyn Connection>),
+}
+
+#[cfg(feature = "std")]
+mod tcp {
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+
+    use super::*;
+
+    // the sockets of the operating system; listeners listen on every interface
+    pub struct StdNetwork;
+
+    impl From<io::Error> for NetError {
+        fn from(error: io::Error) -> Self {
+            match error.kind() {
+                io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound => NetError::Refused,
+                io::ErrorKind::AddrInUse | io::ErrorKind::PermissionDenied => {
+                    NetError::AddressInUse
+                }
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof => NetError::Closed,
+                _ => NetError::Io,
+            }
+        }
+    }
+
+    impl Network for StdNetwork {
+        fn listen(&mut self, port: u16) -> Result<Box<dyn Listener>, NetError> {
+            Ok(Box::new(TcpListener::bind(("0.0.0.0", port))?))
+        }
+
+        fn connect(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>, NetError> {
+            // a host that doesn't resolve fails with an error of kind Other or Uncategorized
+            let stream = TcpStream::connect((host, port)).map_err(|error| match error.kind() {
+                io::ErrorKind::ConnectionRefused => NetError::Refused,
+                _ if error.raw_os_error().is_none() => NetError::Refused,
+                _ => NetError::from(error),
+            })?;
+            Ok(Box::new(TcpConnection::new(stream)))
+        }
+    }
+
+    impl Listener for TcpListener {
+        fn accept(&mut self, wait: bool) -> Result<Option<Box<dyn Connection>>, NetError> {
+            self.set_nonblocking(!wait)?;
+            match TcpListener::accept(self) {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    Ok(Some(Box::new(TcpConnection::new(stream))))
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+                Err(error) => Err(error.into()),
+            }
+        }
+    }
+
+    // sends bytes in batches rather than one packet each
+    struct TcpConnection {
+        stream: TcpStream,
+        outgoing: Vec<u8>,
+        incoming: VecDeque<u8>,
+    }
+
+    impl TcpConnection {
+        fn new(stream: TcpStream) -> Self {
+            TcpConnection {
+                stream,
+                outgoing: Vec::new(),
+                incoming: VecDeque::new(),
+            }
+        }
+
+        fn flush(&mut self) -> Result<(), NetError> {
+            if !self.outgoing.is_empty() {
+                self.stream.set_nonblocking(false)?;
+                self.stream.write_all(&self.outgoing)?;
+                self.outgoing.clear();
+            }
+            Ok(())
+        }
+    }
+
+    impl Connection for TcpConnection {
+        fn send(&mut self, byte: u8) -> Result<(), NetError> {
+            self.outgoing.push(byte);
+            if self.outgoing.len() >= 512 {
+                self.flush()?;
+            }
+            Ok(())
+        }
+
+        // what was sent so far goes out first, as the other end may be waiting for it
+        fn receive(&mut self, wait: bool) -> Result<Option<u8>, NetError> {
+            if let Some(byte) = self.incoming.pop_front() {
+                return Ok(Some(byte));
+            }
+            self.flush()?;
+            self.stream.set_nonblocking(!wait)?;
+            let mut buffer = [0; 512];
+            match self.stream.read(&mut buffer) {
+                Ok(0) => Err(NetError::Closed),
+                Ok(count) => {
+                    self.incoming.extend(&buffer[1..count]);
+                    Ok(Some(buffer[0]))
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+                Err(error) => Err(error.into()),
+            }
+        }
+
+        fn close(&mut self) -> Result<(), NetError> {
+            self.flush()?;
+            // the other end may have closed it already
+            let _ = self.stream.shutdown(Shutdown::Both);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub use tcp::StdNetwork;
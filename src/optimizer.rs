@@ -0,0 +1,342 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::bytecode::{Bytecode, Op, UNCLOSED};
+
+// ops that control can reach other than by falling through: labels, subroutine addresses,
+// jump, call and spawn targets, the handlers after CATCHes and the return address after
+// every call. Patterns must not span these, and basic blocks start at them
+pub fn entry_points(code: &Bytecode) -> Vec<bool> {
+    let mut entries = vec![false; code.ops.len() + 1];
+    let mut mark = |index: usize| {
+        if let Some(entry) = entries.get_mut(index) {
+            *entry = true;
+        }
+    };
+    for &(_, index) in &code.labels {
+        mark(index);
+    }
+    for &index in &code.addresses {
+        mark(index);
+    }
+    for (index, &op) in code.ops.iter().enumerate() {
+        match op {
+            Op::JumpIfZero(target)
+            | Op::Jump(target)
+            | Op::Of(target)
+            | Op::Spawn(target)
+            | Op::Try(target)
+            | Op::Catch(target)
+                if target != UNCLOSED =>
+            {
+                mark(target as usize)
+            }
+            Op::Call(target) => {
+                if target != UNCLOSED {
+                    mark(target as usize);
+                }
+                mark(index + 1);
+            }
+            Op::Execute => mark(index + 1),
+            _ => (),
+        }
+    }
+    entries
+}
+
+// drops the removed ops; jumps, labels and addresses pointing at a removed op move to the
+// op after it
+fn remove(code: &mut Bytecode, removed: &[bool]) {
+    let mut new_index = Vec::with_capacity(removed.len() + 1);
+    let mut kept = 0;
+    for &is_removed in removed {
+        new_index.push(kept);
+        if !is_removed {
+            kept += 1;
+        }
+    }
+    new_index.push(kept);
+    let remap = |target: u32| match new_index.get(target as usize) {
+        Some(&index) if target != UNCLOSED => index as u32,
+        _ => target,
+    };
+
+    let ops = code
+        .ops
+        .iter()
+        .zip(removed)
+        .filter(|(_, &is_removed)| !is_removed);
+    code.ops = ops
+        .map(|(&op, _)| match op {
+            Op::JumpIfZero(target) => Op::JumpIfZero(remap(target)),
+            Op::Jump(target) => Op::Jump(remap(target)),
+            Op::Of(target) => Op::Of(remap(target)),
+            Op::Call(target) => Op::Call(remap(target)),
+            Op::Spawn(target) => Op::Spawn(remap(target)),
+            Op::Try(target) => Op::Try(remap(target)),
+            Op::Catch(target) => Op::Catch(remap(target)),
+            op => op,
+        })
+        .collect();
+    let positions = code.positions.iter().zip(removed);
+    code.positions = positions
+        .filter(|(_, &is_removed)| !is_removed)
+        .map(|(&position, _)| position)
+        .collect();
+    for (_, index) in &mut code.labels {
+        *index = new_index[*index];
+    }
+    for index in &mut code.addresses {
+        *index = new_index[*index];
+    }
+}
+
+// folds constant arithmetic and removes instructions that cancel out
+fn peephole(code: &mut Bytecode) -> bool {
+    let entries = entry_points(code);
+    // whether control can only enter the `length` ops at `index` through the first one
+    let straight = |index: usize, length: usize| (1..length).all(|offset| !entries[index + offset]);
+    let mut removed = vec![false; code.ops.len()];
+    let mut index = 0;
+    while index < code.ops.len() {
+        match code.ops[index..] {
+            [Op::Push(a), Op::Push(b), op @ (Op::Add | Op::Sub), ..] if straight(index, 3) => {
+                let value = match op {
+                    Op::Add => a.wrapping_add(b),
+                    _ => a.wrapping_sub(b),
+                };
+                code.ops[index] = Op::Push(value);
+                removed[index + 1] = true;
+                removed[index + 2] = true;
+                index += 3;
+            }
+            [Op::Swap, Op::Swap, ..] | [Op::Dup, Op::Pop, ..] if straight(index, 2) => {
+                removed[index] = true;
+                removed[index + 1] = true;
+                index += 2;
+            }
+            // THEN, ENDTRY and CASE do nothing at runtime
+            [Op::Nop, ..] => {
+                removed[index] = true;
+                index += 1;
+            }
+            [Op::Jump(target), ..] if target as usize == index + 1 => {
+                removed[index] = true;
+                index += 1;
+            }
+            _ => index += 1,
+        }
+    }
+    let changed = removed.contains(&true);
+    if changed {
+        remove(code, &removed);
+    }
+    changed
+}
+
+// points jumps that land on an ELSE's jump straight at its target
+fn thread_jumps(code: &mut Bytecode) -> bool {
+    let mut changed = false;
+    for index in 0..code.ops.len() {
+        let (Op::JumpIfZero(start) | Op::Jump(start)) = code.ops[index] else {
+            continue;
+        };
+        let mut target = start;
+        let mut visited = vec![index];
+        while let Some(&Op::Jump(next)) = code.ops.get(target as usize) {
+            if next == UNCLOSED {
+                break;
+            }
+            // a loop of jumps never gets anywhere, leave it alone
+            if visited.contains(&(target as usize)) {
+                target = start;
+                break;
+            }
+            visited.push(target as usize);
+            target = next;
+        }
+        if target != start {
+            code.ops[index] = match code.ops[index] {
+                Op::JumpIfZero(_) => Op::JumpIfZero(target),
+                _ => Op::Jump(target),
+            };
+            changed = true;
+        }
+    }
+    changed
+}
+
+// removes ops that no path from the start of the program (or EXECUTE) reaches
+fn eliminate_dead_code(code: &mut Bytecode) -> bool {
+    let mut reached = vec![false; code.ops.len()];
+    let mut pending = vec![0];
+    pending.extend(&code.addresses);
+    while let Some(index) = pending.pop() {
+        match reached.get_mut(index) {
+            Some(reached) if !*reached => *reached = true,
+            _ => continue,
+        }
+        let target = |target: u32| (target != UNCLOSED).then_some(target as usize);
+        match code.ops[index] {
+            Op::Jump(jump_target) | Op::Catch(jump_target) => pending.extend(target(jump_target)),
+            // a call continues after it once the subroutine returns, a spawn right away and
+            // a TRY at its handler on an error
+            Op::JumpIfZero(jump_target)
+            | Op::Of(jump_target)
+            | Op::Call(jump_target)
+            | Op::Spawn(jump_target)
+            | Op::Try(jump_target) => {
+                pending.extend(target(jump_target));
+                pending.push(index + 1);
+            }
+            Op::Return | Op::Halt | Op::Exit => (),
+            _ => pending.push(index + 1),
+        }
+    }
+    let removed: Vec<bool> = reached.iter().map(|reached| !reached).collect();
+    let changed = removed.contains(&true);
+    if changed {
+        // labels of dead code would otherwise move onto the code after it
+        code.labels
+            .retain(|&(_, index)| reached.get(index).copied().unwrap_or(true));
+        remove(code, &removed);
+    }
+    changed
+}
+
+// drops labels that no call, spawn or address points at
+fn prune_labels(code: &mut Bytecode) -> bool {
+    let mut called: Vec<usize> = code
+        .ops
+        .iter()
+        .filter_map(|&op| match op {
+            Op::Call(target) | Op::Spawn(target) => Some(target as usize),
+            _ => None,
+        })
+        .collect();
+    called.extend(&code.addresses);
+    let count = code.labels.len();
+    code.labels.retain(|(_, index)| called.contains(index));
+    code.labels.len() != count
+}
+
+// the end (the RETURN) of a subroutine that can be copied into its callers: at most
+// `threshold` ops without calls, whose jumps all stay inside it
+fn inline_body(code: &Bytecode, start: usize, threshold: usize) -> Option<usize> {
+    let length = code
+        .ops
+        .get(start..)?
+        .iter()
+        .take(threshold + 1)
+        .position(|&op| op == Op::Return)?;
+    let end = start + length;
+    let inlinable = code.ops[start..end].iter().all(|&op| match op {
+        Op::JumpIfZero(target)
+        | Op::Jump(target)
+        | Op::Of(target)
+        | Op::Try(target)
+        | Op::Catch(target) => target == UNCLOSED || (start..=end).contains(&(target as usize)),
+        Op::Call(_) | Op::Execute => false,
+        _ => true,
+    });
+    inlinable.then_some(end)
+}
+
+// replaces calls of short subroutines with a copy of their body; errors in the copy still
+// point at the lines of the subroutine
+fn inline_calls(code: &mut Bytecode, threshold: usize) -> bool {
+    let bodies: Vec<Option<(usize, usize)>> = code
+        .ops
+        .iter()
+        .map(|&op| match op {
+            Op::Call(target) if target != UNCLOSED => {
+                let start = target as usize;
+                inline_body(code, start, threshold).map(|end| (start, end))
+            }
+            _ => None,
+        })
+        .collect();
+    if bodies.iter().all(Option::is_none) {
+        return false;
+    }
+
+    // where every op ends up once the bodies are copied in
+    let mut new_index = Vec::with_capacity(code.ops.len() + 1);
+    let mut next = 0;
+    for body in &bodies {
+        new_index.push(next);
+        next += body.map_or(1, |(start, end)| end - start);
+    }
+    new_index.push(next);
+    let relocate = |op: Op, map: &dyn Fn(usize) -> usize| {
+        let map = |target: u32| match target {
+            UNCLOSED => UNCLOSED,
+            target => map(target as usize) as u32,
+        };
+        match op {
+            Op::JumpIfZero(target) => Op::JumpIfZero(map(target)),
+            Op::Jump(target) => Op::Jump(map(target)),
+            Op::Of(target) => Op::Of(map(target)),
+            Op::Call(target) => Op::Call(map(target)),
+            Op::Spawn(target) => Op::Spawn(map(target)),
+            Op::Try(target) => Op::Try(map(target)),
+            Op::Catch(target) => Op::Catch(map(target)),
+            op => op,
+        }
+    };
+
+    let mut ops = Vec::with_capacity(next);
+    let mut positions = Vec::with_capacity(next);
+    for (index, body) in bodies.iter().enumerate() {
+        match *body {
+            Some((start, end)) => {
+                // jumps to the RETURN land after the copy
+                let copy_start = new_index[index];
+                for body_index in start..end {
+                    let op = relocate(code.ops[body_index], &|target| copy_start + target - start);
+                    ops.push(op);
+                    positions.push(code.positions[body_index]);
+                }
+            }
+            None => {
+                ops.push(relocate(code.ops[index], &|target| new_index[target]));
+                positions.push(code.positions[index]);
+            }
+        }
+    }
+    code.ops = ops;
+    code.positions = positions;
+    for (_, index) in &mut code.labels {
+        *index = new_index[*index];
+    }
+    for index in &mut code.addresses {
+        *index = new_index[*index];
+    }
+    true
+}
+
+pub const DEFAULT_INLINE_THRESHOLD: usize = 8;
+
+pub struct Options {
+    // 1 rewrites instructions and removes dead code, 2 also inlines subroutines
+    pub level: u8,
+    // the longest subroutine body (in ops) that gets inlined
+    pub inline_threshold: usize,
+}
+
+// rewrites the code until no pattern applies anymore. The result computes the same
+// output, but may not fail where removed instructions would have under- or overflowed
+// the stack
+pub fn optimize(code: &mut Bytecode, options: &Options) {
+    if options.level == 0 {
+        return;
+    }
+    loop {
+        let inlined = options.level >= 2 && inline_calls(code, options.inline_threshold);
+        let rewritten =
+            thread_jumps(code) | peephole(code) | eliminate_dead_code(code) | prune_labels(code);
+        if !inlined && !rewritten {
+            break;
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::bytecode::Op;
+use crate::interpreter::{label_name, Program, RuntimeError};
+
+// what --profile counts while running a program, per op
+pub struct Profile {
+    // how often every op ran
+    counts: Vec<u64>,
+    // how often the subroutine starting at every op was called
+    calls: Vec<u64>,
+    // time spent in the subroutine starting at every op, including what it calls
+    time: Vec<Duration>,
+    // the subroutines being run, when they were entered and their call stack, innermost last
+    frames: Vec<(usize, Instant, usize)>,
+    // every call stack seen, as its caller's stack and the subroutine called; the first is
+    // the top level
+    stacks: Vec<(usize, usize)>,
+    stack_ids: HashMap<(usize, usize), usize>,
+    // instructions run in every call stack
+    samples: Vec<u64>,
+    // how often every subroutine called another, by where the caller starts (None for the
+    // top level) and where the one it called starts
+    edges: HashMap<(Option<usize>, usize), u64>,
+    elapsed: Duration,
+}
+
+impl Profile {
+    pub fn new(program: &Program) -> Self {
+        let length = program.code.ops.len();
+        // a label at the very end of the program points past the last op
+        Profile {
+            counts: vec![0; length],
+            calls: vec![0; length + 1],
+            time: vec![Duration::ZERO; length + 1],
+            frames: Vec::new(),
+            stacks: vec![(0, 0)],
+            stack_ids: HashMap::new(),
+            samples: vec![0],
+            edges: HashMap::new(),
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    // runs the program like Program::run, counting every step
+    pub fn run(&mut self, program: &mut Program) -> Result<(), RuntimeError> {
+        let start = Instant::now();
+        let result = self.run_counting(program);
+        while !self.frames.is_empty() {
+            self.leave();
+        }
+        self.elapsed = start.elapsed();
+        result
+    }
+
+    fn run_counting(&mut self, program: &mut Program) -> Result<(), RuntimeError> {
+        while !program.halted {
+            let pc = program.pc;
+            let depth = program.call_depth();
+            if let Some(count) = self.counts.get_mut(pc) {
+                *count += 1;
+            }
+            let stack = self.stack();
+            self.samples[stack] += 1;
+            program.step()?;
+            match program.code.ops.get(pc) {
+                Some(&Op::Call(_) | &Op::Execute) => {
+                    let target = program.pc;
+                    self.calls[target] += 1;
+                    let caller = self.frames.last().map(|&(start, _, _)| start);
+                    *self.edges.entry((caller, target)).or_default() += 1;
+                    // a tail call replaces the caller's frame
+                    if program.call_depth() == depth {
+                        self.leave();
+                    }
+                    let stack = self.push_stack(target);
+                    self.frames.push((target, Instant::now(), stack));
+                }
+                Some(Op::Return) => self.leave(),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn edges(&self) -> &HashMap<(Option<usize>, usize), u64> {
+        &self.edges
+    }
+
+    fn leave(&mut self) {
+        let Some((start, entered, _)) = self.frames.pop() else {
+            return;
+        };
+        // a recursive call's time is already part of the outermost one
+        if self.frames.iter().all(|&(other, _, _)| other != start) {
+            self.time[start] += entered.elapsed();
+        }
+    }
+
+    fn stack(&self) -> usize {
+        self.frames.last().map_or(0, |&(_, _, stack)| stack)
+    }
+
+    // the current call stack with a call of the subroutine at `start` on top
+    fn push_stack(&mut self, start: usize) -> usize {
+        let caller = self.stack();
+        *self.stack_ids.entry((caller, start)).or_insert_with(|| {
+            self.stacks.push((caller, start));
+            self.samples.push(0);
+            self.stacks.len() - 1
+        })
+    }
+
+    // the instructions run in every call stack in the folded format of flamegraph.pl and
+    // inferno, outermost subroutine first, e.g.
+    //   (top level) 4
+    //   (top level);fib 18
+    //   (top level);fib;fib 30
+    pub fn folded(&self, program: &Program) -> String {
+        let mut lines: Vec<String> = (0..self.stacks.len())
+            .filter(|&stack| self.samples[stack] > 0)
+            .map(|mut stack| {
+                let count = self.samples[stack];
+                let mut names = Vec::new();
+                while stack != 0 {
+                    let (caller, start) = self.stacks[stack];
+                    let name = program.code.enclosing_label(start).unwrap_or_default();
+                    names.push(label_name(name));
+                    stack = caller;
+                }
+                names.push("(top level)".to_string());
+                names.reverse();
+                format!("{} {}\n", names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    // the lines and subroutines that ran, the most expensive first, e.g.
+    //   profile: 1200 instructions in 1.2ms
+    //
+    //   line  instructions        %  source
+    //      8           600   50.0%  dup
+    //
+    //   label        calls  instructions        %  cumulative time
+    //   (top level)      -           100    8.3%  1.2ms
+    //   fib            100          1100   91.7%  1.1ms
+    pub fn report(&self, program: &Program) -> String {
+        let code = &program.code;
+        let total: u64 = self.counts.iter().sum();
+        let percent = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+        let mut out = format!(
+            "profile: {} instructions in {:.3?}\n\n",
+            total, self.elapsed
+        );
+
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        for (index, &count) in self.counts.iter().enumerate() {
+            if count > 0 {
+                *lines.entry(code.positions[index].0).or_default() += count;
+            }
+        }
+        let mut lines: Vec<(usize, u64)> = lines.into_iter().collect();
+        lines.sort_by_key(|&(_, count)| Reverse(count));
+        out.push_str("line  instructions        %  source\n");
+        for (line_number, count) in lines {
+            let source = program.source.line(line_number).map_or("", str::trim);
+            out.push_str(&format!(
+                "{:>4}  {:>12}  {:>6.1}%  {}\n",
+                line_number,
+                count,
+                percent(count),
+                source
+            ));
+        }
+
+        // instructions run in the body of every subroutine, keyed by where it starts
+        let mut bodies: BTreeMap<Option<usize>, u64> = BTreeMap::new();
+        for (index, &count) in self.counts.iter().enumerate() {
+            let start = code
+                .labels
+                .iter()
+                .map(|&(_, start)| start)
+                .filter(|&start| start <= index)
+                .max();
+            *bodies.entry(start).or_default() += count;
+        }
+        let mut labels = vec![(
+            "(top level)".to_string(),
+            None,
+            bodies.get(&None).copied().unwrap_or(0),
+            self.elapsed,
+        )];
+        for (&start, &count) in &bodies {
+            // subroutines that never ran
+            let Some(start) = start.filter(|&start| count > 0 || self.calls[start] > 0) else {
+                continue;
+            };
+            let name = code.enclosing_label(start).unwrap_or_default();
+            labels.push((
+                label_name(name),
+                Some(self.calls[start]),
+                count,
+                self.time[start],
+            ));
+        }
+        labels.sort_by_key(|label| Reverse(label.3));
+        let width = labels.iter().map(|label| label.0.len()).max().unwrap_or(0);
+        out.push_str(&format!(
+            "\n{:<width$}  {:>6}  instructions        %  cumulative time\n",
+            "label", "calls"
+        ));
+        for (name, calls, count, time) in labels {
+            let calls = calls.map_or("-".to_string(), |calls| calls.to_string());
+            out.push_str(&format!(
+                "{:<width$}  {:>6}  {:>12}  {:>6.1}%  {:.3?}\n",
+                name,
+                calls,
+                count,
+                percent(count),
+                time
+            ));
+        }
+        out
+    }
+}
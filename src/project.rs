@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// the file with a project's default options, found in the program's directory or the
+// closest directory above it
+pub const FILE_NAME: &str = "fifth.toml";
+
+// the options a project file can set, by their command-line name, and whether they're
+// flags (true or false) rather than values
+const OPTIONS: &[(&str, bool)] = &[
+    ("stack-size", false),
+    ("stack-init", false),
+    ("max-output", false),
+    ("timeout", false),
+    ("seed", false),
+    ("optimize", false),
+    ("inline-threshold", false),
+    ("jit", true),
+    ("allow-fs", true),
+    ("allow-net", true),
+    ("allow-env", true),
+    ("deny-warnings", true),
+    ("case-sensitive", true),
+    ("strict-case", true),
+    ("allow", false),
+    ("deny", false),
+    ("error-format", false),
+    ("color", false),
+];
+
+// the project file that applies to `filename` (a program or the directory of golden
+// tests), searched from the current directory for programs that aren't files
+pub fn find(filename: &str) -> Option<PathBuf> {
+    let start = match filename {
+        "" | "-" => std::env::current_dir().ok()?,
+        filename if Path::new(filename).is_dir() => PathBuf::from(filename),
+        filename => match Path::new(filename).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+    };
+    // relative paths have no ancestors beyond the current directory
+    start
+        .canonicalize()
+        .ok()?
+        .ancestors()
+        .map(|directory| directory.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+// the options in a project file as command-line arguments, which the arguments given on
+// the command line come after and so override. The file is a small part of TOML: a
+// `name = value` per line with a number, a string, true or false, or an array of
+// integers or strings on one line, and comments, e.g.
+//   stack-size = 1024
+//   optimize = 2
+//   allow-fs = true
+//   allow = ["dup-pop", "empty-branch"]
+pub fn load(path: &Path) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut args = Vec::new();
+    for (line_number, line) in (1..).zip(text.lines()) {
+        let error = |message: &str| format!("{} line {}: {}", path.display(), line_number, message);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(error("expected `name = value`"));
+        };
+        let name = name.trim();
+        let Some(&(_, flag)) = OPTIONS.iter().find(|(option, _)| *option == name) else {
+            return Err(error(&format!("unknown option '{}'", name)));
+        };
+        let value = parse_value(value.trim()).ok_or_else(|| error("invalid value"))?;
+        match (flag, value, name) {
+            (true, Value::Bool(true), _) => args.push(format!("--{}", name)),
+            (true, Value::Bool(false), _) => (),
+            (true, _, _) => return Err(error(&format!("'{}' is true or false", name))),
+            (false, Value::Bool(_), _) => {
+                return Err(error(&format!("'{}' isn't true or false", name)))
+            }
+            (false, Value::Text(level), "optimize") => args.push(format!("-O{}", level)),
+            (false, Value::Text(value), _) => args.push(format!("--{}={}", name, value)),
+        }
+    }
+    Ok(args)
+}
+
+// the line up to a '#' outside of a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => (),
+        }
+    }
+    line
+}
+
+enum Value {
+    Bool(bool),
+    // as it would be written on the command line, with an array's items separated by commas
+    Text(String),
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    if let Some(items) = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+    {
+        let items: Vec<String> = items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| match parse_value(item)? {
+                Value::Text(text) => Some(text),
+                Value::Bool(_) => None,
+            })
+            .collect::<Option<_>>()?;
+        return Some(Value::Text(items.join(",")));
+    }
+    if let Some(string) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        let mut unescaped = String::new();
+        let mut chars = string.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unescaped.push(match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    c @ ('"' | '\\') => c,
+                    _ => return None,
+                }),
+                '"' => return None,
+                c => unescaped.push(c),
+            }
+        }
+        return Some(Value::Text(unescaped));
+    }
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        // whole numbers, or decimals like a timeout of 0.5 seconds
+        value
+            if value.parse::<u64>().is_ok()
+                || value.chars().all(|c| c.is_ascii_digit() || c == '.')
+                    && value.parse::<f64>().is_ok() =>
+        {
+            Some(Value::Text(value.to_string()))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,233 @@
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fifth::clock::{Clock, SystemClock};
+
+use crate::file_io;
+
+// recordings start with these bytes and the format version; version 1 recordings have no
+// ticks
+const MAGIC: &[u8; 4] = b"5THR";
+const VERSION: u16 = 2;
+
+// everything a run depends on besides the program and its command line: the seed of
+// RANDOM (which otherwise comes from the clock), what every READ_CHAR got, None
+// standing for the end of the input, and what every TICKS got
+pub struct Recording {
+    seed: u64,
+    reads: Rc<RefCell<Vec<Option<u8>>>>,
+    ticks: Rc<RefCell<Vec<u64>>>,
+}
+
+// passes the input through, writing down every read
+struct Recorder {
+    input: Box<dyn Read>,
+    reads: Rc<RefCell<Vec<Option<u8>>>>,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let result = self.input.read(buffer);
+        let mut reads = self.reads.borrow_mut();
+        match result {
+            Ok(count) if count > 0 => reads.extend(buffer[..count].iter().copied().map(Some)),
+            // the program reads 0 for an unreadable input, like for its end
+            _ => reads.push(None),
+        }
+        result
+    }
+}
+
+// gives the program what it read when it was recorded, and then the end of the input
+struct Replayer {
+    reads: std::vec::IntoIter<Option<u8>>,
+}
+
+impl Read for Replayer {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match (buffer.first_mut(), self.reads.next()) {
+            (Some(first), Some(Some(value))) => {
+                *first = value;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+// the system clock, writing down every time it's read
+struct RecordingClock {
+    clock: SystemClock,
+    ticks: Rc<RefCell<Vec<u64>>>,
+}
+
+impl Clock for RecordingClock {
+    fn sleep(&mut self, milliseconds: u64) {
+        self.clock.sleep(milliseconds);
+    }
+
+    fn now(&mut self) -> u64 {
+        let now = self.clock.now();
+        self.ticks.borrow_mut().push(now);
+        now
+    }
+}
+
+// gives the program the times it read when it was recorded, and then the last of them
+// forever, without sleeping
+struct ReplayingClock {
+    ticks: std::vec::IntoIter<u64>,
+    last: u64,
+}
+
+impl Clock for ReplayingClock {
+    fn sleep(&mut self, _: u64) {}
+
+    fn now(&mut self) -> u64 {
+        if let Some(now) = self.ticks.next() {
+            self.last = now;
+        }
+        self.last
+    }
+}
+
+fn invalid(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is not a recording", path.display()),
+    )
+}
+
+impl Recording {
+    // the given seed, or one from the clock like RANDOM would use
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_nanos() as u64)
+                .unwrap_or(0)
+        });
+        Recording {
+            seed,
+            reads: Rc::new(RefCell::new(Vec::new())),
+            ticks: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // wraps the program's input so every read ends up in the recording
+    pub fn record(&self, input: Box<dyn Read>) -> Box<dyn Read> {
+        Box::new(Recorder {
+            input,
+            reads: Rc::clone(&self.reads),
+        })
+    }
+
+    // a system clock whose every reading ends up in the recording
+    pub fn record_clock(&self) -> impl Clock {
+        RecordingClock {
+            clock: SystemClock::new(),
+            ticks: Rc::clone(&self.ticks),
+        }
+    }
+
+    // an input that reads what was recorded
+    pub fn replay(&self) -> Box<dyn Read> {
+        Box::new(Replayer {
+            reads: self.reads.borrow().clone().into_iter(),
+        })
+    }
+
+    // a clock that reads what was recorded
+    pub fn replay_clock(&self) -> impl Clock {
+        ReplayingClock {
+            ticks: self.ticks.borrow().clone().into_iter(),
+            last: 0,
+        }
+    }
+
+    // the magic and version, the seed (u64) and the number of reads (u32), then every read
+    // as two bytes: 1 and the byte read, or 0 0 for the end of the input, and then the
+    // number of ticks (u32) and every one of them (u64); little endian
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let reads = self.reads.borrow();
+        let ticks = self.ticks.borrow();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(self.seed.to_le_bytes());
+        bytes.extend((reads.len() as u32).to_le_bytes());
+        for read in reads.iter() {
+            bytes.extend(match read {
+                Some(value) => [1, *value],
+                None => [0, 0],
+            });
+        }
+        bytes.extend((ticks.len() as u32).to_le_bytes());
+        for now in ticks.iter() {
+            bytes.extend(now.to_le_bytes());
+        }
+        file_io::write_bytes_to_file(path, &bytes)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let bytes = file_io::read_file(path)?;
+        if !bytes.starts_with(MAGIC) {
+            return Err(invalid(path));
+        }
+        let header = (bytes.get(4..6), bytes.get(6..14), bytes.get(14..18));
+        let (Some(version), Some(seed), Some(count)) = header else {
+            return Err(invalid(path));
+        };
+        let version = u16::from_le_bytes(version.try_into().unwrap());
+        if version == 0 || version > VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} was recorded by another version of fifth",
+                    path.display()
+                ),
+            ));
+        }
+        let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+        let Some((body, rest)) = bytes[18..].split_at_checked(count * 2) else {
+            return Err(invalid(path));
+        };
+        let reads = body
+            .chunks(2)
+            .map(|read| match read {
+                [1, value] => Ok(Some(*value)),
+                [0, 0] => Ok(None),
+                _ => Err(invalid(path)),
+            })
+            .collect::<io::Result<_>>()?;
+        let ticks = if version >= 2 {
+            let (Some(count), Some(ticks)) = (rest.get(..4), rest.get(4..)) else {
+                return Err(invalid(path));
+            };
+            let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+            if ticks.len() != count * 8 {
+                return Err(invalid(path));
+            }
+            ticks
+                .chunks(8)
+                .map(|now| u64::from_le_bytes(now.try_into().unwrap()))
+                .collect()
+        } else if rest.is_empty() {
+            Vec::new()
+        } else {
+            return Err(invalid(path));
+        };
+        Ok(Recording {
+            seed: u64::from_le_bytes(seed.try_into().unwrap()),
+            reads: Rc::new(RefCell::new(reads)),
+            ticks: Rc::new(RefCell::new(ticks)),
+        })
+    }
+}
@@ -0,0 +1,187 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::atomic::Ordering;
+
+use crate::cli::Config;
+use crate::diagnostics::{Diagnostic, Reporter};
+use crate::file_io;
+use crate::interpreter::{Program, RuntimeError};
+use crate::interrupt;
+use crate::Outcome;
+use fifth::environment::StdEnvironment;
+use fifth::files::StdFileSystem;
+use fifth::net::StdNetwork;
+
+// the lines typed since the last complete chunk, and what they leave open: a subroutine
+// that hasn't returned yet, or an IF, TRY or CASE that hasn't been closed
+#[derive(Default)]
+struct Chunk {
+    text: String,
+    depth: usize,
+    defining: bool,
+    defines_label: bool,
+}
+
+impl Chunk {
+    fn push(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+        let words = line
+            .split_whitespace()
+            .take_while(|word| !word.starts_with('#'));
+        for word in words {
+            match word.to_lowercase().as_str() {
+                // a DATA block's name ends with ':' but isn't a label
+                "data" => break,
+                word if word.ends_with(':') => {
+                    self.defining = true;
+                    self.defines_label = true;
+                }
+                "if" | "try" | "case" | "of" => self.depth += 1,
+                "then" | "endtry" | "endof" | "endcase" => {
+                    self.depth = self.depth.saturating_sub(1)
+                }
+                // a RETURN outside of any IF closes the subroutine, as in the formatter
+                "return" if self.depth == 0 => self.defining = false,
+                _ => (),
+            }
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.depth == 0 && !self.defining
+    }
+}
+
+// `fifth repl [<filename>]`: runs the program, if there is one, and then every line typed,
+// keeping the stack, memory and subroutines between them. A line that defines a subroutine
+// only defines it, up to the RETURN that closes it, and lines are collected until every IF,
+// TRY and CASE is closed; an empty line ends a chunk early. A line with errors is forgotten,
+// and an error while running empties the stack. The session ends at the end of the input
+// or at EXIT, with its code
+pub fn repl(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = crate::new_program(config, source);
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
+    }
+    if let Some(seed) = config.seed {
+        program.seed(seed);
+    }
+    if let Some(input_file) = &config.input_file {
+        program.set_input(file_io::open_input(input_file)?);
+    }
+    program.set_output(file_io::Stdout::new());
+    if config.allow_fs {
+        program.set_file_system(StdFileSystem);
+    }
+    if config.allow_net {
+        program.set_network(StdNetwork);
+    }
+    if config.allow_env {
+        program.set_environment(StdEnvironment);
+    }
+    program.set_interrupt(&interrupt::INTERRUPTED);
+    program.stack.extend(&config.stack_init);
+    program.stack.extend(&config.program_args);
+
+    if let Some(outcome) = run(config, &mut program)? {
+        return Ok(outcome);
+    }
+    // prompts and the stack after every line only make sense to someone typing
+    let interactive = io::stdin().is_terminal();
+    let mut chunk = Chunk::default();
+    let mut line = String::new();
+    while program.exit_code.is_none() {
+        if interactive {
+            let prompt = if chunk.text.is_empty() { "> " } else { "| " };
+            io::stdout().write_all(prompt.as_bytes())?;
+            io::stdout().flush()?;
+        }
+        line.clear();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if !line.trim().is_empty() {
+            chunk.push(line);
+            if !chunk.is_complete() {
+                continue;
+            }
+        } else if chunk.text.is_empty() {
+            continue;
+        }
+        let chunk = std::mem::take(&mut chunk);
+
+        let start = program.code.ops.len();
+        if let Err(diagnostics) = program.feed(&chunk.text) {
+            // feed() took the lines back, but the errors point into them
+            let mut source = program.source.clone();
+            source.push(&chunk.text);
+            let reporter = Reporter::new(config, source.text());
+            for err in diagnostics.iter() {
+                reporter.emit(&Diagnostic::parse_error(err));
+            }
+            continue;
+        }
+        if chunk.defines_label {
+            continue;
+        }
+        program.pc = start;
+        program.halted = false;
+        if let Some(outcome) = run(config, &mut program)? {
+            return Ok(outcome);
+        }
+        if interactive && program.exit_code.is_none() {
+            let mut text = format!("<{}>", program.stack.len());
+            for value in &program.stack {
+                text.push_str(&format!(" {}", value));
+            }
+            text.push('\n');
+            io::stdout().write_all(text.as_bytes())?;
+        }
+    }
+    Ok(Outcome::Halted {
+        exit_code: program.exit_code,
+    })
+}
+
+// runs the program from its pc, reporting an error and aborting the run instead of ending
+// the session; Ctrl-C stops the run, but not the session. Only output that can't be
+// written ends it
+fn run(
+    config: &Config,
+    program: &mut Program,
+) -> Result<Option<Outcome>, Box<dyn std::error::Error>> {
+    let result = {
+        let _interrupt = interrupt::Handler::install();
+        program.run()
+    };
+    match result {
+        Ok(()) => (),
+        Err(RuntimeError::OutputFailed(_)) if file_io::BROKEN_PIPE.load(Ordering::Relaxed) => {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe).into());
+        }
+        Err(error @ RuntimeError::OutputFailed(_)) => {
+            return Ok(Some(Outcome::RuntimeFailed {
+                backtrace: program.backtrace(),
+                error,
+                stack: program.stack.clone(),
+            }));
+        }
+        Err(error) => {
+            // every run starts at the top level, though the lines typed come after the
+            // subroutines in the code
+            let mut backtrace = program.backtrace();
+            if let Some(frame) = backtrace.last_mut() {
+                frame.label = None;
+            }
+            let reporter = Reporter::new(config, program.source.text());
+            reporter.emit(&Diagnostic::runtime_error(
+                &error,
+                backtrace,
+                program.stack.clone(),
+            ));
+            program.abort();
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,272 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::bytecode::Bytecode;
+use crate::exceptions::Handler;
+use crate::tasks::Task;
+
+// snapshot files start with MAGIC and VERSION, followed by the fingerprint of the code, the
+// pc, the flags, the exit code, the RNG state, the stack, the call stack, the id of the
+// running task, the tasks, the channels, the exception handlers of every task and the
+// memory cells and the number of cells ALLOT handed out; all numbers are little-endian.
+// Version 1 files have no tasks, version 2 files no channels, version 3 files no handlers,
+// version 4 files no memory and version 5 files nothing allotted
+pub const MAGIC: &[u8; 4] = b"5THS";
+pub const VERSION: u16 = 6;
+
+// the state of a running program, without its code: Program::restore() continues from it
+// in a program parsed from the same source. The input and output aren't part of it
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    // fingerprint() of the code the snapshot was taken from
+    pub code: u64,
+    pub pc: usize,
+    pub stack: Vec<u8>,
+    // the return addresses, innermost last
+    pub call_stack: Vec<usize>,
+    // the TRYs that haven't reached their CATCH, innermost last
+    pub handlers: Vec<Handler>,
+    pub halted: bool,
+    pub exit_code: Option<u8>,
+    // the state of RANDOM, so a resumed program continues the same sequence
+    pub rng: u64,
+    // the id of the running task, whose machine the fields above are
+    pub task: usize,
+    // every task by id once the program spawned one; the running task's entry is empty
+    pub tasks: Vec<Task>,
+    // the values waiting in every channel by id, oldest first
+    pub channels: Vec<Vec<u8>>,
+    // the cells of FETCH and STORE; cells past its end are 0
+    pub memory: Vec<u8>,
+    pub allotted: usize,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    NotSnapshot,
+    UnsupportedVersion(u16),
+    Truncated,
+    OtherProgram,
+    // a pc or return address outside of the code
+    InvalidAddress(usize),
+    StackTooLarge(usize),
+    InvalidTask(usize),
+    MemoryTooLarge(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::NotSnapshot => write!(f, "Not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "Snapshot has version {}, but only versions up to {} are supported",
+                version, VERSION
+            ),
+            SnapshotError::Truncated => write!(f, "Snapshot is truncated"),
+            SnapshotError::OtherProgram => {
+                write!(f, "Snapshot was taken from a different program")
+            }
+            SnapshotError::InvalidAddress(address) => {
+                write!(f, "Snapshot points outside of the code at op {}", address)
+            }
+            SnapshotError::StackTooLarge(size) => write!(
+                f,
+                "Snapshot has {} values on the stack, more than the stack size",
+                size
+            ),
+            SnapshotError::InvalidTask(id) => {
+                write!(f, "Snapshot runs task {}, which it doesn't have", id)
+            }
+            SnapshotError::MemoryTooLarge(size) => {
+                write!(f, "Snapshot has {} memory cells, more than there are", size)
+            }
+        }
+    }
+}
+
+impl core::error::Error for SnapshotError {}
+
+// FNV-1a over the compiled code, so a snapshot isn't resumed in another program
+pub fn fingerprint(code: &Bytecode) -> u64 {
+    code.to_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+fn take<'a>(bytes: &mut &'a [u8], count: usize) -> Result<&'a [u8], SnapshotError> {
+    if count > bytes.len() {
+        return Err(SnapshotError::Truncated);
+    }
+    let (taken, rest) = bytes.split_at(count);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Result<usize, SnapshotError> {
+    Ok(u32::from_le_bytes(take(bytes, 4)?.try_into().unwrap()) as usize)
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Result<u64, SnapshotError> {
+    Ok(u64::from_le_bytes(take(bytes, 8)?.try_into().unwrap()))
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend((value as u32).to_le_bytes());
+}
+
+// a stack and a call stack, each after its length
+fn put_stacks(bytes: &mut Vec<u8>, stack: &[u8], call_stack: &[usize]) {
+    put_u32(bytes, stack.len());
+    bytes.extend(stack);
+    put_u32(bytes, call_stack.len());
+    for &address in call_stack {
+        put_u32(bytes, address);
+    }
+}
+
+// handlers after their count, each as its catch address, depth and call depth
+fn put_handlers(bytes: &mut Vec<u8>, handlers: &[Handler]) {
+    put_u32(bytes, handlers.len());
+    for handler in handlers {
+        put_u32(bytes, handler.catch);
+        put_u32(bytes, handler.depth);
+        put_u32(bytes, handler.calls);
+    }
+}
+
+fn take_handlers(bytes: &mut &[u8]) -> Result<Vec<Handler>, SnapshotError> {
+    (0..take_u32(bytes)?)
+        .map(|_| {
+            Ok(Handler {
+                catch: take_u32(bytes)?,
+                depth: take_u32(bytes)?,
+                calls: take_u32(bytes)?,
+            })
+        })
+        .collect()
+}
+
+fn take_stacks(bytes: &mut &[u8]) -> Result<(Vec<u8>, Vec<usize>), SnapshotError> {
+    let size = take_u32(bytes)?;
+    let stack = take(bytes, size)?.to_vec();
+    let depth = take_u32(bytes)?;
+    let call_stack = (0..depth)
+        .map(|_| take_u32(bytes))
+        .collect::<Result<_, _>>()?;
+    Ok((stack, call_stack))
+}
+
+impl Snapshot {
+    // the flags byte has bit 0 for halted and bit 1 for an exit code, which follows it
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(self.code.to_le_bytes());
+        put_u32(&mut bytes, self.pc);
+        bytes.push(u8::from(self.halted) | u8::from(self.exit_code.is_some()) << 1);
+        bytes.push(self.exit_code.unwrap_or(0));
+        bytes.extend(self.rng.to_le_bytes());
+        put_stacks(&mut bytes, &self.stack, &self.call_stack);
+        // every task has its pc, a result flag and the result, and its stacks
+        put_u32(&mut bytes, self.task);
+        put_u32(&mut bytes, self.tasks.len());
+        for task in &self.tasks {
+            put_u32(&mut bytes, task.pc);
+            bytes.push(u8::from(task.result.is_some()));
+            bytes.push(task.result.unwrap_or(0));
+            put_stacks(&mut bytes, &task.stack, &task.call_stack);
+        }
+        put_u32(&mut bytes, self.channels.len());
+        for values in &self.channels {
+            put_u32(&mut bytes, values.len());
+            bytes.extend(values);
+        }
+        // the running task's first
+        put_handlers(&mut bytes, &self.handlers);
+        for task in &self.tasks {
+            put_handlers(&mut bytes, &task.handlers);
+        }
+        put_u32(&mut bytes, self.memory.len());
+        bytes.extend(&self.memory);
+        put_u32(&mut bytes, self.allotted);
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(SnapshotError::NotSnapshot);
+        }
+        let bytes = &mut bytes;
+        take(bytes, MAGIC.len())?;
+        let version = u16::from_le_bytes(take(bytes, 2)?.try_into().unwrap());
+        if version == 0 || version > VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let code = take_u64(bytes)?;
+        let pc = take_u32(bytes)?;
+        let flags = take(bytes, 2)?;
+        let (flags, exit_code) = (flags[0], flags[1]);
+        let rng = take_u64(bytes)?;
+        let (stack, call_stack) = take_stacks(bytes)?;
+        let mut task = 0;
+        let mut tasks = Vec::new();
+        if version >= 2 {
+            task = take_u32(bytes)?;
+            for _ in 0..take_u32(bytes)? {
+                let pc = take_u32(bytes)?;
+                let result = take(bytes, 2)?;
+                let result = (result[0] != 0).then_some(result[1]);
+                let (stack, call_stack) = take_stacks(bytes)?;
+                tasks.push(Task {
+                    pc,
+                    stack,
+                    call_stack,
+                    handlers: Vec::new(),
+                    result,
+                });
+            }
+        }
+        let mut channels = Vec::new();
+        if version >= 3 {
+            for _ in 0..take_u32(bytes)? {
+                let length = take_u32(bytes)?;
+                channels.push(take(bytes, length)?.to_vec());
+            }
+        }
+        let mut handlers = Vec::new();
+        if version >= 4 {
+            handlers = take_handlers(bytes)?;
+            for task in &mut tasks {
+                task.handlers = take_handlers(bytes)?;
+            }
+        }
+        let mut memory = Vec::new();
+        if version >= 5 {
+            let size = take_u32(bytes)?;
+            memory = take(bytes, size)?.to_vec();
+        }
+        let allotted = if version >= 6 { take_u32(bytes)? } else { 0 };
+        Ok(Snapshot {
+            code,
+            pc,
+            stack,
+            call_stack,
+            handlers,
+            halted: flags & 1 != 0,
+            exit_code: (flags & 2 != 0).then_some(exit_code),
+            rng,
+            task,
+            tasks,
+            channels,
+            memory,
+            allotted,
+        })
+    }
+}
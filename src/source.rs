@@ -0,0 +1,71 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// the text of a program in one buffer with the offset every line starts at, so a line can
+// be looked up by number without keeping each one as a string of its own. Lines end like
+// in str::lines(), at "\n" or "\r\n"
+#[derive(Clone, Debug, Default)]
+pub struct Source {
+    text: String,
+    starts: Vec<usize>,
+}
+
+impl Source {
+    pub fn new(text: &str) -> Self {
+        let mut source = Source::default();
+        source.push(text);
+        source
+    }
+
+    // adds `text` after the last line, starting a new line if the last one has no ending
+    pub fn push(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        let offset = self.text.len();
+        self.text.push_str(text);
+        let starts = text
+            .match_indices('\n')
+            .map(|(index, _)| offset + index + 1);
+        self.starts.extend(
+            core::iter::once(offset)
+                .chain(starts)
+                .filter(|&start| start < self.text.len()),
+        );
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // the line with the 1-based `line_number`, without its line ending
+    pub fn line(&self, line_number: usize) -> Option<&str> {
+        let start = *self.starts.get(line_number.checked_sub(1)?)?;
+        let end = self
+            .starts
+            .get(line_number)
+            .copied()
+            .unwrap_or(self.text.len());
+        let line = &self.text[start..end];
+        Some(match line.strip_suffix('\n') {
+            Some(line) => line.strip_suffix('\r').unwrap_or(line),
+            None => line,
+        })
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.text.lines()
+    }
+
+    // the number of lines
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+}
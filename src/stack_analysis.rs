@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::analysis;
+use crate::bytecode;
+use crate::interpreter::{Program, Token};
+use crate::test_runner::is_test;
+
+// the set of stack depths (0..=capacity) possible at some point of the program
+#[derive(Clone, PartialEq)]
+struct DepthSet {
+    bits: Vec<u64>,
+}
+
+impl DepthSet {
+    fn new(capacity: usize) -> Self {
+        Self {
+            bits: vec![0; capacity / 64 + 1],
+        }
+    }
+
+    fn with(capacity: usize, depth: usize) -> Self {
+        let mut set = Self::new(capacity);
+        set.insert(depth);
+        set
+    }
+
+    fn insert(&mut self, depth: usize) {
+        self.bits[depth / 64] |= 1 << (depth % 64);
+    }
+
+    // returns whether anything was added
+    fn union(&mut self, other: &DepthSet) -> bool {
+        let mut changed = false;
+        for (bits, other_bits) in self.bits.iter_mut().zip(&other.bits) {
+            changed |= *other_bits & !*bits != 0;
+            *bits |= other_bits;
+        }
+        changed
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.bits.iter().enumerate().flat_map(|(word, &bits)| {
+            (0..64)
+                .filter(move |bit| bits & (1 << bit) != 0)
+                .map(move |bit| word * 64 + bit)
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum StackIssue {
+    Underflow {
+        line_number: usize,
+        column: usize,
+        token: Token,
+        max_depth: usize,
+    },
+    Overflow {
+        line_number: usize,
+        column: usize,
+        token: Token,
+        min_depth: usize,
+    },
+}
+
+impl StackIssue {
+    pub fn line_number(&self) -> usize {
+        match self {
+            StackIssue::Underflow { line_number, .. }
+            | StackIssue::Overflow { line_number, .. } => *line_number,
+        }
+    }
+
+    pub fn column(&self) -> usize {
+        match self {
+            StackIssue::Underflow { column, .. } | StackIssue::Overflow { column, .. } => *column,
+        }
+    }
+
+    // stable identifier for tooling
+    pub fn code(&self) -> &'static str {
+        match self {
+            StackIssue::Underflow { .. } => "guaranteed-underflow",
+            StackIssue::Overflow { .. } => "guaranteed-overflow",
+        }
+    }
+}
+
+impl fmt::Display for StackIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackIssue::Underflow {
+                token, max_depth, ..
+            } => write!(
+                f,
+                "Stack underflow on every path ('{}' with at most {} byte(s) on the stack)",
+                token, max_depth
+            ),
+            StackIssue::Overflow {
+                token, min_depth, ..
+            } => write!(
+                f,
+                "Stack overflow on every path ('{}' with at least {} byte(s) on the stack)",
+                token, min_depth
+            ),
+        }
+    }
+}
+
+pub struct StackReport {
+    pub issues: Vec<StackIssue>,
+    pub min_depth: usize,
+    pub max_depth: usize,
+}
+
+// entry states of every token for one entry point, plus the depths it can return with
+struct Flow {
+    states: Vec<Option<DepthSet>>,
+    exits: DepthSet,
+}
+
+// adds `depths` to the entry state of the token at `index`, queueing it if that changed it
+fn merge(
+    states: &mut [Option<DepthSet>],
+    pending: &mut Vec<usize>,
+    index: usize,
+    depths: &DepthSet,
+) {
+    let Some(state) = states.get_mut(index) else {
+        return;
+    };
+    match state {
+        Some(state) => {
+            if state.union(depths) {
+                pending.push(index);
+            }
+        }
+        None => {
+            *state = Some(depths.clone());
+            pending.push(index);
+        }
+    }
+}
+
+struct Analyzer<'a> {
+    program: &'a Program,
+    targets: Vec<usize>,
+    // the subroutines PUSH_ADDR names, which EXECUTE can call
+    addressed: Vec<usize>,
+    capacity: usize,
+    // (label index, entry depth) -> depths the subroutine can return with
+    summaries: HashMap<(usize, usize), DepthSet>,
+}
+
+impl Analyzer<'_> {
+    fn flow(&mut self, entry: usize, depths: DepthSet) -> Flow {
+        let tokens = &self.program.tokens;
+        let mut states: Vec<Option<DepthSet>> = vec![None; tokens.len()];
+        let mut exits = DepthSet::new(self.capacity);
+        if entry >= tokens.len() {
+            return Flow { states, exits };
+        }
+        states[entry] = Some(depths);
+        let mut pending = vec![entry];
+
+        while let Some(index) = pending.pop() {
+            let state = states[index].clone().unwrap();
+            let mut out = DepthSet::new(self.capacity);
+            let successors = match &tokens[index].token {
+                Token::Call(label) => {
+                    if let Some(target) = self.program.label_index(label) {
+                        for depth in state.iter() {
+                            // unknown summaries start out empty and are filled in by the fixpoint
+                            let summary = self
+                                .summaries
+                                .entry((target, depth))
+                                .or_insert_with(|| DepthSet::new(self.capacity));
+                            out.union(summary);
+                        }
+                    }
+                    vec![index + 1]
+                }
+                // pops the address, then returns like any of the subroutines it can call
+                Token::Execute => {
+                    for depth in state.iter().filter(|&depth| depth > 0) {
+                        for &target in &self.addressed {
+                            let summary = self
+                                .summaries
+                                .entry((target, depth - 1))
+                                .or_insert_with(|| DepthSet::new(self.capacity));
+                            out.union(summary);
+                        }
+                    }
+                    vec![index + 1]
+                }
+                // the task starts with a copy of the stack; its summary is never used, it
+                // only has the subroutine analyzed from there
+                Token::Spawn(label) => {
+                    let target = self.program.label_index(label);
+                    for depth in state.iter() {
+                        if let Some(target) = target {
+                            self.summaries
+                                .entry((target, depth))
+                                .or_insert_with(|| DepthSet::new(self.capacity));
+                        }
+                        if depth < self.capacity {
+                            out.insert(depth + 1);
+                        }
+                    }
+                    vec![index + 1]
+                }
+                // a matching OF pops the selector too, one that doesn't match skips its body
+                Token::Of => {
+                    let mut skipped = DepthSet::new(self.capacity);
+                    for depth in state.iter().filter(|&depth| depth >= 2) {
+                        out.insert(depth - 2);
+                        skipped.insert(depth - 1);
+                    }
+                    merge(&mut states, &mut pending, self.targets[index] + 1, &skipped);
+                    vec![index + 1]
+                }
+                // the handler after the CATCH starts with the depth at the TRY plus the
+                // error's code
+                Token::Try => {
+                    let mut caught = DepthSet::new(self.capacity);
+                    for depth in state.iter() {
+                        if depth < self.capacity {
+                            caught.insert(depth + 1);
+                        }
+                    }
+                    merge(&mut states, &mut pending, self.targets[index] + 1, &caught);
+                    out = state;
+                    vec![index + 1]
+                }
+                Token::Return => {
+                    exits.union(&state);
+                    Vec::new()
+                }
+                token => {
+                    let (pops, pushes) = token.stack_effect().unwrap();
+                    for depth in state.iter() {
+                        if depth >= pops && depth - pops + pushes <= self.capacity {
+                            out.insert(depth - pops + pushes);
+                        }
+                    }
+                    analysis::successors(self.program, &self.targets, index)
+                }
+            };
+            for successor in successors {
+                merge(&mut states, &mut pending, successor, &out);
+            }
+        }
+        Flow { states, exits }
+    }
+
+    fn entry_points(&self, initial_depth: usize) -> Vec<(usize, usize)> {
+        let mut entries = vec![(0, initial_depth)];
+        entries.extend(
+            self.program
+                .labels()
+                .filter(|(label, _)| is_test(label))
+                .map(|(_, index)| (index, 0)),
+        );
+        entries
+    }
+
+    // recomputes subroutine summaries until they no longer change
+    fn solve(&mut self, initial_depth: usize) {
+        loop {
+            let known = self.summaries.len();
+            let mut changed = false;
+            for (entry, depth) in self.entry_points(initial_depth) {
+                self.flow(entry, DepthSet::with(self.capacity, depth));
+            }
+            let keys: Vec<(usize, usize)> = self.summaries.keys().copied().collect();
+            for (entry, depth) in keys {
+                let flow = self.flow(entry, DepthSet::with(self.capacity, depth));
+                let summary = self.summaries.get_mut(&(entry, depth)).unwrap();
+                changed |= summary.union(&flow.exits);
+            }
+            if !changed && self.summaries.len() == known {
+                break;
+            }
+        }
+    }
+}
+
+// tracks the possible stack depths before every token along all control-flow paths,
+// starting with `initial_depth` bytes on the stack
+pub fn analyze(program: &Program, initial_depth: usize) -> StackReport {
+    let mut analyzer = Analyzer {
+        program,
+        targets: bytecode::branch_targets(&program.tokens),
+        addressed: program
+            .tokens
+            .iter()
+            .filter_map(|annotated_token| match &annotated_token.token {
+                Token::PushAddr(label) => program.label_index(label),
+                _ => None,
+            })
+            .collect(),
+        capacity: program.stack_size,
+        summaries: HashMap::new(),
+    };
+    analyzer.solve(initial_depth);
+
+    // merge the states of every context a token is reached in
+    let mut contexts = analyzer.entry_points(initial_depth);
+    contexts.extend(analyzer.summaries.keys().copied());
+    let mut merged: Vec<Option<DepthSet>> = vec![None; program.tokens.len()];
+    for (entry, depth) in contexts {
+        let flow = analyzer.flow(entry, DepthSet::with(analyzer.capacity, depth));
+        for (merged_state, state) in merged.iter_mut().zip(flow.states) {
+            match (merged_state.as_mut(), state) {
+                (Some(merged_state), Some(state)) => {
+                    merged_state.union(&state);
+                }
+                (None, Some(state)) => *merged_state = Some(state),
+                (_, None) => (),
+            }
+        }
+    }
+
+    let mut report = StackReport {
+        issues: Vec::new(),
+        min_depth: initial_depth,
+        max_depth: initial_depth,
+    };
+    for (annotated_token, state) in program.tokens.iter().zip(&merged) {
+        let Some(state) = state else {
+            continue;
+        };
+        let (Some(min_depth), Some(max_depth)) = (state.iter().next(), state.iter().last()) else {
+            continue;
+        };
+        report.min_depth = report.min_depth.min(min_depth);
+        report.max_depth = report.max_depth.max(max_depth);
+        let Some((pops, pushes)) = annotated_token.token.stack_effect() else {
+            continue;
+        };
+        if max_depth < pops {
+            report.issues.push(StackIssue::Underflow {
+                line_number: annotated_token.line_number,
+                column: annotated_token.column,
+                token: annotated_token.token.clone(),
+                max_depth,
+            });
+        } else if min_depth >= pops && min_depth - pops + pushes > analyzer.capacity {
+            report.issues.push(StackIssue::Overflow {
+                line_number: annotated_token.line_number,
+                column: annotated_token.column,
+                token: annotated_token.token.clone(),
+                min_depth,
+            });
+        }
+    }
+    report
+}
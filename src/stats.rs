@@ -0,0 +1,71 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::interpreter::{Program, RuntimeError};
+
+// what --stats counts while running a program
+#[derive(Default)]
+pub struct Stats {
+    // how often every kind of instruction ran, by mnemonic
+    instructions: BTreeMap<&'static str, u64>,
+    steps: u64,
+    peak_stack_depth: usize,
+    peak_call_depth: usize,
+    elapsed: Duration,
+}
+
+impl Stats {
+    // runs the program like Program::run, counting every step
+    pub fn run(&mut self, program: &mut Program) -> Result<(), RuntimeError> {
+        let start = Instant::now();
+        self.peak_stack_depth = program.stack.len();
+        let result = self.run_counting(program);
+        self.elapsed = start.elapsed();
+        result
+    }
+
+    fn run_counting(&mut self, program: &mut Program) -> Result<(), RuntimeError> {
+        while !program.halted {
+            if let Some(op) = program.code.ops.get(program.pc) {
+                *self.instructions.entry(op.name()).or_default() += 1;
+                self.steps += 1;
+            }
+            program.step()?;
+            self.peak_stack_depth = self.peak_stack_depth.max(program.stack.len());
+            self.peak_call_depth = self.peak_call_depth.max(program.call_depth());
+        }
+        Ok(())
+    }
+
+    // the totals followed by the instructions that ran, the most frequent first, e.g.
+    //   steps: 91
+    //   wall time: 24.8µs
+    //   peak stack depth: 5
+    //   peak call depth: 4
+    //
+    //   instruction        count        %
+    //   jump_if_zero          16    17.6%
+    pub fn report(&self) -> String {
+        let mut out = format!(
+            "steps: {}\nwall time: {:.1?}\npeak stack depth: {}\npeak call depth: {}\n\n",
+            self.steps, self.elapsed, self.peak_stack_depth, self.peak_call_depth
+        );
+        let mut instructions: Vec<(&str, u64)> = self
+            .instructions
+            .iter()
+            .map(|(&name, &count)| (name, count))
+            .collect();
+        instructions.sort_by_key(|&(_, count)| Reverse(count));
+        out.push_str("instruction        count        %\n");
+        for (name, count) in instructions {
+            out.push_str(&format!(
+                "{:<12}  {:>10}  {:>6.1}%\n",
+                name,
+                count,
+                count as f64 * 100.0 / self.steps.max(1) as f64
+            ));
+        }
+        out
+    }
+}
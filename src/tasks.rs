@@ -0,0 +1,62 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::exceptions::Handler;
+
+// task ids are bytes on the stack, so there can't be more tasks than that, counting the
+// program itself as task 0
+pub const MAX_TASKS: usize = 256;
+// and the same goes for channels
+pub const MAX_CHANNELS: usize = 256;
+// SEND waits while a channel holds this many values
+pub const CHANNEL_CAPACITY: usize = 256;
+
+// the return address at the bottom of a spawned task's call stack: returning to it ends
+// the task. It's what a snapshot stores it as, so it reads back the same
+pub const TASK_END: usize = u32::MAX as usize;
+
+// why SPAWN, JOIN or a channel word failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskError {
+    TooManyTasks,
+    UnknownTask(u8),
+    TooManyChannels,
+    UnknownChannel(u8),
+    // every task that hasn't finished is waiting in JOIN, SEND or RECV
+    Deadlock,
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskError::TooManyTasks => write!(f, "Too many tasks"),
+            TaskError::UnknownTask(id) => write!(f, "Unknown task {}", id),
+            TaskError::TooManyChannels => write!(f, "Too many channels"),
+            TaskError::UnknownChannel(id) => write!(f, "Unknown channel {}", id),
+            TaskError::Deadlock => write!(f, "Deadlock: every task is waiting"),
+        }
+    }
+}
+
+// a task that isn't running; the running task's machine is the program's. Once a
+// program spawned a task, Program keeps one of these for every task by id, with an empty
+// one for the running task
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Task {
+    pub pc: usize,
+    pub stack: Vec<u8>,
+    pub call_stack: Vec<usize>,
+    pub handlers: Vec<Handler>,
+    // once the task returned, the top of its stack (0 if it was empty) for JOIN
+    pub result: Option<u8>,
+}
+
+impl Task {
+    pub fn finished(&self) -> bool {
+        self.result.is_some()
+    }
+}
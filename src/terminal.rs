@@ -0,0 +1,91 @@
+use alloc::format;
+use alloc::string::String;
+
+// where KEY and KEY? read keys from. A program without a keyboard reads keys from its
+// input instead, as if every byte of it were a key already pressed
+pub trait Keyboard {
+    // waits for the next key; None if there won't be any
+    fn read_key(&mut self) -> Option<u8>;
+    // the next key if one was pressed, without waiting
+    fn poll_key(&mut self) -> Option<u8>;
+}
+
+// the escape sequences of CLEAR_SCREEN and CURSOR_AT, which any ANSI terminal understands
+pub const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+// the column and row count from 0, and the terminal counts from 1
+pub fn cursor_at(column: u8, row: u8) -> String {
+    format!("\x1b[{};{}H", u16::from(row) + 1, u16::from(column) + 1)
+}
+
+// stdin of a terminal, with the keys neither echoed nor held back until the end of the line.
+// Ctrl-C still stops the program, and the terminal is restored when this is dropped.
+// Keys that send escape sequences, like the arrow keys, read as the bytes of the sequence
+#[cfg(all(feature = "terminal", unix))]
+pub struct RawKeyboard {
+    original: libc::termios,
+}
+
+#[cfg(all(feature = "terminal", unix))]
+impl RawKeyboard {
+    // None if stdin isn't a terminal
+    pub fn new() -> Option<Self> {
+        // SAFETY: tcgetattr() only writes to the termios it's given
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return None;
+            }
+            let mut original = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return None;
+            }
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(RawKeyboard { original })
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = 0u8;
+        // SAFETY: read() writes at most one byte into `byte`
+        let count = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+        (count == 1).then_some(byte)
+    }
+}
+
+#[cfg(all(feature = "terminal", unix))]
+impl Keyboard for RawKeyboard {
+    fn read_key(&mut self) -> Option<u8> {
+        self.read_byte()
+    }
+
+    fn poll_key(&mut self) -> Option<u8> {
+        let mut stdin = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: poll() gets one pollfd, and a timeout of 0 doesn't wait
+        let ready = unsafe { libc::poll(&mut stdin, 1, 0) };
+        if ready == 1 {
+            self.read_byte()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(all(feature = "terminal", unix))]
+impl Drop for RawKeyboard {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings tcgetattr() read
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use crate::cli::Config;
+use crate::diagnostics::caller_lines;
+use crate::file_io::{self, SharedBuffer};
+use crate::interpreter::label_name;
+use crate::Outcome;
+use fifth::clock::VirtualClock;
+use fifth::environment::StdEnvironment;
+use fifth::files::StdFileSystem;
+use fifth::net::StdNetwork;
+
+const TEST_PREFIX: &str = "TEST_";
+
+// whether a label is a test, whatever the case of its prefix in a case-sensitive program
+pub fn is_test(label: &str) -> bool {
+    label
+        .get(..TEST_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(TEST_PREFIX))
+}
+
+pub fn run_tests(config: &Config, source: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut program = crate::new_program(config, source);
+    // tests don't wait for SLEEP, and TICKS only counts the time slept
+    program.set_clock(VirtualClock::default());
+    if let Some(seed) = config.seed {
+        program.seed(seed);
+    }
+    // every test reads the input file from its start, so it doesn't depend on the tests
+    // before it
+    let input = match &config.input_file {
+        Some(input_file) => Some(file_io::read_file(input_file)?),
+        None => None,
+    };
+    if config.allow_fs {
+        program.set_file_system(StdFileSystem);
+    }
+    if config.allow_net {
+        program.set_network(StdNetwork);
+    }
+    if config.allow_env {
+        program.set_environment(StdEnvironment);
+    }
+
+    if let Err(diagnostics) = program.parse() {
+        return Ok(Outcome::ParseFailed(diagnostics));
+    }
+    if let Some(outcome) = crate::deny_warnings(config, source, &program) {
+        return Ok(outcome);
+    }
+
+    let mut tests: Vec<(String, usize)> = program
+        .labels()
+        .filter(|(label, _)| is_test(label))
+        .map(|(label, index)| (label.to_string(), index))
+        .collect();
+    tests.sort_by_key(|&(_, index)| index);
+
+    println!("running {} tests", tests.len());
+    let mut failures = Vec::new();
+    for (label, _) in &tests {
+        let name = label_name(label);
+        if let Some(input) = &input {
+            program.set_input(Cursor::new(input.clone()));
+        }
+        program.call(label)?;
+        match program.run() {
+            Ok(_) => println!("test {} ... ok", name),
+            Err(err) => {
+                println!("test {} ... FAILED", name);
+                let mut failure = format!("{}: line {}: {}", name, err.token().line_number, err);
+                for line in caller_lines(&program.backtrace()) {
+                    failure.push_str(&format!("\n        {}", line));
+                }
+                failures.push(failure);
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!();
+        println!("failures:");
+        for failure in &failures {
+            println!("    {}", failure);
+        }
+    }
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failures.len(),
+        failures.len()
+    );
+
+    Ok(Outcome::Tested {
+        failed: failures.len(),
+    })
+}
+
+// `fifth test --golden <directory>`: runs every program in the directory and below it and
+// compares what it prints with the .expected file next to it; with --update, the .expected
+// files are written instead wherever they differ. A program reads the .input file next to
+// it, if there is one, and otherwise no input
+pub fn run_golden(config: &Config) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut programs = Vec::new();
+    find_programs(Path::new(&config.filename), &mut programs)?;
+    programs.sort();
+
+    println!("running {} golden tests", programs.len());
+    let mut failures = Vec::new();
+    let mut updated = 0;
+    for path in &programs {
+        let name = path.display();
+        let expected_path = path.with_extension("expected");
+        let expected = file_io::read_file(&expected_path).ok();
+        let output = match run_program(config, path) {
+            Ok(output) => output,
+            Err(err) => {
+                println!("golden {} ... FAILED", name);
+                failures.push(format!("{}: {}", name, err).replace('\n', "\n        "));
+                continue;
+            }
+        };
+        if expected.as_ref() == Some(&output) {
+            println!("golden {} ... ok", name);
+        } else if config.update {
+            file_io::write_bytes_to_file(&expected_path, &output)?;
+            println!("golden {} ... updated", name);
+            updated += 1;
+        } else {
+            println!("golden {} ... FAILED", name);
+            let failure = match expected {
+                Some(expected) => diff(
+                    &String::from_utf8_lossy(&expected),
+                    &String::from_utf8_lossy(&output),
+                )
+                .iter()
+                .fold(format!("{}: output differs", name), |failure, line| {
+                    failure + "\n        " + line
+                }),
+                None => format!(
+                    "{}: no {} (--update writes it)",
+                    name,
+                    expected_path.display()
+                ),
+            };
+            failures.push(failure);
+        }
+    }
+
+    if !failures.is_empty() {
+        println!();
+        println!("failures:");
+        for failure in &failures {
+            println!("    {}", failure);
+        }
+    }
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed{}",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        programs.len() - failures.len(),
+        failures.len(),
+        if config.update {
+            format!("; {} updated", updated)
+        } else {
+            String::new()
+        }
+    );
+
+    Ok(Outcome::Tested {
+        failed: failures.len(),
+    })
+}
+
+// the .5th (or .fifth) files in `directory` and its subdirectories
+fn find_programs(directory: &Path, programs: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_programs(&path, programs)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "5th" || extension == "fifth")
+        {
+            programs.push(path);
+        }
+    }
+    Ok(())
+}
+
+// what the program prints, or why it didn't run to the end. Its seed is 0 unless --seed is
+// given and it doesn't wait in SLEEP, so it prints the same every run; its stack starts with
+// --stack-init and the arguments, as with `run`
+fn run_program(config: &Config, path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let source = file_io::read_file_to_string(path)?;
+    let mut program = crate::new_program(config, &source);
+    program.seed(config.seed.unwrap_or(0));
+    program.set_clock(VirtualClock::default());
+    let input = file_io::read_file(path.with_extension("input")).unwrap_or_default();
+    program.set_input(Cursor::new(input));
+    let output = SharedBuffer::default();
+    program.set_output(output.clone());
+    if config.allow_fs {
+        program.set_file_system(StdFileSystem);
+    }
+    if config.allow_net {
+        program.set_network(StdNetwork);
+    }
+    if config.allow_env {
+        program.set_environment(StdEnvironment);
+    }
+    program.parse()?;
+    program.stack.extend(&config.stack_init);
+    program.stack.extend(&config.program_args);
+    if let Err(err) = program.run() {
+        return Err(format!("line {}: {}", err.token().line_number, err).into());
+    }
+    Ok(output.0.take())
+}
+
+// the lines only `expected` has with "-" before them and the ones only `actual` has with
+// "+", from a longest common subsequence of the two
+fn diff(expected: &str, actual: &str) -> Vec<String> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    // common[i][j]: the length of the longest common subsequence of expected[i..] and
+    // actual[j..]
+    let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push(format!(" {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == actual.len() || (i < expected.len() && common[i + 1][j] >= common[i][j + 1])
+        {
+            lines.push(format!("-{}", expected[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", actual[j]));
+            j += 1;
+        }
+    }
+    lines
+}
@@ -0,0 +1,76 @@
+use crate::bytecode::Op;
+use crate::interpreter::{label_name, ExecutionObserver, Program, RuntimeError};
+use crate::log::{Log, Verbosity};
+
+// the subroutines being run, outermost first, e.g. `top level > fact > mul`
+fn breadcrumb(program: &Program) -> String {
+    let frames = program.backtrace();
+    let names: Vec<String> = frames
+        .iter()
+        .rev()
+        .map(|frame| match &frame.label {
+            Some(label) => label_name(label),
+            None => "top level".to_string(),
+        })
+        .collect();
+    names.join(" > ")
+}
+
+// the stack top first with the index of every slot (counted from the bottom), marking what
+// the last instruction popped with - and pushed with +, e.g. after `add`:
+//    -  2 | 4
+//    -  1 | 3
+//    +  1 | 7
+//       0 | 5
+fn render_stack(stack: &[u8], previous: &[u8]) -> String {
+    let kept = stack
+        .iter()
+        .zip(previous)
+        .take_while(|(value, old)| value == old)
+        .count();
+    let mut out = String::new();
+    for (index, value) in previous.iter().enumerate().skip(kept).rev() {
+        out.push_str(&format!(" - {:>3} | {}\n", index, value));
+    }
+    for (index, value) in stack.iter().enumerate().rev() {
+        let marker = if index >= kept { '+' } else { ' ' };
+        out.push_str(&format!(" {} {:>3} | {}\n", marker, index, value));
+    }
+    if out.is_empty() {
+        out.push_str("   (empty)\n");
+    }
+    out
+}
+
+// logs the call stack, the stack and the line before every step
+struct Tracer {
+    previous: Vec<u8>,
+    log: Log,
+}
+
+impl ExecutionObserver for Tracer {
+    fn on_step(&mut self, program: &Program, index: usize, _op: Op) {
+        let token = program.code.token(index);
+        self.log.write(
+            Verbosity::Trace,
+            &format!(
+                "Call stack: {}\nStack:\n{}Line {}: {}\n",
+                breadcrumb(program),
+                render_stack(&program.stack, &self.previous),
+                token.line_number,
+                token.token
+            ),
+        );
+        self.previous.clone_from(&program.stack);
+    }
+}
+
+pub fn run(program: &mut Program, log: Log) -> Result<(), RuntimeError> {
+    program.set_observer(Box::new(Tracer {
+        previous: program.stack.clone(),
+        log,
+    }));
+    let result = program.run();
+    program.remove_observer();
+    result
+}
@@ -0,0 +1,930 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::bytecode::{Bytecode, Op, UNCLOSED};
+use crate::files::FileError;
+use crate::terminal;
+
+// Compiles bytecode to a WebAssembly module. The module imports its I/O from the host:
+//   fifth.print_byte(byte), fifth.print_char(byte)  PRINT_BYTE and PRINT_CHAR
+//   fifth.print_utf8(byte)                           PRINT_UTF8, a byte of UTF-8 text
+//   fifth.read_char() -> byte                        READ_CHAR, 0 at the end of the input
+//   fifth.random() -> byte                           RANDOM
+//   fifth.error(code, line, column)                  a runtime error, code is its exit code
+// and exports its memory and `run(depth) -> exit code`, which runs the program on the
+// `depth` bytes the host put at the start of the memory.
+//
+// Every basic block of the program becomes one case of a loop around a br_table on the
+// block number; jumps, calls and returns set the block number and restart the loop.
+
+// the exit codes of runtime errors, the same as the interpreter's
+const STACK_OVERFLOW: i32 = 3;
+const STACK_UNDERFLOW: i32 = 4;
+const INVALID_LABEL: i32 = 5;
+const CALL_STACK_UNDERFLOW: i32 = 6;
+const UNCLOSED_IF: i32 = 7;
+const ASSERTION_FAILED: i32 = 8;
+const TASK_ERROR: i32 = 11;
+const UNCAUGHT_EXCEPTION: i32 = 12;
+const OUT_OF_MEMORY: i32 = 13;
+
+const PAGE_SIZE: usize = 65536;
+
+// function indices: the imports come first
+const PRINT_BYTE: u32 = 0;
+const PRINT_CHAR: u32 = 1;
+const READ_CHAR: u32 = 2;
+const RANDOM: u32 = 3;
+const ERROR: u32 = 4;
+const PRINT_UTF8: u32 = 5;
+
+// locals of `run`; the parameter is the stack depth
+const SP: u32 = 0;
+const BLOCK: u32 = 1;
+// address of the next free slot of the call stack
+const CSP: u32 = 2;
+const A: u32 = 3;
+const B: u32 = 4;
+// address of the first memory cell ALLOT hasn't handed out, counted from the first cell
+const HERE: u32 = 5;
+// scratch locals of READ_LINE and PARSE_NUM
+const C: u32 = 6;
+const D: u32 = 7;
+const E: u32 = 8;
+// 1 if the last READ_CHAR or READ_LINE ran out of input; read_char() returns 0 for that,
+// so a 0 byte ends the input too
+const END_OF_INPUT: u32 = 9;
+
+// instructions
+const UNREACHABLE: u8 = 0x00;
+const BLOCK_START: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const BR_TABLE: u8 = 0x0e;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const I32_LOAD: u8 = 0x28;
+const I32_LOAD8_U: u8 = 0x2d;
+const I32_STORE: u8 = 0x36;
+const I32_STORE8: u8 = 0x3a;
+const MEMORY_SIZE: u8 = 0x3f;
+const MEMORY_GROW: u8 = 0x40;
+const I32_CONST: u8 = 0x41;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_NE: u8 = 0x47;
+const I32_LT_U: u8 = 0x49;
+const I32_GT_U: u8 = 0x4b;
+const I32_LE_U: u8 = 0x4d;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I32_MUL: u8 = 0x6c;
+const I32_AND: u8 = 0x71;
+const I32_OR: u8 = 0x72;
+const I32_SHR_U: u8 = 0x76;
+const BULK_PREFIX: u8 = 0xfc;
+const MEMORY_COPY: u32 = 10;
+const EMPTY_BLOCK_TYPE: u8 = 0x40;
+const I32: u8 = 0x7f;
+
+fn unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn signed(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn name(bytes: &mut Vec<u8>, name: &str) {
+    unsigned(bytes, name.len() as u64);
+    bytes.extend(name.as_bytes());
+}
+
+fn section(module: &mut Vec<u8>, id: u8, contents: Vec<u8>) {
+    module.push(id);
+    unsigned(module, contents.len() as u64);
+    module.extend(contents);
+}
+
+// a vector of items, each already encoded
+fn vector(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    unsigned(&mut bytes, items.len() as u64);
+    for item in items {
+        bytes.extend(item);
+    }
+    bytes
+}
+
+// the body of `run`, keeping track of how deeply blocks are nested
+struct Emitter {
+    code: Vec<u8>,
+    depth: u32,
+    // the depth of the dispatch loop
+    dispatch: u32,
+    stack_size: usize,
+    // the address of the memory cells of FETCH and STORE
+    cells: usize,
+    // the address of the bottom of the call stack
+    call_stack: usize,
+}
+
+impl Emitter {
+    fn op(&mut self, opcode: u8) -> &mut Self {
+        self.code.push(opcode);
+        self
+    }
+
+    fn index(&mut self, opcode: u8, index: u32) -> &mut Self {
+        self.code.push(opcode);
+        unsigned(&mut self.code, index.into());
+        self
+    }
+
+    fn get(&mut self, local: u32) -> &mut Self {
+        self.index(LOCAL_GET, local)
+    }
+
+    fn set(&mut self, local: u32) -> &mut Self {
+        self.index(LOCAL_SET, local)
+    }
+
+    fn constant(&mut self, value: i64) -> &mut Self {
+        self.code.push(I32_CONST);
+        signed(&mut self.code, value);
+        self
+    }
+
+    fn memory(&mut self, opcode: u8, align: u32) -> &mut Self {
+        self.code.push(opcode);
+        unsigned(&mut self.code, align.into());
+        unsigned(&mut self.code, 0);
+        self
+    }
+
+    fn begin(&mut self, opcode: u8) -> &mut Self {
+        self.code.extend([opcode, EMPTY_BLOCK_TYPE]);
+        self.depth += 1;
+        self
+    }
+
+    fn end(&mut self) -> &mut Self {
+        self.code.push(END);
+        self.depth -= 1;
+        self
+    }
+
+    // pushes the address of the byte `offset` places below the top of the stack
+    fn slot(&mut self, offset: i64) -> &mut Self {
+        self.get(SP).constant(offset).op(I32_SUB)
+    }
+
+    fn load(&mut self, offset: i64) -> &mut Self {
+        self.slot(offset).memory(I32_LOAD8_U, 0)
+    }
+
+    // stores the value computed by `value` at the given slot
+    fn store(&mut self, offset: i64, value: impl FnOnce(&mut Self)) -> &mut Self {
+        self.slot(offset);
+        value(self);
+        self.memory(I32_STORE8, 0)
+    }
+
+    fn grow(&mut self, by: i64) -> &mut Self {
+        self.get(SP).constant(by).op(I32_ADD).set(SP)
+    }
+
+    // reports an error at `position` and stops if the condition on the stack holds
+    fn fail_if(&mut self, code: i32, position: (usize, usize)) -> &mut Self {
+        self.begin(IF);
+        self.constant(code.into())
+            .constant(position.0 as i64)
+            .constant(position.1 as i64)
+            .index(CALL, ERROR)
+            .constant(code.into())
+            .op(RETURN);
+        self.end()
+    }
+
+    fn fail(&mut self, code: i32, position: (usize, usize)) -> &mut Self {
+        self.constant(1).fail_if(code, position)
+    }
+
+    fn need(&mut self, count: i64, position: (usize, usize)) -> &mut Self {
+        self.get(SP)
+            .constant(count)
+            .op(I32_LT_U)
+            .fail_if(STACK_UNDERFLOW, position)
+    }
+
+    fn room(&mut self, count: i64, position: (usize, usize)) -> &mut Self {
+        let stack_size = self.stack_size as i64;
+        self.get(SP)
+            .constant(count)
+            .op(I32_ADD)
+            .constant(stack_size)
+            .op(I32_GT_U)
+            .fail_if(STACK_OVERFLOW, position)
+    }
+
+    // pops the top byte into a local
+    fn pop(&mut self, local: u32, position: (usize, usize)) -> &mut Self {
+        self.need(1, position).grow(-1).load(0).set(local)
+    }
+
+    // restarts the dispatch loop
+    fn dispatch(&mut self) -> &mut Self {
+        self.index(BR, self.depth - self.dispatch)
+    }
+
+    fn goto(&mut self, block: usize) -> &mut Self {
+        self.constant(block as i64).set(BLOCK).dispatch()
+    }
+
+    // CALL and EXECUTE: pushes `return_block` on the call stack (unless it's a tail call
+    // with a caller, which reuses its frame) and runs `jump` to go to the subroutine
+    fn call(&mut self, return_block: usize, tail_call: bool, jump: impl Fn(&mut Self)) {
+        let call_stack = self.call_stack as i64;
+        if tail_call {
+            self.get(CSP).constant(call_stack).op(I32_NE).begin(IF);
+            jump(self);
+            self.end();
+        }
+        // the call stack grows a page at a time
+        self.get(CSP)
+            .constant(4)
+            .op(I32_ADD)
+            .code
+            .extend([MEMORY_SIZE, 0]);
+        self.constant(PAGE_SIZE as i64)
+            .op(I32_MUL)
+            .op(I32_GT_U)
+            .begin(IF);
+        self.constant(1).code.extend([MEMORY_GROW, 0]);
+        self.constant(-1).op(I32_EQ).begin(IF).op(UNREACHABLE).end();
+        self.end();
+        self.get(CSP)
+            .constant(return_block as i64)
+            .memory(I32_STORE, 2);
+        self.get(CSP).constant(4).op(I32_ADD).set(CSP);
+        jump(self);
+    }
+
+    // PICK: copies the byte `A` places below the top
+    fn pick(&mut self, position: (usize, usize)) -> &mut Self {
+        self.get(SP)
+            .get(A)
+            .op(I32_LE_U)
+            .fail_if(STACK_UNDERFLOW, position)
+            .room(1, position);
+        self.store(0, |e| {
+            e.get(SP)
+                .constant(1)
+                .op(I32_SUB)
+                .get(A)
+                .op(I32_SUB)
+                .memory(I32_LOAD8_U, 0);
+        })
+        .grow(1)
+    }
+
+    // ROLL: moves the byte `A` places down to the top
+    fn roll(&mut self, position: (usize, usize)) -> &mut Self {
+        self.get(SP)
+            .get(A)
+            .op(I32_LT_U)
+            .fail_if(STACK_UNDERFLOW, position);
+        self.get(A).begin(IF);
+        self.get(SP).get(A).op(I32_SUB).set(B);
+        self.get(B).memory(I32_LOAD8_U, 0);
+        // the moved byte stays on the wasm stack while the bytes above it shift down
+        self.get(B)
+            .get(B)
+            .constant(1)
+            .op(I32_ADD)
+            .get(A)
+            .constant(1)
+            .op(I32_SUB);
+        self.code.push(BULK_PREFIX);
+        unsigned(&mut self.code, MEMORY_COPY.into());
+        self.code.extend([0, 0]);
+        self.set(B).store(1, |e| {
+            e.get(B);
+        });
+        self.end()
+    }
+}
+
+// like the interpreter, a call with only THENs between it and a RETURN is a tail call,
+// which reuses its caller's frame if there is one
+fn tail_call(code: &Bytecode, index: usize) -> bool {
+    code.ops[index + 1..].iter().find(|&&op| op != Op::Nop) == Some(&Op::Return)
+}
+
+// the op indices at which basic blocks start; the end of the code counts as one
+fn leaders(code: &Bytecode) -> Vec<bool> {
+    let mut leaders = vec![false; code.ops.len() + 1];
+    leaders[0] = true;
+    leaders[code.ops.len()] = true;
+    for &target in &code.addresses {
+        if let Some(leader) = leaders.get_mut(target) {
+            *leader = true;
+        }
+    }
+    for (index, &op) in code.ops.iter().enumerate() {
+        match op {
+            Op::JumpIfZero(target)
+            | Op::Jump(target)
+            | Op::Of(target)
+            | Op::Catch(target)
+            | Op::Call(target) => {
+                if let Some(leader) = leaders.get_mut(target as usize) {
+                    *leader = true;
+                }
+            }
+            _ => (),
+        }
+        if matches!(
+            op,
+            Op::Jump(_)
+                | Op::Catch(_)
+                | Op::Call(_)
+                | Op::Execute
+                | Op::Return
+                | Op::Halt
+                | Op::Exit
+        ) {
+            leaders[index + 1] = true;
+        }
+    }
+    leaders
+}
+
+fn run_body(code: &Bytecode, stack_size: usize, cells: usize, call_stack: usize) -> Vec<u8> {
+    let leaders = leaders(code);
+    // the block every op index belongs to
+    let mut blocks = Vec::with_capacity(leaders.len());
+    let mut count = 0;
+    for &leader in &leaders {
+        if leader {
+            count += 1;
+        }
+        blocks.push(count - 1);
+    }
+
+    let mut e = Emitter {
+        code: Vec::new(),
+        depth: 0,
+        dispatch: 0,
+        stack_size,
+        cells,
+        call_stack,
+    };
+    // locals: block, csp, a, b, here, c, d, e, end of input
+    e.code.extend([1, 9, I32]);
+    e.constant(call_stack as i64).set(CSP);
+    e.constant(code.data.len() as i64).set(HERE);
+    e.begin(LOOP);
+    e.dispatch = e.depth;
+    for _ in 0..count {
+        e.begin(BLOCK_START);
+    }
+    e.get(BLOCK).code.push(BR_TABLE);
+    unsigned(&mut e.code, count as u64);
+    for block in 0..count {
+        unsigned(&mut e.code, block as u64);
+    }
+    unsigned(&mut e.code, count as u64 - 1);
+    e.end();
+
+    for (index, &op) in code.ops.iter().enumerate() {
+        if leaders[index] && index > 0 {
+            e.end();
+        }
+        let at = code.positions[index];
+        match op {
+            Op::Push(value) => {
+                e.room(1, at).store(0, |e| {
+                    e.constant(value.into());
+                });
+                e.grow(1);
+            }
+            Op::Pop => {
+                e.need(1, at).grow(-1);
+            }
+            Op::Dup => {
+                e.need(1, at).room(1, at).store(0, |e| {
+                    e.load(1);
+                });
+                e.grow(1);
+            }
+            Op::Swap => {
+                e.need(2, at).load(1).set(A);
+                e.store(1, |e| {
+                    e.load(2);
+                })
+                .store(2, |e| {
+                    e.get(A);
+                });
+            }
+            Op::Rotate => {
+                e.need(3, at).load(3).set(A);
+                e.store(3, |e| {
+                    e.load(2);
+                })
+                .store(2, |e| {
+                    e.load(1);
+                })
+                .store(1, |e| {
+                    e.get(A);
+                });
+            }
+            Op::Over => {
+                e.need(2, at).room(1, at).store(0, |e| {
+                    e.load(2);
+                });
+                e.grow(1);
+            }
+            Op::Nip => {
+                e.need(2, at).store(2, |e| {
+                    e.load(1);
+                });
+                e.grow(-1);
+            }
+            Op::Tuck => {
+                e.need(2, at).room(1, at).load(1).set(A);
+                e.store(1, |e| {
+                    e.load(2);
+                })
+                .store(2, |e| {
+                    e.get(A);
+                })
+                .store(0, |e| {
+                    e.get(A);
+                });
+                e.grow(1);
+            }
+            Op::PickN(index) => {
+                e.constant(index as i32 as i64).set(A).pick(at);
+            }
+            Op::Pick => {
+                e.pop(A, at).pick(at);
+            }
+            Op::RollN(count) => {
+                e.constant(count as i32 as i64).set(A).roll(at);
+            }
+            Op::Roll => {
+                e.pop(A, at).roll(at);
+            }
+            Op::Add | Op::Sub => {
+                e.need(2, at).store(2, |e| {
+                    e.load(2).load(1).op(match op {
+                        Op::Add => I32_ADD,
+                        _ => I32_SUB,
+                    });
+                });
+                e.grow(-1);
+            }
+            Op::Random => {
+                e.room(1, at).store(0, |e| {
+                    e.index(CALL, RANDOM);
+                });
+                e.grow(1);
+            }
+            Op::Assert => {
+                e.pop(A, at)
+                    .get(A)
+                    .op(I32_EQZ)
+                    .fail_if(ASSERTION_FAILED, at);
+            }
+            Op::AssertEq => {
+                e.need(2, at).grow(-2).load(0).load(-1).op(I32_NE);
+                e.fail_if(ASSERTION_FAILED, at);
+            }
+            Op::PrintHex => {
+                e.pop(A, at);
+                // every digit d is printed as the character '0' + d, or 'a' + d - 10
+                for shift in [4, 0] {
+                    e.get(A)
+                        .constant(shift)
+                        .op(I32_SHR_U)
+                        .constant(0xf)
+                        .op(I32_AND);
+                    e.set(B).get(B).constant(48).op(I32_ADD);
+                    e.get(B).constant(9).op(I32_GT_U).constant(39).op(I32_MUL);
+                    e.op(I32_ADD).index(CALL, PRINT_CHAR);
+                }
+            }
+            // a module has no error output
+            Op::PrintStack => (),
+            // nor a clock, so SLEEP doesn't wait
+            Op::EprintByte | Op::EprintChar | Op::Sleep => {
+                e.need(1, at).grow(-1);
+            }
+            Op::PrintNewline => {
+                e.constant(10).index(CALL, PRINT_CHAR);
+            }
+            Op::PrintSpace => {
+                e.constant(32).index(CALL, PRINT_CHAR);
+            }
+            Op::PrintByte | Op::PrintChar | Op::PrintUtf8 => {
+                let function = match op {
+                    Op::PrintByte => PRINT_BYTE,
+                    Op::PrintChar => PRINT_CHAR,
+                    _ => PRINT_UTF8,
+                };
+                e.pop(A, at).get(A).index(CALL, function);
+            }
+            // keys are read like the input, which a module can't wait for
+            Op::Key | Op::KeyPoll => {
+                e.room(1, at).index(CALL, READ_CHAR).set(A);
+                e.get(A).op(I32_EQZ).set(END_OF_INPUT);
+                e.store(0, |e| {
+                    e.get(A);
+                })
+                .grow(1);
+                if op == Op::KeyPoll {
+                    e.room(1, at)
+                        .store(0, |e| {
+                            e.get(A).op(I32_EQZ).op(I32_EQZ);
+                        })
+                        .grow(1);
+                }
+            }
+            Op::ClearScreen => {
+                for byte in terminal::CLEAR_SCREEN.bytes() {
+                    e.constant(byte.into()).index(CALL, PRINT_CHAR);
+                }
+            }
+            // the escape sequence of terminal::cursor_at()
+            Op::CursorAt => {
+                e.need(2, at).load(1).set(A).load(2).set(B).grow(-2);
+                e.constant(0x1b).index(CALL, PRINT_CHAR);
+                e.constant(b'['.into()).index(CALL, PRINT_CHAR);
+                e.get(A).constant(1).op(I32_ADD).index(CALL, PRINT_BYTE);
+                e.constant(b';'.into()).index(CALL, PRINT_CHAR);
+                e.get(B).constant(1).op(I32_ADD).index(CALL, PRINT_BYTE);
+                e.constant(b'H'.into()).index(CALL, PRINT_CHAR);
+            }
+            Op::ReadChar => {
+                e.room(1, at).index(CALL, READ_CHAR).set(A);
+                e.get(A).op(I32_EQZ).set(END_OF_INPUT);
+                e.store(0, |e| {
+                    e.get(A);
+                })
+                .grow(1);
+            }
+            Op::ReadLine => {
+                let cells = e.cells as i64;
+                // A is the maximum length, B the address and C the length so far
+                e.need(2, at).load(1).set(A).load(2).set(B).grow(-2);
+                // D stays 1 if the line reaches the maximum length before a read
+                e.constant(0).set(C).constant(1).set(D);
+                e.begin(BLOCK_START).begin(LOOP);
+                e.get(A).get(C).op(I32_LE_U).index(BR_IF, 1);
+                e.index(CALL, READ_CHAR).set(D);
+                e.get(D)
+                    .constant(10)
+                    .op(I32_EQ)
+                    .get(D)
+                    .op(I32_EQZ)
+                    .op(I32_OR);
+                e.index(BR_IF, 1);
+                e.get(B).get(C).op(I32_ADD).constant(0xff).op(I32_AND);
+                e.constant(cells).op(I32_ADD).get(D).memory(I32_STORE8, 0);
+                e.get(C).constant(1).op(I32_ADD).set(C).index(BR, 0);
+                e.end().end();
+                e.get(D).op(I32_EQZ).set(END_OF_INPUT);
+                e.store(0, |e| {
+                    e.get(C);
+                })
+                .grow(1);
+            }
+            Op::ParseNum => {
+                let cells = e.cells as i64;
+                // A is the length, B the address, C the digits read so far, D their value
+                // and E the next digit; the loop stops early if there's no number
+                e.need(2, at).load(1).set(A).load(2).set(B);
+                e.constant(0).set(C).constant(0).set(D);
+                e.begin(BLOCK_START).begin(LOOP);
+                e.get(A).get(C).op(I32_LE_U).index(BR_IF, 1);
+                e.get(B).get(C).op(I32_ADD).constant(0xff).op(I32_AND);
+                e.constant(cells).op(I32_ADD).memory(I32_LOAD8_U, 0);
+                e.constant(48).op(I32_SUB).set(E);
+                e.get(E).constant(9).op(I32_GT_U).index(BR_IF, 1);
+                e.get(D).constant(10).op(I32_MUL).get(E).op(I32_ADD).set(D);
+                e.get(D).constant(255).op(I32_GT_U).index(BR_IF, 1);
+                e.get(C).constant(1).op(I32_ADD).set(C).index(BR, 0);
+                e.end().end();
+                e.get(C)
+                    .get(A)
+                    .op(I32_EQ)
+                    .get(A)
+                    .op(I32_EQZ)
+                    .op(I32_EQZ)
+                    .op(I32_AND);
+                e.set(E)
+                    .store(2, |e| {
+                        e.get(D).get(E).op(I32_MUL);
+                    })
+                    .store(1, |e| {
+                        e.get(E);
+                    });
+            }
+            // without a clock, no time passes
+            Op::Ticks => {
+                e.room(2, at)
+                    .store(0, |e| {
+                        e.constant(0);
+                    })
+                    .grow(1)
+                    .store(0, |e| {
+                        e.constant(0);
+                    })
+                    .grow(1);
+            }
+            // a module has no files, sockets or environment: FOPEN, TCP_LISTEN, TCP_CONNECT
+            // and ENV fail as if they were disabled, and every handle is a bad one. All
+            // kinds of errors have the same codes for those
+            Op::Fopen | Op::TcpListen | Op::TcpConnect | Op::Env => {
+                let popped = match op {
+                    Op::Fopen => 3,
+                    Op::TcpListen => 2,
+                    _ => 4,
+                };
+                e.need(popped, at)
+                    .store(popped, |e| {
+                        e.constant(0);
+                    })
+                    .store(popped - 1, |e| {
+                        e.constant(FileError::Disabled.code().into());
+                    })
+                    .grow(2 - popped);
+            }
+            Op::Fread | Op::TcpAccept | Op::TcpRecv => {
+                e.need(1, at)
+                    .room(1, at)
+                    .store(1, |e| {
+                        e.constant(0);
+                    })
+                    .store(0, |e| {
+                        e.constant(FileError::BadHandle.code().into());
+                    })
+                    .grow(1);
+            }
+            Op::Fwrite | Op::Fclose | Op::TcpSend | Op::TcpClose => {
+                if matches!(op, Op::Fwrite | Op::TcpSend) {
+                    e.need(2, at).grow(-1);
+                } else {
+                    e.need(1, at);
+                }
+                e.store(1, |e| {
+                    e.constant(FileError::BadHandle.code().into());
+                });
+            }
+            // there's no screen to show frames on
+            Op::GfxInit | Op::Pixel => {
+                let popped = if op == Op::Pixel { 3 } else { 2 };
+                e.need(popped, at).grow(-popped);
+            }
+            Op::GfxFlush => (),
+            Op::Eof => {
+                e.room(1, at).store(0, |e| {
+                    e.get(END_OF_INPUT);
+                });
+                e.grow(1);
+            }
+            Op::Fetch => {
+                let cells = e.cells as i64;
+                e.need(1, at).store(1, |e| {
+                    e.load(1).constant(cells).op(I32_ADD).memory(I32_LOAD8_U, 0);
+                });
+            }
+            Op::Store => {
+                let cells = e.cells as i64;
+                e.need(2, at).load(1).constant(cells).op(I32_ADD);
+                e.load(2).memory(I32_STORE8, 0).grow(-2);
+            }
+            Op::Allot => {
+                let size = crate::interpreter::MEMORY_SIZE as i64;
+                e.need(1, at).load(1).get(HERE).op(I32_ADD).set(B);
+                e.get(B).constant(size).op(I32_GT_U);
+                e.get(HERE).constant(size).op(I32_EQ).op(I32_OR);
+                e.fail_if(OUT_OF_MEMORY, at).store(1, |e| {
+                    e.get(HERE);
+                });
+                e.get(B).set(HERE);
+            }
+            Op::FetchAt => {
+                let cells = e.cells as i64;
+                e.need(2, at).store(2, |e| {
+                    e.load(2).load(1).op(I32_ADD).constant(0xff).op(I32_AND);
+                    e.constant(cells).op(I32_ADD).memory(I32_LOAD8_U, 0);
+                });
+                e.grow(-1);
+            }
+            Op::StoreAt => {
+                let cells = e.cells as i64;
+                e.need(3, at)
+                    .load(2)
+                    .load(1)
+                    .op(I32_ADD)
+                    .constant(0xff)
+                    .op(I32_AND);
+                e.constant(cells).op(I32_ADD);
+                e.load(3).memory(I32_STORE8, 0).grow(-3);
+            }
+            Op::JumpIfZero(target) => {
+                e.need(1, at).load(1).op(I32_EQZ).begin(IF);
+                match target {
+                    UNCLOSED => e.fail(UNCLOSED_IF, at),
+                    target => e.goto(blocks[target as usize]),
+                };
+                e.end();
+            }
+            Op::Of(target) => {
+                // pops the value, and the selector below it only if they're equal
+                e.need(2, at).grow(-1).load(0).load(1).op(I32_EQ).begin(IF);
+                e.grow(-1).code.push(ELSE);
+                match target {
+                    UNCLOSED => e.fail(UNCLOSED_IF, at),
+                    target => e.goto(blocks[target as usize]),
+                };
+                e.end();
+            }
+            Op::Jump(UNCLOSED) | Op::Try(UNCLOSED) | Op::Catch(UNCLOSED) => {
+                e.fail(UNCLOSED_IF, at);
+            }
+            Op::Jump(target) => {
+                e.goto(blocks[target as usize]);
+            }
+            Op::Nop => (),
+            // a module can't catch errors, so a TRY block always runs to its CATCH
+            Op::Try(_) => (),
+            Op::Catch(target) => {
+                e.goto(blocks[target as usize]);
+            }
+            Op::Throw => {
+                e.pop(A, at).fail(UNCAUGHT_EXCEPTION, at);
+            }
+            // host words only exist in the interpreter
+            Op::Call(UNCLOSED) | Op::Native(_) => {
+                e.fail(INVALID_LABEL, at);
+            }
+            // and so do tasks
+            Op::Spawn(_) | Op::Yield | Op::Join | Op::ChanNew | Op::Send | Op::Recv => {
+                e.fail(TASK_ERROR, at);
+            }
+            Op::Call(target) => {
+                e.call(blocks[index + 1], tail_call(code, index), |e| {
+                    e.goto(blocks[target as usize]);
+                });
+            }
+            Op::Address(address) => {
+                e.room(1, at).store(0, |e| {
+                    e.constant(address.into());
+                });
+                e.grow(1);
+            }
+            Op::Execute => {
+                // the block of the subroutine at the popped address, -1 for no subroutine
+                e.pop(A, at).constant(-1).set(B);
+                for (address, &target) in code.addresses.iter().enumerate() {
+                    e.get(A).constant(address as i64).op(I32_EQ).begin(IF);
+                    e.constant(blocks[target] as i64).set(B).end();
+                }
+                e.get(B).constant(-1).op(I32_EQ).fail_if(INVALID_LABEL, at);
+                e.call(blocks[index + 1], tail_call(code, index), |e| {
+                    e.get(B).set(BLOCK).dispatch();
+                });
+            }
+            Op::Return => {
+                e.get(CSP)
+                    .constant(call_stack as i64)
+                    .op(I32_LE_U)
+                    .fail_if(CALL_STACK_UNDERFLOW, at);
+                e.get(CSP).constant(4).op(I32_SUB).set(CSP);
+                e.get(CSP).memory(I32_LOAD, 2).set(BLOCK).dispatch();
+            }
+            Op::Halt => {
+                e.constant(0).op(RETURN);
+            }
+            Op::Exit => {
+                e.pop(A, at).get(A).op(RETURN);
+            }
+        }
+    }
+    // the end of the code halts the program
+    if !code.ops.is_empty() {
+        e.end();
+    }
+    e.constant(0).op(RETURN);
+    e.end();
+    e.op(UNREACHABLE);
+    e.code.push(END);
+    e.code
+}
+
+pub fn compile(code: &Bytecode, stack_size: usize) -> Vec<u8> {
+    // the data stack comes first, then the memory cells, then the call stack
+    let cells = stack_size;
+    let call_stack = (cells + crate::interpreter::MEMORY_SIZE).next_multiple_of(4);
+    let pages = (call_stack + PAGE_SIZE).div_ceil(PAGE_SIZE);
+
+    let mut module = b"\0asm".to_vec();
+    module.extend(1u32.to_le_bytes());
+
+    let function_type = |params: &[u8], results: &[u8]| {
+        let mut bytes = vec![0x60];
+        unsigned(&mut bytes, params.len() as u64);
+        bytes.extend(params);
+        unsigned(&mut bytes, results.len() as u64);
+        bytes.extend(results);
+        bytes
+    };
+    // 0: byte -> (), 1: () -> byte, 2: error, 3: run
+    let types = vec![
+        function_type(&[I32], &[]),
+        function_type(&[], &[I32]),
+        function_type(&[I32, I32, I32], &[]),
+        function_type(&[I32], &[I32]),
+    ];
+    section(&mut module, 1, vector(types));
+
+    let imports = [
+        ("print_byte", 0),
+        ("print_char", 0),
+        ("read_char", 1),
+        ("random", 1),
+        ("error", 2),
+        ("print_utf8", 0),
+    ];
+    let imports = imports
+        .iter()
+        .map(|&(function, type_index)| {
+            let mut bytes = Vec::new();
+            name(&mut bytes, "fifth");
+            name(&mut bytes, function);
+            bytes.push(0x00);
+            unsigned(&mut bytes, type_index);
+            bytes
+        })
+        .collect();
+    section(&mut module, 2, vector(imports));
+
+    section(&mut module, 3, vector(vec![vec![3]]));
+
+    let mut memory = vec![0x00];
+    unsigned(&mut memory, pages as u64);
+    section(&mut module, 5, vector(vec![memory]));
+
+    let export = |export_name: &str, kind: u8, index: u64| {
+        let mut bytes = Vec::new();
+        name(&mut bytes, export_name);
+        bytes.push(kind);
+        unsigned(&mut bytes, index);
+        bytes
+    };
+    let exports = vec![export("run", 0x00, 6), export("memory", 0x02, 0)];
+    section(&mut module, 7, vector(exports));
+
+    let body = run_body(code, stack_size, cells, call_stack);
+    let mut function = Vec::new();
+    unsigned(&mut function, body.len() as u64);
+    function.extend(body);
+    section(&mut module, 10, vector(vec![function]));
+
+    // the DATA blocks, put in the memory cells when the module is instantiated
+    if !code.data.is_empty() {
+        let mut segment = vec![0x00, I32_CONST];
+        signed(&mut segment, cells as i64);
+        segment.push(END);
+        unsigned(&mut segment, code.data.len() as u64);
+        segment.extend(&code.data);
+        section(&mut module, 11, vector(vec![segment]));
+    }
+    module
+}
@@ -0,0 +1,108 @@
+use std::io::{self, Write};
+
+use wasm_bindgen::prelude::*;
+
+use crate::clock::VirtualClock;
+use crate::interpreter::Program;
+
+#[wasm_bindgen]
+extern "C" {
+    // any JavaScript function taking the printed text
+    pub type OutputCallback;
+
+    #[wasm_bindgen(method, js_name = call)]
+    fn call(this: &OutputCallback, this_arg: &JsValue, text: &str);
+}
+
+// hands everything the program prints to the page's callback
+struct CallbackWriter(OutputCallback);
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0
+            .call(&JsValue::NULL, &String::from_utf8_lossy(buffer));
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// a program a web page can run and inspect one step at a time
+#[wasm_bindgen]
+pub struct Playground {
+    program: Program,
+}
+
+#[wasm_bindgen]
+impl Playground {
+    // fails with the parse errors, one per line
+    pub fn parse(source: &str, stack_size: usize) -> Result<Playground, String> {
+        let mut program = Program::new(source, stack_size);
+        // there's no clock to seed RANDOM from or to sleep with, and no stdin or stdout
+        program.seed(0);
+        program.set_clock(VirtualClock::default());
+        program.set_input(Box::new(io::empty()));
+        program.set_output(Box::new(io::sink()));
+        program.set_error_output(Box::new(io::sink()));
+        program
+            .parse()
+            .map_err(|diagnostics| diagnostics.to_string())?;
+        Ok(Playground { program })
+    }
+
+    pub fn seed(&mut self, seed: u32) {
+        self.program.seed(seed.into());
+    }
+
+    pub fn set_input(&mut self, input: &[u8]) {
+        self.program
+            .set_input(Box::new(io::Cursor::new(input.to_vec())));
+    }
+
+    pub fn set_output(&mut self, callback: OutputCallback) {
+        self.program.set_output(Box::new(CallbackWriter(callback)));
+    }
+
+    // runs one instruction; fails with the runtime error
+    pub fn step(&mut self) -> Result<(), String> {
+        self.program.step().map_err(|err| err.to_string())
+    }
+
+    // runs at most `budget` instructions, so a page stays responsive
+    pub fn run(&mut self, budget: u32) -> Result<(), String> {
+        for _ in 0..budget {
+            if self.program.halted {
+                break;
+            }
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn halted(&self) -> bool {
+        self.program.halted
+    }
+
+    pub fn exit_code(&self) -> Option<u8> {
+        self.program.exit_code
+    }
+
+    // the data stack, bottom first
+    pub fn stack_snapshot(&self) -> Vec<u8> {
+        self.program.stack.clone()
+    }
+
+    // the line of the instruction that runs next, 0 once the program halted
+    pub fn line(&self) -> usize {
+        match self.program.current_token() {
+            Some(current_token) if !self.program.halted => current_token.line_number,
+            _ => 0,
+        }
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.program.call_depth()
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+
+use crate::interpreter::{label_name, Program, Token};
+use crate::test_runner::is_test;
+
+// every label with the line it's defined on and the lines that call, spawn or push its
+// address, then the calls to labels that don't exist and the labels nothing calls, e.g.
+//   label    defined  called from
+//   fib            8  2, 15, 17
+//   worker        20  4 (spawn)
+//
+//   undefined  called from
+//   frob       12
+//
+//   never called
+//   helper (line 30)
+pub fn report(program: &Program) -> String {
+    let mut sites: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for annotated_token in &program.tokens {
+        let (label, kind) = match &annotated_token.token {
+            Token::Call(label) => (label, ""),
+            Token::Spawn(label) => (label, " (spawn)"),
+            Token::PushAddr(label) => (label, " (push_addr)"),
+            _ => continue,
+        };
+        sites
+            .entry(label)
+            .or_default()
+            .push(format!("{}{}", annotated_token.line_number, kind));
+    }
+
+    let labels: Vec<(&str, usize)> = program
+        .labels()
+        .map(|(label, _)| (label, program.label_line(label).unwrap_or(0)))
+        .collect();
+    let width = labels
+        .iter()
+        .map(|(label, _)| label.len())
+        .max()
+        .unwrap_or(0)
+        .max("label".len());
+    let mut out = format!("{:<width$}  defined  called from\n", "label");
+    let mut never_called = String::new();
+    for &(label, line_number) in &labels {
+        let called = match sites.remove(label) {
+            Some(sites) => sites.join(", "),
+            None => {
+                // tests are called by the test command
+                if !is_test(label) {
+                    never_called.push_str(&format!(
+                        "{} (line {})\n",
+                        label_name(label),
+                        line_number
+                    ));
+                }
+                "-".to_string()
+            }
+        };
+        out.push_str(&format!(
+            "{:<width$}  {:>7}  {}\n",
+            label_name(label),
+            line_number,
+            called
+        ));
+    }
+
+    if !sites.is_empty() {
+        let width = sites
+            .keys()
+            .map(|label| label.len())
+            .max()
+            .unwrap_or(0)
+            .max("undefined".len());
+        out.push_str(&format!("\n{:<width$}  called from\n", "undefined"));
+        for (label, sites) in &sites {
+            out.push_str(&format!(
+                "{:<width$}  {}\n",
+                label_name(label),
+                sites.join(", ")
+            ));
+        }
+    }
+
+    if !never_called.is_empty() {
+        out.push_str("\nnever called\n");
+        out.push_str(&never_called);
+    }
+    out
+}